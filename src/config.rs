@@ -1,76 +1,313 @@
 // (C) Copyright 2020, by Germans Media Technology & Services
 // Commedia
-// YAML Config file loader
+// Config file loader (YAML, TOML or JSON)
 
-use std::fs::File;
-use std::io::{BufRead,BufReader,Lines};
+use std::fmt;
+use std::collections::BTreeMap;
+
+use rand::Rng;
+
+use serde::Deserialize;
+use serde::de::DeserializeSeed;
 
 use crate::*;
 
 #[derive(Debug)]
-struct Line {
-    indent: usize,
-    dashed: bool,
-    key: String,
-    value: String,
+pub struct ConfigError {
+    pub file: String,  // the config file, an included file, or --set
+    pub line: usize,  // 1-based, 0 when the error is not about a particular line
+    pub column: usize,  // 1-based
+    pub key: String,  // dotted path, like my_session.head.pos.x
+    pub message: String,
 }
 
-#[derive(Debug)]
-struct Parser<'a> {
-    lines: Lines<BufReader<&'a File>>,
-    pub linenr: usize,
-    pub line: Option<Line>,
-}
-
-impl<'a> Parser<'a> {
-    pub fn accept(&mut self) {
-        loop {
-            let mut line = match self.lines.next() {
-                None => {
-                    self.line = None;
-                    return;  // end-of-file
-                },
-                Some(line) => match line {
-                    Err(_) => {
-                        self.line = None;
-                        return;  // read error
-                    },
-                    Ok(line) => line,
-                },
-            };
-            self.linenr += 1;
-            let mut indent = 0;
-            let mut chars = line.chars();
-            while let Some(c) = chars.next() {
-                if c == ' ' {
-                    indent += 1;
-                }
-                else {
-                    break;
-                }
-            }
-            line = line.split_off(indent);
-            if !line.is_empty() && !line.starts_with('#') {
-                let dashed = if line.starts_with('-') {
-                    line = line.split_off(1).trim().to_string();
-                    true
-                }
-                else {
-                    false
-                };
-                let result: Vec<&str> = line.split(':').collect();
-                let key = result[0].to_string();
-                let value = if result.len() > 1 {
-                    result[1..].join(" ").trim().to_string()
-                }
-                else {
-                    "".to_string()
-                };
-                self.line = Some(Line { indent: indent,dashed: dashed,key: key,value: value, });
-                return;
-            }
+impl ConfigError {
+    pub fn new(file: &str,line: usize,column: usize,key: &str,message: &str) -> ConfigError {
+        ConfigError {
+            file: file.to_string(),
+            line: line,
+            column: column,
+            key: key.to_string(),
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self,f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,"{}: ",self.file)?;
+        if self.line != 0 {
+            write!(f,"line {}, column {}: ",self.line,self.column)?;
+        }
+        if !self.key.is_empty() {
+            write!(f,"{}: ",self.key)?;
+        }
+        write!(f,"{}",self.message)
+    }
+}
+
+// schema of the config file, as written by the user; everything is optional and falls back to the defaults in load_config
+
+#[derive(Debug,Clone,Deserialize)]
+#[serde(untagged)]
+pub enum DistributionSpec {
+    Constant(f32),  // 0.5
+    Text(String),  // normal 0,1
+}
+
+#[derive(Debug,Clone,Deserialize)]
+#[serde(untagged)]
+pub enum SizeSpec {
+    Text(String),  // 256,192
+    List(Vec<usize>),  // [256,192]
+}
+
+#[derive(Debug,Clone,Default,Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct XYZSpec {
+    pub x: Option<DistributionSpec>,
+    pub y: Option<DistributionSpec>,
+    pub z: Option<DistributionSpec>,
+    pub mvnormal: Option<String>,
+    pub palette: Option<String>,
+}
+
+#[derive(Debug,Clone,Default,Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct YPBSpec {
+    pub y: Option<DistributionSpec>,
+    pub p: Option<DistributionSpec>,
+    pub b: Option<DistributionSpec>,
+    pub mvnormal: Option<String>,
+    pub palette: Option<String>,
+}
+
+#[derive(Debug,Clone,Default,Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RGBSpec {
+    pub r: Option<DistributionSpec>,
+    pub g: Option<DistributionSpec>,
+    pub b: Option<DistributionSpec>,
+    pub mvnormal: Option<String>,
+    pub palette: Option<String>,
+}
+
+#[derive(Debug,Clone,Default,Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HeadSpec {
+    pub pos: Option<XYZSpec>,
+    pub dir: Option<YPBSpec>,
+    pub vel: Option<XYZSpec>,
+    pub angvel: Option<YPBSpec>,
+}
+
+#[derive(Debug,Clone,Default,Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EyeSpec {
+    pub y: Option<DistributionSpec>,
+    pub p: Option<DistributionSpec>,
+    pub b: Option<DistributionSpec>,
+    pub mvnormal: Option<String>,
+    pub palette: Option<String>,
+    pub angvel: Option<YPBSpec>,
+}
+
+#[derive(Debug,Clone,Default,Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LightSpec {
+    pub dir: Option<YPBSpec>,
+    pub color: Option<RGBSpec>,
+    pub angvel: Option<YPBSpec>,
+}
+
+#[derive(Debug,Clone,Deserialize)]
+#[serde(untagged)]
+pub enum BackgroundSpec {
+    Text(String),  // black, or image followed by a directory
+    Color {
+        color: RGBSpec,
+    },
+}
+
+#[derive(Debug,Clone,Default,Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SessionSpec {
+    pub extends: Option<String>,  // name of the session to inherit from
+    pub path: Option<String>,
+    pub csv: Option<String>,
+    pub count: Option<usize>,
+    pub seed: Option<u64>,
+    pub workers: Option<usize>,
+    pub batch: Option<usize>,
+    pub antialias: Option<String>,
+    pub style: Option<String>,
+    pub frames: Option<usize>,
+    pub fps: Option<f32>,
+    pub format: Option<String>,
+    pub shards: Option<usize>,
+    pub depth: Option<String>,
+    pub mask: Option<bool>,
+    pub landmarks: Option<bool>,
+    pub boxes: Option<bool>,
+    pub size: Option<SizeSpec>,
+    pub projection: Option<String>,
+    pub head: Option<HeadSpec>,
+    pub lefteye: Option<EyeSpec>,
+    pub righteye: Option<EyeSpec>,
+    pub light: Option<LightSpec>,
+    pub background: Option<BackgroundSpec>,
+    pub ambient: Option<RGBSpec>,
+    pub skin: Option<RGBSpec>,
+    pub sclera: Option<RGBSpec>,
+    pub iris: Option<RGBSpec>,
+}
+
+// fill in what a spec leaves out from a base spec (a session it extends, or the defaults)
+pub trait Inherit {
+    fn inherit(&self,base: &Self) -> Self;
+}
+
+fn inherit_option<T: Inherit + Clone>(spec: &Option<T>,base: &Option<T>) -> Option<T> {
+    match (spec,base) {
+        (Some(spec),Some(base)) => Some(spec.inherit(base)),
+        (Some(spec),None) => Some(spec.clone()),
+        (None,base) => base.clone(),
+    }
+}
+
+// mvnormal and palette replace the components, so they are inherited together; setting a component drops the joint of the base
+fn inherit_joint(spec: (&Option<String>,&Option<String>),components: bool,base: (&Option<String>,&Option<String>)) -> (Option<String>,Option<String>) {
+    if spec.0.is_some() || spec.1.is_some() {
+        (spec.0.clone(),spec.1.clone())
+    }
+    else if components {
+        (None,None)
+    }
+    else {
+        (base.0.clone(),base.1.clone())
+    }
+}
+
+impl Inherit for XYZSpec {
+    fn inherit(&self,base: &XYZSpec) -> XYZSpec {
+        let components = self.x.is_some() || self.y.is_some() || self.z.is_some();
+        let (mvnormal,palette) = inherit_joint((&self.mvnormal,&self.palette),components,(&base.mvnormal,&base.palette));
+        XYZSpec {
+            x: self.x.clone().or(base.x.clone()),
+            y: self.y.clone().or(base.y.clone()),
+            z: self.z.clone().or(base.z.clone()),
+            mvnormal: mvnormal,
+            palette: palette,
+        }
+    }
+}
+
+impl Inherit for YPBSpec {
+    fn inherit(&self,base: &YPBSpec) -> YPBSpec {
+        let components = self.y.is_some() || self.p.is_some() || self.b.is_some();
+        let (mvnormal,palette) = inherit_joint((&self.mvnormal,&self.palette),components,(&base.mvnormal,&base.palette));
+        YPBSpec {
+            y: self.y.clone().or(base.y.clone()),
+            p: self.p.clone().or(base.p.clone()),
+            b: self.b.clone().or(base.b.clone()),
+            mvnormal: mvnormal,
+            palette: palette,
+        }
+    }
+}
+
+impl Inherit for RGBSpec {
+    fn inherit(&self,base: &RGBSpec) -> RGBSpec {
+        let components = self.r.is_some() || self.g.is_some() || self.b.is_some();
+        let (mvnormal,palette) = inherit_joint((&self.mvnormal,&self.palette),components,(&base.mvnormal,&base.palette));
+        RGBSpec {
+            r: self.r.clone().or(base.r.clone()),
+            g: self.g.clone().or(base.g.clone()),
+            b: self.b.clone().or(base.b.clone()),
+            mvnormal: mvnormal,
+            palette: palette,
+        }
+    }
+}
+
+impl Inherit for HeadSpec {
+    fn inherit(&self,base: &HeadSpec) -> HeadSpec {
+        HeadSpec {
+            pos: inherit_option(&self.pos,&base.pos),
+            dir: inherit_option(&self.dir,&base.dir),
+            vel: inherit_option(&self.vel,&base.vel),
+            angvel: inherit_option(&self.angvel,&base.angvel),
+        }
+    }
+}
+
+impl Inherit for EyeSpec {
+    fn inherit(&self,base: &EyeSpec) -> EyeSpec {
+        let components = self.y.is_some() || self.p.is_some() || self.b.is_some();
+        let (mvnormal,palette) = inherit_joint((&self.mvnormal,&self.palette),components,(&base.mvnormal,&base.palette));
+        EyeSpec {
+            y: self.y.clone().or(base.y.clone()),
+            p: self.p.clone().or(base.p.clone()),
+            b: self.b.clone().or(base.b.clone()),
+            mvnormal: mvnormal,
+            palette: palette,
+            angvel: inherit_option(&self.angvel,&base.angvel),
+        }
+    }
+}
+
+impl Inherit for LightSpec {
+    fn inherit(&self,base: &LightSpec) -> LightSpec {
+        LightSpec {
+            dir: inherit_option(&self.dir,&base.dir),
+            color: inherit_option(&self.color,&base.color),
+            angvel: inherit_option(&self.angvel,&base.angvel),
+        }
+    }
+}
+
+impl Inherit for BackgroundSpec {
+    fn inherit(&self,base: &BackgroundSpec) -> BackgroundSpec {
+        match (self,base) {
+            (BackgroundSpec::Color { color, },BackgroundSpec::Color { color: base_color, }) => BackgroundSpec::Color { color: color.inherit(base_color), },
+            _ => self.clone(),
+        }
+    }
+}
+
+impl Inherit for SessionSpec {
+    fn inherit(&self,base: &SessionSpec) -> SessionSpec {
+        SessionSpec {
+            extends: base.extends.clone(),  // continue with the base of the base
+            path: self.path.clone().or(base.path.clone()),
+            csv: self.csv.clone().or(base.csv.clone()),
+            count: self.count.or(base.count),
+            seed: self.seed.or(base.seed),
+            workers: self.workers.or(base.workers),
+            batch: self.batch.or(base.batch),
+            antialias: self.antialias.clone().or(base.antialias.clone()),
+            style: self.style.clone().or(base.style.clone()),
+            frames: self.frames.or(base.frames),
+            fps: self.fps.or(base.fps),
+            format: self.format.clone().or(base.format.clone()),
+            shards: self.shards.or(base.shards),
+            depth: self.depth.clone().or(base.depth.clone()),
+            mask: self.mask.or(base.mask),
+            landmarks: self.landmarks.or(base.landmarks),
+            boxes: self.boxes.or(base.boxes),
+            size: self.size.clone().or(base.size.clone()),
+            projection: self.projection.clone().or(base.projection.clone()),
+            head: inherit_option(&self.head,&base.head),
+            lefteye: inherit_option(&self.lefteye,&base.lefteye),
+            righteye: inherit_option(&self.righteye,&base.righteye),
+            light: inherit_option(&self.light,&base.light),
+            background: inherit_option(&self.background,&base.background),
+            ambient: inherit_option(&self.ambient,&base.ambient),
+            skin: inherit_option(&self.skin,&base.skin),
+            sclera: inherit_option(&self.sclera,&base.sclera),
+            iris: inherit_option(&self.iris,&base.iris),
         }
-    }        
+    }
 }
 
 #[derive(Debug)]
@@ -91,22 +328,220 @@ pub enum SessionStyle {
 pub enum SessionFormat {
     BMP,       // as BMP (depth is stored in the alpha channel)
     PNG,       // as PNG (depth is stored in the alpha channel)
-    ProtoBuf,  // as TensorFlow protobuf array
+    PNG16,     // as 16-bit PNG (depth is stored in the 16-bit alpha channel)
+    ProtoBuf,  // as TensorFlow TFRecord files of tf.train.Example records
+}
+
+#[derive(Debug)]
+pub enum SessionDepth {
+    None,               // no separate depth map
+    PNG16(f32,f32),     // 16-bit grayscale PNG, depth is multiplied and offset
+    PFM,                // 32-bit float PFM
+    Raw,                // raw 32-bit little-endian floats
 }
 
 #[derive(Debug)]
 pub enum SessionDistribution {
     Constant(f32),  // value (identical for each instance)
     Normal(rand_distr::Normal<f32>),  // avg,stddev (different by stddev around avg for each instance)
+    Uniform(rand::distributions::Uniform<f32>,f32,f32),  // min,max (equally likely anywhere between min and max)
+    TruncNormal(rand_distr::Normal<f32>,f32,f32),  // avg,stddev,min,max (normal, but never outside min and max)
+    LogNormal(rand_distr::LogNormal<f32>,f32,f32),  // mu,sigma (exp of a normal distribution with avg mu and stddev sigma)
+    Beta(rand_distr::Beta<f32>),  // alpha,beta (between 0 and 1)
+    Choice(Vec<f32>,rand::distributions::WeightedIndex<f32>),  // one of the values, optionally weighted
+}
+
+// complementary error function, with a fractional error below 1.2e-7 everywhere (Numerical Recipes erfcc)
+fn erfc(x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1.0 / (1.0 + 0.5 * z);
+    let r = t * (-z * z - 1.26551223 + t * (1.00002368 + t * (0.37409196 + t * (0.09678418 + t * (-0.18628806 + t * (0.27886807 + t * (-1.13520398 + t * (1.48851587 + t * (-0.82215223 + t * 0.17087277))))))))).exp();
+    if x >= 0.0 { r } else { 2.0 - r }
+}
+
+// standard normal cumulative distribution, accurate relative to its value far into the lower tail
+fn normal_cdf(x: f64) -> f64 {
+    0.5 * erfc(-x / std::f64::consts::SQRT_2)
+}
+
+// inverse of normal_cdf (Acklam's rational approximation, relative error below 1.2e-9)
+fn normal_quantile(p: f64) -> f64 {
+    const A: [f64; 6] = [-3.969683028665376e+01,2.209460984245205e+02,-2.759285104469687e+02,1.383577518672690e+02,-3.066479806614716e+01,2.506628277459239e+00];
+    const B: [f64; 5] = [-5.447609879822406e+01,1.615858368580409e+02,-1.556989798598866e+02,6.680131188771972e+01,-1.328068155288572e+01];
+    const C: [f64; 6] = [-7.784894002430293e-03,-3.223964580411365e-01,-2.400758277161838e+00,-2.549732539343734e+00,4.374664141464968e+00,2.938163982698783e+00];
+    const D: [f64; 4] = [7.784695709041462e-03,3.224671290700398e-01,2.445134137142996e+00,3.754408661907416e+00];
+    const LOW: f64 = 0.02425;
+    let tail = |q: f64| (((((C[0] * q + C[1]) * q + C[2]) * q + C[3]) * q + C[4]) * q + C[5]) / ((((D[0] * q + D[1]) * q + D[2]) * q + D[3]) * q + 1.0);
+    let p = p.max(f64::MIN_POSITIVE).min(1.0 - f64::EPSILON);
+    if p < LOW {
+        tail((-2.0 * p.ln()).sqrt())
+    }
+    else if p <= 1.0 - LOW {
+        let q = p - 0.5;
+        let r = q * q;
+        (((((A[0] * r + A[1]) * r + A[2]) * r + A[3]) * r + A[4]) * r + A[5]) * q / (((((B[0] * r + B[1]) * r + B[2]) * r + B[3]) * r + B[4]) * r + 1.0)
+    }
+    else {
+        -tail((-2.0 * (1.0 - p).ln()).sqrt())
+    }
+}
+
+// sample a normal distribution truncated to min..max by inverting its cumulative distribution, so bounds far in a tail are still exact
+fn sample_truncnormal(normal: &rand_distr::Normal<f32>,min: f32,max: f32,rng: &mut ChaCha8Rng) -> f32 {
+    let mean = normal.mean() as f64;
+    let std_dev = normal.std_dev() as f64;
+    let a = (min as f64 - mean) / std_dev;
+    let b = (max as f64 - mean) / std_dev;
+
+    // an interval entirely above the mean is mirrored into the lower tail, where the cumulative distribution keeps its precision
+    let (low,high,sign) = if a > 0.0 { (-b,-a,-1.0) } else { (a,b,1.0) };
+    let p_low = normal_cdf(low);
+    let p_high = normal_cdf(high);
+    let p = p_low + rng.gen::<f64>() * (p_high - p_low);
+    let value = (mean + std_dev * sign * normal_quantile(p)) as f32;
+
+    // only rounding can leave the interval here
+    value.max(min).min(max)
 }
 
 impl SessionDistribution {
-    pub fn instantiate(&self,rng: &mut rand::rngs::ThreadRng) -> f32 {
+    pub fn instantiate(&self,rng: &mut ChaCha8Rng) -> f32 {
         match self {
             SessionDistribution::Constant(value) => *value,
             SessionDistribution::Normal(normal) => normal.sample(rng) as f32,
+            SessionDistribution::Uniform(uniform,_,_) => uniform.sample(rng),
+            SessionDistribution::TruncNormal(normal,min,max) => sample_truncnormal(normal,*min,*max,rng),
+            SessionDistribution::LogNormal(lognormal,_,_) => lognormal.sample(rng),
+            SessionDistribution::Beta(beta) => beta.sample(rng),
+            SessionDistribution::Choice(values,weights) => values[weights.sample(rng)],
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            SessionDistribution::Constant(_) => "constant",
+            SessionDistribution::Normal(_) => "normal",
+            SessionDistribution::Uniform(_,_,_) => "uniform",
+            SessionDistribution::TruncNormal(_,_,_) => "truncnormal",
+            SessionDistribution::LogNormal(_,_,_) => "lognormal",
+            SessionDistribution::Beta(_) => "beta",
+            SessionDistribution::Choice(_,_) => "choice",
+        }
+    }
+
+    // interval that practically all samples fall in
+    pub fn range(&self) -> (f32,f32) {
+        match self {
+            SessionDistribution::Constant(value) => (*value,*value),
+            SessionDistribution::Normal(normal) => (normal.mean() - RANGE_STDDEVS * normal.std_dev(),normal.mean() + RANGE_STDDEVS * normal.std_dev()),
+            SessionDistribution::Uniform(_,min,max) => (*min,*max),
+            SessionDistribution::TruncNormal(normal,min,max) => ((normal.mean() - RANGE_STDDEVS * normal.std_dev()).max(*min).min(*max),(normal.mean() + RANGE_STDDEVS * normal.std_dev()).max(*min).min(*max)),
+            SessionDistribution::LogNormal(_,mu,sigma) => ((mu - RANGE_STDDEVS * sigma).exp(),(mu + RANGE_STDDEVS * sigma).exp()),
+            SessionDistribution::Beta(_) => (0.0,1.0),
+            SessionDistribution::Choice(values,_) => (values.iter().cloned().fold(f32::MAX,f32::min),values.iter().cloned().fold(f32::MIN,f32::max)),
+        }
+    }
+}
+
+// number of standard deviations around the average that covers 99.7% of a normal distribution
+const RANGE_STDDEVS: f32 = 3.0;
+
+// range of one component of an XYZ, YPB or RGB block, as reported by validate
+pub struct ComponentRange {
+    pub name: &'static str,
+    pub kind: &'static str,
+    pub min: f32,
+    pub max: f32,
+    pub color: bool,  // an RGB component, clamped to 0..1 when rendered
+}
+
+fn component_ranges(components: [(&'static str,&SessionDistribution); 3],joint: &Option<SessionJoint>,color: bool) -> Vec<ComponentRange> {
+    let mut ranges: Vec<ComponentRange> = Vec::new();
+    for i in 0..3 {
+        let (kind,(min,max)) = if let Some(joint) = joint {
+            (joint.kind(),joint.range(i))
+        }
+        else {
+            (components[i].1.kind(),components[i].1.range())
+        };
+        ranges.push(ComponentRange { name: components[i].0,kind: kind,min: min,max: max,color: color, });
+    }
+    ranges
+}
+
+#[derive(Debug)]
+pub enum SessionJoint {
+    MVNormal([f32; 3],[f32; 9]),  // mean,lower triangular cholesky factor of the 3x3 covariance (correlated components)
+    Palette(Vec<[f32; 3]>),  // rows from a palette file (one row is picked for each instance)
+}
+
+impl SessionJoint {
+    pub fn instantiate(&self,rng: &mut ChaCha8Rng) -> [f32; 3] {
+        match self {
+            SessionJoint::MVNormal(mean,l) => {
+                let n0: f32 = StandardNormal.sample(rng);
+                let n1: f32 = StandardNormal.sample(rng);
+                let n2: f32 = StandardNormal.sample(rng);
+                [
+                    mean[0] + l[0] * n0,
+                    mean[1] + l[3] * n0 + l[4] * n1,
+                    mean[2] + l[6] * n0 + l[7] * n1 + l[8] * n2,
+                ]
+            },
+            SessionJoint::Palette(rows) => *rows.choose(rng).unwrap(),
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            SessionJoint::MVNormal(_,_) => "mvnormal",
+            SessionJoint::Palette(_) => "palette",
+        }
+    }
+
+    // interval that practically all samples of a component fall in
+    pub fn range(&self,i: usize) -> (f32,f32) {
+        match self {
+            SessionJoint::MVNormal(mean,l) => {
+                let mut variance = 0.0f32;
+                for k in 0..i + 1 {
+                    variance += l[i * 3 + k] * l[i * 3 + k];
+                }
+                (mean[i] - RANGE_STDDEVS * variance.sqrt(),mean[i] + RANGE_STDDEVS * variance.sqrt())
+            },
+            SessionJoint::Palette(rows) => (rows.iter().map(|row| row[i]).fold(f32::MAX,f32::min),rows.iter().map(|row| row[i]).fold(f32::MIN,f32::max)),
+        }
+    }
+}
+
+// cholesky decomposition of a symmetric positive semi-definite 3x3 matrix, or None if the matrix does not qualify
+fn cholesky(c: &[f32]) -> Option<[f32; 9]> {
+    let mut l = [0.0f32; 9];
+    for i in 0..3 {
+        for j in 0..3 {
+            if (c[i * 3 + j] - c[j * 3 + i]).abs() > 1e-6 {
+                return None;
+            }
+        }
+    }
+    for i in 0..3 {
+        for j in 0..i + 1 {
+            let mut sum = c[i * 3 + j];
+            for k in 0..j {
+                sum -= l[i * 3 + k] * l[j * 3 + k];
+            }
+            if i == j {
+                if sum < -1e-6 {
+                    return None;
+                }
+                l[i * 3 + i] = sum.max(0.0).sqrt();
+            }
+            else {
+                l[i * 3 + j] = if l[j * 3 + j] > 0.0 { sum / l[j * 3 + j] } else { 0.0 };
+            }
         }
     }
+    Some(l)
 }
 
 #[derive(Debug)]
@@ -114,6 +549,25 @@ pub struct SessionXYZ {
     pub x: SessionDistribution,
     pub y: SessionDistribution,
     pub z: SessionDistribution,
+    pub joint: Option<SessionJoint>,  // when present, replaces the independent components
+}
+
+impl SessionXYZ {
+    pub fn instantiate(&self,rng: &mut ChaCha8Rng) -> f32xyz {
+        if let Some(joint) = &self.joint {
+            let v = joint.instantiate(rng);
+            return f32xyz { x: v[0],y: v[1],z: v[2], };
+        }
+        f32xyz {
+            x: self.x.instantiate(rng),
+            y: self.y.instantiate(rng),
+            z: self.z.instantiate(rng),
+        }
+    }
+
+    pub fn ranges(&self) -> Vec<ComponentRange> {
+        component_ranges([("x",&self.x),("y",&self.y),("z",&self.z)],&self.joint,false)
+    }
 }
 
 #[derive(Debug)]
@@ -121,6 +575,25 @@ pub struct SessionYPB {
     pub y: SessionDistribution,
     pub p: SessionDistribution,
     pub b: SessionDistribution,
+    pub joint: Option<SessionJoint>,  // when present, replaces the independent components
+}
+
+impl SessionYPB {
+    pub fn instantiate(&self,rng: &mut ChaCha8Rng) -> f32ypb {
+        if let Some(joint) = &self.joint {
+            let v = joint.instantiate(rng);
+            return f32ypb { y: v[0],p: v[1],b: v[2], };
+        }
+        f32ypb {
+            y: self.y.instantiate(rng),
+            p: self.p.instantiate(rng),
+            b: self.b.instantiate(rng),
+        }
+    }
+
+    pub fn ranges(&self) -> Vec<ComponentRange> {
+        component_ranges([("y",&self.y),("p",&self.p),("b",&self.b)],&self.joint,false)
+    }
 }
 
 #[derive(Debug)]
@@ -128,6 +601,25 @@ pub struct SessionRGB {
     pub r: SessionDistribution,
     pub g: SessionDistribution,
     pub b: SessionDistribution,
+    pub joint: Option<SessionJoint>,  // when present, replaces the independent components
+}
+
+impl SessionRGB {
+    pub fn instantiate(&self,rng: &mut ChaCha8Rng) -> f32rgb {
+        if let Some(joint) = &self.joint {
+            let v = joint.instantiate(rng);
+            return f32rgb { r: v[0],g: v[1],b: v[2], };
+        }
+        f32rgb {
+            r: self.r.instantiate(rng),
+            g: self.g.instantiate(rng),
+            b: self.b.instantiate(rng),
+        }
+    }
+
+    pub fn ranges(&self) -> Vec<ComponentRange> {
+        component_ranges([("r",&self.r),("g",&self.g),("b",&self.b)],&self.joint,true)
+    }
 }
 
 #[derive(Debug)]
@@ -142,16 +634,32 @@ pub struct Session {
     pub path: SessionPath,
     pub csv: String,
     pub count: usize,
+    pub seed: Option<u64>,
+    pub workers: usize,
+    pub batch: usize,  // images rendered before reading them back
+    pub antialias: Antialias,
     pub style: SessionStyle,
+    pub frames: usize,
+    pub fps: f32,
     pub format: SessionFormat,
+    pub shards: usize,
+    pub depth: SessionDepth,
+    pub mask: bool,
+    pub landmarks: bool,
+    pub boxes: bool,
     pub size: usizexy,
     pub projection: f32m4x4,
     pub head_pos: SessionXYZ,
     pub head_dir: SessionYPB,
+    pub head_vel: Option<SessionXYZ>,
+    pub head_angvel: Option<SessionYPB>,
     pub lefteye: SessionYPB,
+    pub lefteye_angvel: Option<SessionYPB>,
     pub righteye: SessionYPB,
+    pub righteye_angvel: Option<SessionYPB>,
     pub light_dir: SessionYPB,
     pub light_color: SessionRGB,
+    pub light_angvel: Option<SessionYPB>,
     pub background: SessionBackground,
     pub ambient_color: SessionRGB,
     pub skin_color: SessionRGB,
@@ -159,480 +667,1412 @@ pub struct Session {
     pub iris_color: SessionRGB,
 }
 
-fn parse_distribution(parser: &mut Parser) -> Option<SessionDistribution> {
-    if let Some(line) = &parser.line {
-        if line.value.starts_with("normal") {
-            let value = line.value["normal".len()..].trim().to_string();
-            let comp: Vec<&str> = value.split(',').collect();
-            if comp.len() != 2 {
-                println!("line {}: normal distribution has 2 parameters: avg and stddev",parser.linenr);
-                return None;
-            }
-            let avg = comp[0].parse::<f32>().unwrap();
-            let stddev = comp[1].parse::<f32>().unwrap();
-            Some(SessionDistribution::Normal(rand_distr::Normal::<f32>::new(avg,stddev).unwrap()))
-        }
-        else if let Ok(value) = line.value.parse::<f32>() {
-            Some(SessionDistribution::Constant(value))
-        }
-        else {
-            None
-        }
-    }
-    else {
-        None
-    }
+// where a key is set, line 0 when not known
+#[derive(Clone,Debug)]
+struct KeyLocation {
+    file: String,
+    line: usize,
+    column: usize,
 }
 
-fn parse_xyz(parser: &mut Parser) -> Option<SessionXYZ> {
-    let mut pos = SessionXYZ {
-        x: SessionDistribution::Constant(0.0),
-        y: SessionDistribution::Constant(0.0),
-        z: SessionDistribution::Constant(0.0),
-    };
-    let current_indent = if let Some(line) = &parser.line {
-        line.indent
-    }
-    else {
-        println!("line {}: missing position specification",parser.linenr);
-        return None;
-    };
-    while let Some(line) = &parser.line {
-        match line.key.as_str() {
-            "x" => {
-                pos.x = if let Some(value) = parse_distribution(parser) { value } else { return None; };
-                parser.accept();
-            },
-            "y" => {
-                pos.y = if let Some(value) = parse_distribution(parser) { value } else { return None; };
-                parser.accept();
-            },
-            "z" => {
-                pos.z = if let Some(value) = parse_distribution(parser) { value } else { return None; };
-                parser.accept();
-            },
-            _ => {
-                if line.indent != current_indent {
-                    return Some(pos);
-                }
-                else {
-                    println!("line {}: x, y or z expected",parser.linenr);
-                    return None;
-                }
-            }
-        }
-    }
-    Some(pos)
+// what was read, so errors can point at the file and line that set a key
+#[derive(Default)]
+struct Sources {
+    locations: BTreeMap<String,KeyLocation>,  // by dotted key, the last file to set a key wins
+    yaml: Vec<(String,String)>,  // name and text of the YAML files, in the order they are merged
 }
 
-fn parse_ypb(parser: &mut Parser) -> Option<SessionYPB> {
-    let mut dir = SessionYPB {
-        y: SessionDistribution::Constant(0.0),
-        p: SessionDistribution::Constant(0.0),
-        b: SessionDistribution::Constant(0.0),
-    };
-    let current_indent = if let Some(line) = &parser.line {
-        line.indent
-    }
-    else {
-        println!("line {}: missing direction specification",parser.linenr);
-        return None;
-    };
-    while let Some(line) = &parser.line {
-        match line.key.as_str() {
-            "y" => {
-                dir.y = if let Some(value) = parse_distribution(parser) { value } else { return None; };
-                parser.accept();
-            },
-            "p" => {
-                dir.p = if let Some(value) = parse_distribution(parser) { value } else { return None; };
-                parser.accept();
-            },
-            "b" => {
-                dir.b = if let Some(value) = parse_distribution(parser) { value } else { return None; };
-                parser.accept();
-            },
-            _ => {
-                if line.indent != current_indent {
-                    return Some(dir);
-                }
-                else {
-                    println!("line {}: y, p or b expected",parser.linenr);
-                    return None;
-                }
+// collects the errors while building sessions, so all of them can be reported
+struct Checker {
+    file: String,
+    locations: BTreeMap<String,KeyLocation>,
+    errors: Vec<ConfigError>,
+}
+
+impl Checker {
+
+    // at the key, or else the nearest block around it, that was set in a file
+    fn error(&mut self,key: &str,message: &str) {
+        let mut path = key;
+        let location = loop {
+            if let Some(location) = self.locations.get(path) {
+                break Some(location.clone());
             }
+            match path.rfind('.') {
+                Some(index) => path = &path[..index],
+                None => break None,
+            }
+        };
+        match location {
+            Some(location) => self.error_at(&location,key,message),
+            None => self.errors.push(ConfigError::new(&self.file,0,0,key,message)),
         }
     }
-    Some(dir)
+
+    fn error_at(&mut self,location: &KeyLocation,key: &str,message: &str) {
+        self.errors.push(ConfigError::new(&location.file,location.line,location.column,key,message));
+    }
 }
 
-fn parse_rgb(parser: &mut Parser) -> Option<SessionRGB> {
-    let mut color = SessionRGB {
-        r: SessionDistribution::Constant(0.0),
-        g: SessionDistribution::Constant(0.0),
-        b: SessionDistribution::Constant(0.0),
-    };
-    let current_indent = if let Some(line) = &parser.line {
-        line.indent
+fn parse_number<T: std::str::FromStr>(checker: &mut Checker,key: &str,value: &str,what: &str) -> Option<T> {
+    if let Ok(value) = value.trim().parse::<T>() {
+        Some(value)
     }
     else {
-        println!("line {}: missing color specification",parser.linenr);
-        return None;
-    };
-    while let Some(line) = &parser.line {
-        match line.key.as_str() {
-            "r" => {
-                color.r = if let Some(value) = parse_distribution(parser) { value } else { return None; };
-                parser.accept();
-            },
-            "g" => {
-                color.g = if let Some(value) = parse_distribution(parser) { value } else { return None; };
-                parser.accept();
-            },
-            "b" => {
-                color.b = if let Some(value) = parse_distribution(parser) { value } else { return None; };
-                parser.accept();
-            },
-            _ => {
-                if line.indent != current_indent {
-                    return Some(color);
-                }
-                else {
-                    println!("line {}: r, g or b expected",parser.linenr);
-                    return None;
-                }
-            }
-        }
+        checker.error(key,&format!("{} expected, found '{}'",what,value.trim()));
+        None
     }
-    Some(color)
 }
 
-fn parse_head(parser: &mut Parser) -> Option<(SessionXYZ,SessionYPB)> {
-    let mut pos = SessionXYZ {
-        x: SessionDistribution::Constant(0.0),
-        y: SessionDistribution::Constant(0.0),
-        z: SessionDistribution::Constant(0.0),
-    };
-    let mut dir = SessionYPB {
-        y: SessionDistribution::Constant(0.0),
-        p: SessionDistribution::Constant(0.0),
-        b: SessionDistribution::Constant(0.0),
-    };
-    let current_indent = if let Some(line) = &parser.line {
-        line.indent
-    }
-    else {
-        println!("line {}: missing head specification",parser.linenr);
+fn parse_parameters(checker: &mut Checker,key: &str,value: &str,name: &str,names: &str,count: usize) -> Option<Vec<f32>> {
+    let comp: Vec<&str> = value.split(',').collect();
+    if comp.len() != count {
+        checker.error(key,&format!("{} has {} parameters: {}",name,count,names));
         return None;
-    };
-    while let Some(line) = &parser.line {
-        match line.key.as_str() {
-            "pos" => {
-                parser.accept();
-                pos = if let Some(value) = parse_xyz(parser) { value } else { return None; };
-            },
-            "dir" => {
-                parser.accept();
-                dir = if let Some(value) = parse_ypb(parser) { value } else { return None; };
-            },
-            _ => {
-                if line.indent != current_indent {
-                    return Some((pos,dir))
-                }
-                else {
-                    println!("line {}: pos or dir expected",parser.linenr);
-                    return None;
-                }
-            },
-        }
     }
-    Some((pos,dir))
+    let mut params: Vec<f32> = Vec::new();
+    for c in comp {
+        params.push(parse_number::<f32>(checker,key,c,"number")?);
+    }
+    Some(params)
 }
 
-fn parse_light(parser: &mut Parser) -> Option<(SessionYPB,SessionRGB)> {
-    let mut dir = SessionYPB {
-        y: SessionDistribution::Constant(0.0),
-        p: SessionDistribution::Constant(0.0),
-        b: SessionDistribution::Constant(0.0),
-    };
-    let mut color = SessionRGB {
-        r: SessionDistribution::Constant(0.0),
-        g: SessionDistribution::Constant(0.0),
-        b: SessionDistribution::Constant(0.0),
+fn parse_distribution(checker: &mut Checker,key: &str,spec: &DistributionSpec) -> Option<SessionDistribution> {
+    let value = match spec {
+        DistributionSpec::Constant(value) => {
+            return Some(SessionDistribution::Constant(*value));
+        },
+        DistributionSpec::Text(value) => value.trim(),
     };
-    let current_indent = if let Some(line) = &parser.line {
-        line.indent
+    if value.starts_with("normal") {
+        let p = parse_parameters(checker,key,value["normal".len()..].trim(),"normal distribution","avg and stddev",2)?;
+        if let Ok(normal) = rand_distr::Normal::<f32>::new(p[0],p[1]) {
+            Some(SessionDistribution::Normal(normal))
+        }
+        else {
+            checker.error(key,"normal distribution needs stddev >= 0");
+            None
+        }
+    }
+    else if value.starts_with("uniform") {
+        let p = parse_parameters(checker,key,value["uniform".len()..].trim(),"uniform distribution","min and max",2)?;
+        if p[0] >= p[1] {
+            checker.error(key,"uniform distribution needs min < max");
+            return None;
+        }
+        Some(SessionDistribution::Uniform(rand::distributions::Uniform::new_inclusive(p[0],p[1]),p[0],p[1]))
+    }
+    else if value.starts_with("truncnormal") {
+        let p = parse_parameters(checker,key,value["truncnormal".len()..].trim(),"truncnormal distribution","avg, stddev, min and max",4)?;
+        if p[2] > p[3] {
+            checker.error(key,"truncnormal distribution needs min <= max");
+            return None;
+        }
+        if p[1] <= 0.0 {
+            checker.error(key,"truncnormal distribution needs stddev > 0");
+            return None;
+        }
+        if let Ok(normal) = rand_distr::Normal::<f32>::new(p[0],p[1]) {
+            Some(SessionDistribution::TruncNormal(normal,p[2],p[3]))
+        }
+        else {
+            checker.error(key,"truncnormal distribution needs stddev > 0");
+            None
+        }
+    }
+    else if value.starts_with("lognormal") {
+        let p = parse_parameters(checker,key,value["lognormal".len()..].trim(),"lognormal distribution","mu and sigma",2)?;
+        if let Ok(lognormal) = rand_distr::LogNormal::<f32>::new(p[0],p[1]) {
+            Some(SessionDistribution::LogNormal(lognormal,p[0],p[1]))
+        }
+        else {
+            checker.error(key,"lognormal distribution needs sigma >= 0");
+            None
+        }
+    }
+    else if value.starts_with("beta") {
+        let p = parse_parameters(checker,key,value["beta".len()..].trim(),"beta distribution","alpha and beta",2)?;
+        if let Ok(beta) = rand_distr::Beta::<f32>::new(p[0],p[1]) {
+            Some(SessionDistribution::Beta(beta))
+        }
+        else {
+            checker.error(key,"beta distribution needs alpha > 0 and beta > 0");
+            None
+        }
+    }
+    else if value.starts_with("choice") {
+        let value = value["choice".len()..].trim();
+        let (values,weights) = if let Some(index) = value.find("weights") {
+            (value[..index].trim(),Some(value[index + "weights".len()..].trim()))
+        }
+        else {
+            (value,None)
+        };
+        let mut parsed_values: Vec<f32> = Vec::new();
+        for v in values.split(',') {
+            parsed_values.push(parse_number::<f32>(checker,key,v,"number")?);
+        }
+        let mut parsed_weights: Vec<f32> = Vec::new();
+        match weights {
+            Some(weights) => {
+                for w in weights.split(',') {
+                    parsed_weights.push(parse_number::<f32>(checker,key,w,"weight")?);
+                }
+            },
+            None => {
+                parsed_weights = vec![1.0; parsed_values.len()];
+            },
+        }
+        if parsed_weights.len() != parsed_values.len() {
+            checker.error(key,"choice distribution needs as many weights as values");
+            return None;
+        }
+        if let Ok(index) = rand::distributions::WeightedIndex::new(parsed_weights) {
+            Some(SessionDistribution::Choice(parsed_values,index))
+        }
+        else {
+            checker.error(key,"choice distribution needs positive weights");
+            None
+        }
+    }
+    else if let Ok(value) = value.parse::<f32>() {
+        Some(SessionDistribution::Constant(value))
+    }
+    else {
+        checker.error(key,&format!("number or distribution expected, found '{}'",value));
+        None
+    }
+}
+
+fn parse_mvnormal(checker: &mut Checker,key: &str,value: &str) -> Option<SessionJoint> {
+    let (mean,cov) = if let Some(index) = value.find("cov") {
+        (value[..index].trim(),value[index + "cov".len()..].trim())
     }
     else {
-        println!("line {}: missing light specification",parser.linenr);
+        checker.error(key,"mvnormal needs a mean vector and a covariance matrix (mean cov matrix)");
         return None;
     };
-    while let Some(line) = &parser.line {
-        match line.key.as_str() {
-            "dir" => {
-                parser.accept();
-                dir = if let Some(value) = parse_ypb(parser) { value } else { return None; };
-            },
-            "color" => {
-                parser.accept();
-                color = if let Some(value) = parse_rgb(parser) { value } else { return None; };
-            },
+    let mean = parse_parameters(checker,key,mean,"mvnormal mean","3 values",3)?;
+    let cov = parse_parameters(checker,key,cov,"mvnormal covariance","9 values",9)?;
+    if let Some(l) = cholesky(&cov) {
+        Some(SessionJoint::MVNormal([mean[0],mean[1],mean[2]],l))
+    }
+    else {
+        checker.error(key,"mvnormal covariance should be symmetric and positive semi-definite");
+        None
+    }
+}
+
+fn parse_palette(checker: &mut Checker,key: &str,name: &str) -> Option<SessionJoint> {
+    let text = match std::fs::read_to_string(name) {
+        Ok(text) => text,
+        Err(_) => {
+            checker.error(key,&format!("cannot read palette file {}",name));
+            return None;
+        },
+    };
+    let mut rows: Vec<[f32; 3]> = Vec::new();
+    for (i,row) in text.lines().enumerate() {
+        let row = row.trim();
+        if row.is_empty() || row.starts_with('#') {
+            continue;
+        }
+        let comp: Vec<f32> = row.split(|c: char| (c == ',') || c.is_whitespace()).filter(|c| !c.is_empty()).filter_map(|c| c.parse::<f32>().ok()).collect();
+        if comp.len() != 3 {
+            checker.error(key,&format!("palette file {} line {} should have 3 values",name,i + 1));
+            return None;
+        }
+        rows.push([comp[0],comp[1],comp[2]]);
+    }
+    if rows.is_empty() {
+        checker.error(key,&format!("palette file {} is empty",name));
+        return None;
+    }
+    Some(SessionJoint::Palette(rows))
+}
+
+fn build_joint(checker: &mut Checker,key: &str,mvnormal: &Option<String>,palette: &Option<String>) -> Option<SessionJoint> {
+    match (mvnormal,palette) {
+        (Some(_),Some(_)) => {
+            checker.error(key,"mvnormal and palette cannot be combined");
+            None
+        },
+        (Some(mvnormal),None) => parse_mvnormal(checker,&format!("{}.mvnormal",key),mvnormal),
+        (None,Some(palette)) => parse_palette(checker,&format!("{}.palette",key),palette),
+        (None,None) => None,
+    }
+}
+
+// a missing component keeps its default
+fn build_component(checker: &mut Checker,key: &str,name: &str,spec: &Option<DistributionSpec>,default: f32) -> SessionDistribution {
+    if let Some(spec) = spec {
+        if let Some(distribution) = parse_distribution(checker,&format!("{}.{}",key,name),spec) {
+            return distribution;
+        }
+    }
+    SessionDistribution::Constant(default)
+}
+
+fn build_xyz(checker: &mut Checker,key: &str,spec: &XYZSpec,default: [f32; 3]) -> SessionXYZ {
+    SessionXYZ {
+        x: build_component(checker,key,"x",&spec.x,default[0]),
+        y: build_component(checker,key,"y",&spec.y,default[1]),
+        z: build_component(checker,key,"z",&spec.z,default[2]),
+        joint: build_joint(checker,key,&spec.mvnormal,&spec.palette),
+    }
+}
+
+fn build_ypb(checker: &mut Checker,key: &str,spec: &YPBSpec,default: [f32; 3]) -> SessionYPB {
+    SessionYPB {
+        y: build_component(checker,key,"y",&spec.y,default[0]),
+        p: build_component(checker,key,"p",&spec.p,default[1]),
+        b: build_component(checker,key,"b",&spec.b,default[2]),
+        joint: build_joint(checker,key,&spec.mvnormal,&spec.palette),
+    }
+}
+
+fn build_rgb(checker: &mut Checker,key: &str,spec: &RGBSpec,default: [f32; 3]) -> SessionRGB {
+    SessionRGB {
+        r: build_component(checker,key,"r",&spec.r,default[0]),
+        g: build_component(checker,key,"g",&spec.g,default[1]),
+        b: build_component(checker,key,"b",&spec.b,default[2]),
+        joint: build_joint(checker,key,&spec.mvnormal,&spec.palette),
+    }
+}
+
+fn build_eye(checker: &mut Checker,key: &str,spec: &EyeSpec) -> SessionYPB {
+    SessionYPB {
+        y: build_component(checker,key,"y",&spec.y,0.0),
+        p: build_component(checker,key,"p",&spec.p,0.0),
+        b: build_component(checker,key,"b",&spec.b,0.0),
+        joint: build_joint(checker,key,&spec.mvnormal,&spec.palette),
+    }
+}
+
+fn build_session(checker: &mut Checker,name: &str,spec: &SessionSpec) -> Session {
+    let key = |k: &str| format!("{}.{}",name,k);
+    let mut session = Session {
+        name: name.to_string(),
+        path: SessionPath::Replace("./".to_string()),
+        csv: "./files.cvs".to_string(),
+        count: 16384,
+        seed: None,
+        workers: 1,
+        batch: 1,
+        antialias: Antialias::Supersample(4,AntialiasFilter::Box),
+        style: SessionStyle::Still,
+        frames: 32,
+        fps: 30.0,
+        format: SessionFormat::BMP,
+        shards: 1,
+        depth: SessionDepth::None,
+        mask: false,
+        landmarks: false,
+        boxes: false,
+        size: usizexy { x: 256,y: 192, },
+        projection: f32m4x4::perspective(30.0,4.0 / 3.0,0.1,100.0),
+        head_pos: build_xyz(checker,&key("head.pos"),&XYZSpec::default(),[0.0,0.0,0.0]),
+        head_dir: build_ypb(checker,&key("head.dir"),&YPBSpec::default(),[0.0,0.0,0.0]),
+        head_vel: None,
+        head_angvel: None,
+        lefteye: build_eye(checker,&key("lefteye"),&EyeSpec::default()),
+        lefteye_angvel: None,
+        righteye: build_eye(checker,&key("righteye"),&EyeSpec::default()),
+        righteye_angvel: None,
+        light_dir: build_ypb(checker,&key("light.dir"),&YPBSpec::default(),[0.0,0.0,0.0]),
+        light_color: build_rgb(checker,&key("light.color"),&RGBSpec::default(),[1.0,1.0,1.0]),
+        light_angvel: None,
+        background: SessionBackground::Color(build_rgb(checker,&key("background"),&RGBSpec::default(),[0.0,0.0,0.0])),
+        ambient_color: build_rgb(checker,&key("ambient"),&RGBSpec::default(),[0.2,0.2,0.2]),
+        skin_color: build_rgb(checker,&key("skin"),&RGBSpec::default(),[0.8,0.7,0.6]),
+        sclera_color: build_rgb(checker,&key("sclera"),&RGBSpec::default(),[0.8,0.8,0.8]),
+        iris_color: build_rgb(checker,&key("iris"),&RGBSpec::default(),[0.2,0.3,0.4]),
+    };
+    if let Some(path) = &spec.path {
+        if path.starts_with("replace") {
+            session.path = SessionPath::Replace(path["replace".len()..].trim().to_string());
+        }
+        else if path.starts_with("append") {
+            session.path = SessionPath::Append(path["append".len()..].trim().to_string());
+        }
+        else {
+            checker.error(&key("path"),"replace or append expected");
+        }
+    }
+    if let Some(csv) = &spec.csv {
+        session.csv = csv.clone();
+    }
+    if let Some(count) = spec.count {
+        session.count = count;
+    }
+    session.seed = spec.seed;
+    if let Some(workers) = spec.workers {
+        session.workers = workers.max(1);
+    }
+    if let Some(batch) = spec.batch {
+        session.batch = batch.max(1);
+    }
+    if let Some(antialias) = &spec.antialias {
+        let antialias = antialias.trim();
+        if antialias.starts_with("supersample") {
+            let comp: Vec<&str> = antialias["supersample".len()..].split(',').map(|c| c.trim()).collect();
+            if comp.len() > 2 {
+                checker.error(&key("antialias"),"supersample has 1 or 2 parameters: factor and filter");
+            }
+            else if let Some(factor) = parse_number::<usize>(checker,&key("antialias"),comp[0],"integer") {
+                let filter = match comp.get(1) {
+                    None | Some(&"box") => Some(AntialiasFilter::Box),
+                    Some(&"tent") => Some(AntialiasFilter::Tent),
+                    Some(&"gaussian") => Some(AntialiasFilter::Gaussian),
+                    Some(_) => {
+                        checker.error(&key("antialias"),"invalid filter (should be box, tent or gaussian)");
+                        None
+                    },
+                };
+                if ![1,2,4,8].contains(&factor) {
+                    checker.error(&key("antialias"),"supersampling factor should be 1, 2, 4 or 8");
+                }
+
+                // with factor 1 the tent and gaussian kernels would be 2 taps wide and shift the image by half a pixel
+                else if (factor == 1) && matches!(filter,Some(AntialiasFilter::Tent) | Some(AntialiasFilter::Gaussian)) {
+                    checker.error(&key("antialias"),"tent and gaussian filters need a supersampling factor of 2 or more");
+                }
+                else if let Some(filter) = filter {
+                    session.antialias = Antialias::Supersample(factor,filter);
+                }
+            }
+        }
+        else if antialias.starts_with("msaa") {
+            if let Some(samples) = parse_number::<usize>(checker,&key("antialias"),&antialias["msaa".len()..],"integer") {
+                if ![1,2,4,8].contains(&samples) {
+                    checker.error(&key("antialias"),"number of samples should be 1, 2, 4 or 8");
+                }
+                else {
+                    session.antialias = Antialias::MSAA(samples);
+                }
+            }
+        }
+        else if antialias == "none" {
+            session.antialias = Antialias::Supersample(1,AntialiasFilter::Box);
+        }
+        else {
+            checker.error(&key("antialias"),"invalid antialiasing (should be supersample, msaa or none)");
+        }
+    }
+    if let Some(style) = &spec.style {
+        let style = style.trim();
+        if style.starts_with("still_depth") {
+            if let Some(p) = parse_parameters(checker,&key("style"),style["still_depth".len()..].trim(),"still_depth","scale and offset",2) {
+                session.style = SessionStyle::StillDepth(p[0],p[1]);
+            }
+        }
+        else if style.starts_with("moving_depth") {
+            if let Some(p) = parse_parameters(checker,&key("style"),style["moving_depth".len()..].trim(),"moving_depth","scale and offset",2) {
+                session.style = SessionStyle::MovingDepth(p[0],p[1]);
+            }
+        }
+        else if style == "still" {
+            session.style = SessionStyle::Still;
+        }
+        else if style == "moving" {
+            session.style = SessionStyle::Moving;
+        }
+        else {
+            checker.error(&key("style"),"invalid session style (should be still, still_depth, moving or moving_depth)");
+        }
+    }
+    if let Some(frames) = spec.frames {
+        if frames < 1 {
+            checker.error(&key("frames"),"at least 1 frame expected");
+        }
+        else {
+            session.frames = frames;
+        }
+    }
+    if let Some(fps) = spec.fps {
+        if fps.is_nan() || (fps <= 0.0) {
+            checker.error(&key("fps"),"positive frame rate expected");
+        }
+        else {
+            session.fps = fps;
+        }
+    }
+    if let Some(format) = &spec.format {
+        match format.trim() {
+            "bmp" => { session.format = SessionFormat::BMP; },
+            "png" => { session.format = SessionFormat::PNG; },
+            "png16" => { session.format = SessionFormat::PNG16; },
+            "protobuf" => { session.format = SessionFormat::ProtoBuf; },
             _ => {
-                if line.indent != current_indent {
-                    return Some((dir,color))
+                checker.error(&key("format"),"invalid session format (should be bmp, png, png16 or protobuf)");
+            },
+        }
+    }
+    if let Some(shards) = spec.shards {
+        if shards < 1 {
+            checker.error(&key("shards"),"at least 1 shard expected");
+        }
+        else {
+            session.shards = shards;
+        }
+    }
+    if let Some(depth) = &spec.depth {
+        let depth = depth.trim();
+        if depth.starts_with("png16") {
+            if let Some(p) = parse_parameters(checker,&key("depth"),depth["png16".len()..].trim(),"png16","scale and offset",2) {
+                session.depth = SessionDepth::PNG16(p[0],p[1]);
+            }
+        }
+        else if depth == "pfm" {
+            session.depth = SessionDepth::PFM;
+        }
+        else if depth == "raw" {
+            session.depth = SessionDepth::Raw;
+        }
+        else if depth == "none" {
+            session.depth = SessionDepth::None;
+        }
+        else {
+            checker.error(&key("depth"),"invalid depth map format (should be none, png16, pfm or raw)");
+        }
+    }
+    if let Some(mask) = spec.mask {
+        session.mask = mask;
+    }
+    if let Some(landmarks) = spec.landmarks {
+        session.landmarks = landmarks;
+    }
+    if let Some(boxes) = spec.boxes {
+        session.boxes = boxes;
+    }
+    if let Some(size) = &spec.size {
+        let comp: Vec<String> = match size {
+            SizeSpec::Text(text) => text.split(',').map(|c| c.to_string()).collect(),
+            SizeSpec::List(list) => list.iter().map(|c| c.to_string()).collect(),
+        };
+        if comp.len() != 2 {
+            checker.error(&key("size"),"size has 2 parameters: width and height");
+        }
+        else {
+            let x = parse_number::<usize>(checker,&key("size"),&comp[0],"integer");
+            let y = parse_number::<usize>(checker,&key("size"),&comp[1],"integer");
+            if let (Some(x),Some(y)) = (x,y) {
+                if (x == 0) || (y == 0) {
+                    checker.error(&key("size"),"size should be at least 1,1");
                 }
                 else {
-                    println!("line {}: dir or color expected",parser.linenr);
-                    return None;
+                    session.size = usizexy { x: x,y: y, };
+                }
+            }
+        }
+    }
+    if let Some(projection) = &spec.projection {
+        let projection = projection.trim();
+        if projection.starts_with("perspective") {
+            let comp: Vec<&str> = projection["perspective".len()..].trim().split(',').collect();
+            if comp.len() != 4 {
+                checker.error(&key("projection"),"perspective has 4 parameters: fovy, aspect, near and far");
+            }
+            else {
+                let fovy = parse_number::<f32>(checker,&key("projection"),comp[0],"number");
+                let aspect = if comp[1].contains('/') {
+                    let vals: Vec<&str> = comp[1].split('/').collect();
+                    if vals.len() != 2 {
+                        checker.error(&key("projection"),"aspect should be a number or a fraction");
+                        None
+                    }
+                    else {
+                        let num = parse_number::<f32>(checker,&key("projection"),vals[0],"number");
+                        let den = parse_number::<f32>(checker,&key("projection"),vals[1],"number");
+                        if let (Some(num),Some(den)) = (num,den) { Some(num / den) } else { None }
+                    }
+                }
+                else {
+                    parse_number::<f32>(checker,&key("projection"),comp[1],"number")
+                };
+                let near = parse_number::<f32>(checker,&key("projection"),comp[2],"number");
+                let far = parse_number::<f32>(checker,&key("projection"),comp[3],"number");
+                if let (Some(fovy),Some(aspect),Some(near),Some(far)) = (fovy,aspect,near,far) {
+                    session.projection = f32m4x4::perspective(fovy,aspect,near,far);
+                }
+            }
+        }
+        else {
+            checker.error(&key("projection"),"only perspective supported");
+        }
+    }
+    if let Some(head) = &spec.head {
+        if let Some(pos) = &head.pos {
+            session.head_pos = build_xyz(checker,&key("head.pos"),pos,[0.0,0.0,0.0]);
+        }
+        if let Some(dir) = &head.dir {
+            session.head_dir = build_ypb(checker,&key("head.dir"),dir,[0.0,0.0,0.0]);
+        }
+        if let Some(vel) = &head.vel {
+            session.head_vel = Some(build_xyz(checker,&key("head.vel"),vel,[0.0,0.0,0.0]));
+        }
+        if let Some(angvel) = &head.angvel {
+            session.head_angvel = Some(build_ypb(checker,&key("head.angvel"),angvel,[0.0,0.0,0.0]));
+        }
+    }
+    if let Some(eye) = &spec.lefteye {
+        session.lefteye = build_eye(checker,&key("lefteye"),eye);
+        if let Some(angvel) = &eye.angvel {
+            session.lefteye_angvel = Some(build_ypb(checker,&key("lefteye.angvel"),angvel,[0.0,0.0,0.0]));
+        }
+    }
+    if let Some(eye) = &spec.righteye {
+        session.righteye = build_eye(checker,&key("righteye"),eye);
+        if let Some(angvel) = &eye.angvel {
+            session.righteye_angvel = Some(build_ypb(checker,&key("righteye.angvel"),angvel,[0.0,0.0,0.0]));
+        }
+    }
+    if let Some(light) = &spec.light {
+        if let Some(dir) = &light.dir {
+            session.light_dir = build_ypb(checker,&key("light.dir"),dir,[0.0,0.0,0.0]);
+        }
+        if let Some(color) = &light.color {
+            session.light_color = build_rgb(checker,&key("light.color"),color,[1.0,1.0,1.0]);
+        }
+        if let Some(angvel) = &light.angvel {
+            session.light_angvel = Some(build_ypb(checker,&key("light.angvel"),angvel,[0.0,0.0,0.0]));
+        }
+    }
+    if let Some(background) = &spec.background {
+        match background {
+            BackgroundSpec::Text(text) => {
+                let text = text.trim();
+                if text == "black" {
+                    session.background = SessionBackground::Color(build_rgb(checker,&key("background"),&RGBSpec::default(),[0.0,0.0,0.0]));
+                }
+                else if text.starts_with("image") {
+                    session.background = SessionBackground::Image(text["image".len()..].trim().to_string());
+                }
+                else {
+                    checker.error(&key("background"),"black, image or a color block expected");
                 }
             },
+            BackgroundSpec::Color { color, } => {
+                session.background = SessionBackground::Color(build_rgb(checker,&key("background.color"),color,[0.0,0.0,0.0]));
+            },
         }
     }
-    Some((dir,color))
+    if let Some(ambient) = &spec.ambient {
+        session.ambient_color = build_rgb(checker,&key("ambient"),ambient,[0.2,0.2,0.2]);
+    }
+    if let Some(skin) = &spec.skin {
+        session.skin_color = build_rgb(checker,&key("skin"),skin,[0.8,0.7,0.6]);
+    }
+    if let Some(sclera) = &spec.sclera {
+        session.sclera_color = build_rgb(checker,&key("sclera"),sclera,[0.8,0.8,0.8]);
+    }
+    if let Some(iris) = &spec.iris {
+        session.iris_color = build_rgb(checker,&key("iris"),iris,[0.2,0.3,0.4]);
+    }
+    session
 }
 
-pub fn load_config(name: &str) -> Option<Vec<Session>> {
-    let file = File::open(name).expect("cannot open config file");
-    let reader = BufReader::new(&file);
-    let mut parser = Parser {
-        lines: reader.lines(),
-        linenr: 0usize,
-        line: None,
-    };
-    parser.accept();
-    let mut sessions: Vec<Session> = Vec::new();
-    while let Some(line) = &parser.line {
-        if line.indent != 0 {
-            println!("line {}: session should start at first column",parser.linenr);
-            return None;
+// 1-based line and column of a byte offset
+fn line_column(text: &str,offset: usize) -> (usize,usize) {
+    let before = &text[..offset.min(text.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.len() - before.rfind('\n').map(|i| i + 1).unwrap_or(0) + 1;
+    (line,column)
+}
+
+// replace ${VAR} and ${VAR:-default} by environment variables, except in comment lines
+fn substitute(name: &str,text: &str) -> Result<String,Vec<ConfigError>> {
+    let mut result = String::new();
+    let mut errors: Vec<ConfigError> = Vec::new();
+    for (i,line) in text.split('\n').enumerate() {
+        if i > 0 {
+            result.push('\n');
         }
-        let mut session = Session {
-            name: line.key.clone(),
-            path: SessionPath::Replace("./".to_string()),
-            csv: "./files.cvs".to_string(),
-            count: 16384,
-            style: SessionStyle::Still,
-            format: SessionFormat::BMP,
-            size: usizexy { x: 256,y: 192, },
-            projection: f32m4x4::perspective(30.0,4.0 / 3.0,0.1,100.0),
-            head_pos: SessionXYZ {
-                x: SessionDistribution::Constant(0.0),
-                y: SessionDistribution::Constant(0.0),
-                z: SessionDistribution::Constant(0.0),
-            },
-            head_dir: SessionYPB {
-                y: SessionDistribution::Constant(0.0),
-                p: SessionDistribution::Constant(0.0),
-                b: SessionDistribution::Constant(0.0),
-            },
-            lefteye: SessionYPB {
-                y: SessionDistribution::Constant(0.0),
-                p: SessionDistribution::Constant(0.0),
-                b: SessionDistribution::Constant(0.0),
-            },
-            righteye: SessionYPB {
-                y: SessionDistribution::Constant(0.0),
-                p: SessionDistribution::Constant(0.0),
-                b: SessionDistribution::Constant(0.0),
-            },
-            light_dir: SessionYPB {
-                y: SessionDistribution::Constant(0.0),
-                p: SessionDistribution::Constant(0.0),
-                b: SessionDistribution::Constant(0.0),    
-            },
-            light_color: SessionRGB {
-                r: SessionDistribution::Constant(1.0),
-                g: SessionDistribution::Constant(1.0),
-                b: SessionDistribution::Constant(1.0),
+        if line.trim_start().starts_with('#') {
+            result.push_str(line);
+            continue;
+        }
+        let mut rest = line;
+        while let Some(start) = rest.find("${") {
+            result.push_str(&rest[..start]);
+            let column = line.len() - rest.len() + start + 1;
+            let after = &rest[start + 2..];
+            let end = if let Some(end) = after.find('}') {
+                end
+            }
+            else {
+                errors.push(ConfigError::new(name,i + 1,column,"","unterminated ${"));
+                rest = "";
+                break;
+            };
+            let expression = &after[..end];
+            let (variable,default) = if let Some(index) = expression.find(":-") {
+                (&expression[..index],Some(&expression[index + 2..]))
+            }
+            else {
+                (expression,None)
+            };
+            match (std::env::var(variable),default) {
+                (Ok(value),_) => result.push_str(&value),
+                (Err(_),Some(default)) => result.push_str(default),
+                (Err(_),None) => {
+                    errors.push(ConfigError::new(name,i + 1,column,variable,"environment variable is not set"));
+                },
+            }
+            rest = &after[end + 1..];
+        }
+        result.push_str(rest);
+    }
+    if errors.is_empty() {
+        Ok(result)
+    }
+    else {
+        Err(errors)
+    }
+}
+
+fn extension(name: &str) -> String {
+    std::path::Path::new(name).extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase()
+}
+
+// read a YAML, TOML or JSON file (by extension) into one document, and return it with the text after substitution; YAML anchors, aliases and merge keys are resolved
+fn read_document(name: &str) -> Result<(serde_yaml::Value,String),Vec<ConfigError>> {
+    let text = match std::fs::read_to_string(name) {
+        Ok(text) => text,
+        Err(error) => {
+            return Err(vec![ConfigError::new(name,0,0,"",&format!("cannot open {}: {}",name,error))]);
+        },
+    };
+    let text = substitute(name,&text)?;
+    let result = match extension(name).as_str() {
+        "toml" => match toml::from_str::<toml::Value>(&text) {
+            Ok(value) => serde_yaml::to_value(value).map_err(|error| ConfigError::new(name,0,0,"",&error.to_string())),
+            Err(error) => {
+                let (line,column) = if let Some(span) = error.span() { line_column(&text,span.start) } else { (0,0) };
+                Err(ConfigError::new(name,line,column,"",error.message()))
             },
-            background: SessionBackground::Color(SessionRGB {
-                r: SessionDistribution::Constant(0.0),
-                g: SessionDistribution::Constant(0.0),
-                b: SessionDistribution::Constant(0.0),
-            }),
-            ambient_color: SessionRGB {
-                r: SessionDistribution::Constant(0.2),
-                g: SessionDistribution::Constant(0.2),
-                b: SessionDistribution::Constant(0.2),
+        },
+        "json" => match serde_json::from_str::<serde_json::Value>(&text) {
+            Ok(value) => serde_yaml::to_value(value).map_err(|error| ConfigError::new(name,0,0,"",&error.to_string())),
+            Err(error) => Err(ConfigError::new(name,error.line(),error.column(),"",&error.to_string())),
+        },
+        _ => match serde_yaml::from_str::<serde_yaml::Value>(&text) {
+            Ok(mut value) => {
+                if let Err(error) = value.apply_merge() {
+                    Err(ConfigError::new(name,0,0,"",&error.to_string()))
+                }
+                else {
+                    Ok(value)
+                }
             },
-            skin_color: SessionRGB {
-                r: SessionDistribution::Constant(0.8),
-                g: SessionDistribution::Constant(0.7),
-                b: SessionDistribution::Constant(0.6),
+            Err(error) => {
+                let (line,column) = if let Some(location) = error.location() { (location.line(),location.column()) } else { (0,0) };
+                Err(ConfigError::new(name,line,column,"",&error.to_string()))
             },
-            sclera_color: SessionRGB {
-                r: SessionDistribution::Constant(0.8),
-                g: SessionDistribution::Constant(0.8),
-                b: SessionDistribution::Constant(0.8),
+        },
+    };
+    result.map(|value| (value,text)).map_err(|error| vec![error])
+}
+
+fn unquote(key: &str) -> &str {
+    key.trim().trim_matches(|c: char| (c == '"') || (c == '\''))
+}
+
+// dotted keys of the block mappings of a YAML file, with their line and column; flow mappings and sequences are not followed
+fn scan_yaml_keys(name: &str,text: &str,locations: &mut BTreeMap<String,KeyLocation>) {
+    let mut stack: Vec<(usize,String)> = Vec::new();  // indentation and key of the enclosing blocks
+    for (i,line) in text.split('\n').enumerate() {
+        let content = line.trim_start();
+        let indent = line.len() - content.len();
+        if content.is_empty() || content.starts_with('#') || content.starts_with('-') {
+            continue;
+        }
+        let end = if let Some(end) = content.find(": ") {
+            end
+        }
+        else if content.ends_with(':') {
+            content.len() - 1
+        }
+        else {
+            continue;
+        };
+        while matches!(stack.last(),Some((n,_)) if *n >= indent) {
+            stack.pop();
+        }
+        stack.push((indent,unquote(&content[..end]).to_string()));
+        let path: Vec<&str> = stack.iter().map(|(_,key)| key.as_str()).collect();
+        locations.insert(path.join("."),KeyLocation { file: name.to_string(),line: i + 1,column: indent + 1, });
+    }
+}
+
+// dotted keys of the tables and keys of a TOML file, with their line and column
+fn scan_toml_keys(name: &str,text: &str,locations: &mut BTreeMap<String,KeyLocation>) {
+    let mut table: Option<String> = None;
+    for (i,line) in text.split('\n').enumerate() {
+        let content = line.trim_start();
+        let location = KeyLocation { file: name.to_string(),line: i + 1,column: line.len() - content.len() + 1, };
+        if content.starts_with("[[") {
+            table = None;  // arrays of tables are not followed
+        }
+        else if content.starts_with('[') {
+            if let Some(end) = content.find(']') {
+                let parts: Vec<&str> = content[1..end].split('.').map(unquote).collect();
+                let path = parts.join(".");
+                locations.insert(path.clone(),location);
+                table = Some(path);
+            }
+        }
+        else if !content.starts_with('#') {
+            if let Some(end) = content.find('=') {
+                let parts: Vec<&str> = content[..end].split('.').map(unquote).collect();
+                let key = parts.join(".");
+                let path = match &table {
+                    Some(table) => format!("{}.{}",table,key),
+                    None => key,
+                };
+                locations.insert(path,location);
+            }
+        }
+    }
+}
+
+// deserializes one session from the text of a YAML file, only to find the line and column of an error
+struct SessionSeed<'a> {
+    name: &'a str,
+}
+
+impl<'de,'a> serde::de::DeserializeSeed<'de> for SessionSeed<'a> {
+    type Value = ();
+
+    fn deserialize<D: serde::Deserializer<'de>>(self,deserializer: D) -> std::result::Result<(),D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de,'a> serde::de::Visitor<'de> for SessionSeed<'a> {
+    type Value = ();
+
+    fn expecting(&self,f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,"sessions")
+    }
+
+    fn visit_map<A: serde::de::MapAccess<'de>>(self,mut map: A) -> std::result::Result<(),A::Error> {
+        while let Some(key) = map.next_key::<serde_yaml::Value>()? {
+            if key.as_str() == Some(self.name) {
+                map.next_value::<SessionSpec>()?;
+            }
+            else {
+                map.next_value::<serde::de::IgnoredAny>()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// where the error in a session comes from, trying the YAML files that could have set it, the last one first
+fn locate_session_error(yaml: &[(String,String)],name: &str,message: &str) -> Option<KeyLocation> {
+    for (file,text) in yaml.iter().rev() {
+        if let Err(error) = (SessionSeed { name: name, }).deserialize(serde_yaml::Deserializer::from_str(text)) {
+
+            // the same error as in the merged document, and not one that merge keys would resolve
+            if let Some(location) = error.location() {
+                if error.to_string().contains(message) {
+                    return Some(KeyLocation { file: file.clone(),line: location.line(),column: location.column(), });
+                }
+            }
+        }
+    }
+    None
+}
+
+// merge a document over a base document, mappings are merged key by key
+fn merge_document(base: &mut serde_yaml::Value,document: serde_yaml::Value) {
+    match (base,document) {
+        (serde_yaml::Value::Mapping(base),serde_yaml::Value::Mapping(document)) => {
+            for (key,value) in document {
+                if let Some(base_value) = base.get_mut(&key) {
+                    merge_document(base_value,value);
+                }
+                else {
+                    base.insert(key,value);
+                }
+            }
+        },
+        (base,document) => {
+            *base = document;
+        },
+    }
+}
+
+// read a document, and the documents it includes underneath it; stack holds the files being read, to catch include loops
+fn read_config(name: &str,stack: &mut Vec<std::path::PathBuf>,sources: &mut Sources) -> Result<serde_yaml::Value,Vec<ConfigError>> {
+    let (mut document,text) = read_document(name)?;
+    let mut locations: BTreeMap<String,KeyLocation> = BTreeMap::new();
+    let yaml = match extension(name).as_str() {
+        "toml" => {
+            scan_toml_keys(name,&text,&mut locations);
+            false
+        },
+        "json" => false,
+        _ => {
+            scan_yaml_keys(name,&text,&mut locations);
+            true
+        },
+    };
+    let include_error = |message: &str| {
+        let (line,column) = locations.get("include").map(|l| (l.line,l.column)).unwrap_or((0,0));
+        ConfigError::new(name,line,column,"include",message)
+    };
+    let includes = if let serde_yaml::Value::Mapping(mapping) = &mut document {
+        mapping.shift_remove("include")
+    }
+    else {
+        None
+    };
+    let includes = match includes {
+        None => Vec::new(),
+        Some(serde_yaml::Value::String(include)) => vec![include],
+        Some(serde_yaml::Value::Sequence(includes)) => {
+            let mut names: Vec<String> = Vec::new();
+            for include in includes {
+                if let serde_yaml::Value::String(include) = include {
+                    names.push(include);
+                }
+                else {
+                    return Err(vec![include_error("file name expected")]);
+                }
+            }
+            names
+        },
+        Some(_) => {
+            return Err(vec![include_error("file name or list of file names expected")]);
+        },
+    };
+
+    // included files are relative to the including file
+    let directory = std::path::Path::new(name).parent().map(|p| p.to_path_buf()).unwrap_or_default();
+    let mut base = serde_yaml::Value::Mapping(serde_yaml::Mapping::new());
+    let mut errors: Vec<ConfigError> = Vec::new();
+    for include in includes {
+        let path = directory.join(&include);
+        let canonical = std::fs::canonicalize(&path).unwrap_or(path.clone());
+        if stack.contains(&canonical) {
+            errors.push(include_error(&format!("{} includes itself",include)));
+            continue;
+        }
+        stack.push(canonical);
+        match read_config(&path.to_string_lossy(),stack,sources) {
+            Ok(document) => merge_document(&mut base,document),
+            Err(include_errors) => errors.extend(include_errors),
+        }
+        stack.pop();
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    merge_document(&mut base,document);
+
+    // after the includes, since this document is merged over them
+    sources.locations.extend(locations);
+    if yaml {
+        sources.yaml.push((name.to_string(),text));
+    }
+    Ok(base)
+}
+
+// set a dotted key, like my_session.head.pos.z, creating blocks as needed; * as first part sets the key in every session
+fn apply_override(document: &mut serde_yaml::Value,key: &str,value: &str) -> Result<(),String> {
+    let parts: Vec<&str> = key.split('.').map(|p| p.trim()).collect();
+    if parts.iter().any(|p| p.is_empty()) {
+        return Err("key should be like session.key".to_string());
+    }
+    if parts[0] == "*" {
+        let names: Vec<String> = match &*document {
+            serde_yaml::Value::Mapping(mapping) => mapping.keys().filter_map(|k| k.as_str()).filter(|k| (*k != "defaults") && !k.starts_with('.')).map(|k| k.to_string()).collect(),
+            _ => Vec::new(),
+        };
+        for name in names {
+            apply_override(document,&format!("{}.{}",name,parts[1..].join(".")),value)?;
+        }
+        return Ok(());
+    }
+
+    // values are YAML, so numbers stay numbers
+    let value = serde_yaml::from_str::<serde_yaml::Value>(value).unwrap_or(serde_yaml::Value::String(value.to_string()));
+    if let serde_yaml::Value::Null = document {
+        *document = serde_yaml::Value::Mapping(serde_yaml::Mapping::new());
+    }
+    let mut current = document;
+    for part in &parts[..parts.len() - 1] {
+        let mapping = match current {
+            serde_yaml::Value::Mapping(mapping) => mapping,
+            _ => {
+                return Err(format!("{} is not a block",part));
             },
-            iris_color: SessionRGB {
-                r: SessionDistribution::Constant(0.2),
-                g: SessionDistribution::Constant(0.3),
-                b: SessionDistribution::Constant(0.4),
+        };
+        if !mapping.contains_key(*part) {
+            mapping.insert(serde_yaml::Value::String(part.to_string()),serde_yaml::Value::Mapping(serde_yaml::Mapping::new()));
+        }
+        current = mapping.get_mut(*part).unwrap();
+        if let serde_yaml::Value::Null = current {
+            *current = serde_yaml::Value::Mapping(serde_yaml::Mapping::new());
+        }
+    }
+    if let serde_yaml::Value::Mapping(mapping) = current {
+        mapping.insert(serde_yaml::Value::String(parts[parts.len() - 1].to_string()),value);
+        Ok(())
+    }
+    else {
+        Err(format!("cannot set {}",key))
+    }
+}
+
+// apply extends chain and defaults to a session spec
+fn resolve_spec(checker: &mut Checker,name: &str,specs: &[(String,SessionSpec)],defaults: &SessionSpec) -> Option<SessionSpec> {
+    let mut spec = specs.iter().find(|(n,_)| n == name)?.1.clone();
+    let mut chain: Vec<String> = vec![name.to_string()];
+    while let Some(base_name) = spec.extends.clone() {
+        if chain.contains(&base_name) {
+            checker.error(&format!("{}.extends",name),&format!("sessions extend each other: {} -> {}",chain.join(" -> "),base_name));
+            return None;
+        }
+        if let Some((_,base)) = specs.iter().find(|(n,_)| *n == base_name) {
+            spec = spec.inherit(base);
+            chain.push(base_name);
+        }
+        else {
+            checker.error(&format!("{}.extends",chain[chain.len() - 1]),&format!("unknown session {}",base_name));
+            return None;
+        }
+    }
+    Some(spec.inherit(defaults))
+}
+
+// overrides are key,value pairs from --set
+pub fn load_config(name: &str,overrides: &[(String,String)]) -> Result<Vec<Session>,Vec<ConfigError>> {
+    let mut stack = vec![std::fs::canonicalize(name).unwrap_or(std::path::PathBuf::from(name))];
+    let mut sources = Sources::default();
+    let mut document = read_config(name,&mut stack,&mut sources)?;
+    let mut errors: Vec<ConfigError> = Vec::new();
+    for (key,value) in overrides {
+        if let Err(message) = apply_override(&mut document,key,value) {
+            errors.push(ConfigError::new("--set",0,0,key,&message));
+        }
+        else {
+
+            // errors in a value from --set are reported as such
+            let parts: Vec<&str> = key.split('.').map(|p| p.trim()).collect();
+            let names: Vec<String> = if parts[0] == "*" {
+                match &document {
+                    serde_yaml::Value::Mapping(mapping) => mapping.keys().filter_map(|k| k.as_str()).filter(|k| (*k != "defaults") && !k.starts_with('.')).map(|k| k.to_string()).collect(),
+                    _ => Vec::new(),
+                }
+            }
+            else {
+                vec![parts[0].to_string()]
+            };
+            for name in names {
+                let path = format!("{}.{}",name,parts[1..].join("."));
+                sources.locations.insert(path.trim_end_matches('.').to_string(),KeyLocation { file: "--set".to_string(),line: 0,column: 0, });
+            }
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    let mapping = match document {
+        serde_yaml::Value::Mapping(mapping) => mapping,
+        serde_yaml::Value::Null => serde_yaml::Mapping::new(),
+        _ => {
+            return Err(vec![ConfigError::new(name,0,0,"","sessions expected at the top level")]);
+        },
+    };
+    let Sources { locations,yaml } = sources;
+    let mut checker = Checker { file: name.to_string(),locations: locations,errors: Vec::new(), };
+    let mut specs: Vec<(String,SessionSpec)> = Vec::new();
+    let mut defaults = SessionSpec::default();
+    for (key,value) in mapping {
+        let name = match key {
+            serde_yaml::Value::String(name) => name,
+            _ => {
+                checker.error("","session name should be a string");
+                continue;
             },
         };
-        parser.accept();
-        while let Some(line) = &parser.line {
-            match line.key.as_str() {
-                "path" => {
-                    session.path = if line.value.starts_with("replace") {
-                        SessionPath::Replace(line.value["replace".len()..].trim().to_string())
-                    }
-                    else if line.value.starts_with("append") {
-                        SessionPath::Append(line.value["append".len()..].trim().to_string())
-                    }
-                    else {
-                        println!("line {}: replace or append expected",parser.linenr);
-                        return None;
-                    };
-                    parser.accept();
-                },
-                "csv" => {
-                    session.csv = line.value.clone();
-                    parser.accept();
-                },
-                "count" => {
-                    session.count = line.value.parse::<usize>().unwrap();
-                    parser.accept();
-                },
-                "style" => {
-                    if line.value.starts_with("still_depth") {
-                        let comp: Vec<&str> = line.value["still_depth".len()..].split(',').collect();
-                        let scale = comp[0].trim().parse::<f32>().unwrap();
-                        let offset = comp[1].trim().parse::<f32>().unwrap();
-                        session.style = SessionStyle::StillDepth(scale,offset);
-                    }
-                    else if line.value.starts_with("moving_depth") {
-                        let comp: Vec<&str> = line.value["moving_depth".len()..].split(',').collect();
-                        let scale = comp[0].trim().parse::<f32>().unwrap();
-                        let offset = comp[1].trim().parse::<f32>().unwrap();
-                        session.style = SessionStyle::MovingDepth(scale,offset);
+        match serde_yaml::from_value::<SessionSpec>(value) {
+            Ok(spec) => {
+                if name == "defaults" {
+                    if spec.extends.is_some() {
+                        checker.error("defaults.extends","defaults cannot extend a session");
                     }
-                    else if line.value == "still" {
-                        session.style = SessionStyle::Still;
-                    }
-                    else if line.value == "moving" {
-                        session.style = SessionStyle::Moving;
-                    }
-                    else {
-                        println!("line {}: invalid session style (should be still, still_depth, moving or moving_depth)",parser.linenr);
-                        return None;
-                    }
-                    parser.accept();
-                },
-                "format" => {
-                    session.format = match line.value.as_str() {
-                        "bmp" => SessionFormat::BMP,
-                        "png" => SessionFormat::PNG,
-                        "protobuf" => SessionFormat::ProtoBuf,
-                        _ => {
-                            println!("line {}: invalid session format (should be bmp, png or protobuf)",parser.linenr);
-                            return None;
-                        },
-                    };
-                    parser.accept();
-                },
-                "size" => {
-                    let comp: Vec<&str> = line.value.split(',').collect();
-                    session.size.x = comp[0].trim().parse::<usize>().unwrap();
-                    session.size.y = comp[1].trim().parse::<usize>().unwrap();
-                    parser.accept();
-                },
-                "projection" => {
-                    session.projection = if line.value.starts_with("perspective") {
-                        let value = line.value["perspective".len()..].trim().to_string();
-                        let comp: Vec<&str> = value.split(',').collect();
-                        if comp.len() != 4 {
-                            println!("line {}: perspective has 4 parameters: fovy, aspect, near and far",parser.linenr);
-                            return None;
-                        }
-                        let fovy = comp[0].trim().parse::<f32>().unwrap();
-                        let aspect = if comp[1].contains('/') {
-                            let vals: Vec<&str> = comp[1].split('/').collect();
-                            let num = vals[0].trim().parse::<f32>().unwrap();
-                            let den = vals[1].trim().parse::<f32>().unwrap();
-                            num / den
-                        }
-                        else {
-                            comp[1].parse::<f32>().unwrap()
-                        };
-                        let near = comp[2].trim().parse::<f32>().unwrap();
-                        let far = comp[3].trim().parse::<f32>().unwrap();
-                        f32m4x4::perspective(fovy,aspect,near,far)
-                    }
-                    else {
-                        println!("line {}: only perspective supported",parser.linenr);
-                        return None;
-                    };
-                    parser.accept();
-                },
-                "head" => {
-                    parser.accept();
-                    let result = if let Some((pos,dir)) = parse_head(&mut parser) { (pos,dir) } else { return None; };
-                    session.head_pos = result.0;
-                    session.head_dir = result.1;
-                },
-                "lefteye" => {
-                    parser.accept();
-                    session.lefteye = if let Some(value) = parse_ypb(&mut parser) { value } else { return None; };
-                },
-                "righteye" => {
-                    parser.accept();
-                    session.righteye = if let Some(value) = parse_ypb(&mut parser) { value } else { return None; };
-                },
-                "light" => {
-                    parser.accept();
-                    let result = if let Some((dir,color)) = parse_light(&mut parser) { (dir,color) } else { return None; };
-                    session.light_dir = result.0;
-                    session.light_color = result.1;
-                },
-                "background" => {
-                    session.background = match line.value.as_str() {
-                        "black" => {
-                            SessionBackground::Color(SessionRGB {
-                                r: SessionDistribution::Constant(0.0),
-                                g: SessionDistribution::Constant(0.0),
-                                b: SessionDistribution::Constant(0.0),
-                            })
-                        },
-                        "color" => {
-                            SessionBackground::Color(if let Some(value) = parse_rgb(&mut parser) { value } else { return None; })
-                        },
-                        _ => {
-                            if line.value.starts_with("image") {
-                                SessionBackground::Image(line.value["image".len()..].trim().to_string())
-                            }
-                            else {
-                                println!("line {}: expected black, color or image",parser.linenr);
-                                return None;
-                            }
-                        }
-                    };
-                    parser.accept();
-                },
-                "ambient" => {
-                    parser.accept();
-                    session.ambient_color = if let Some(value) = parse_rgb(&mut parser) { value } else { return None; };
-                },
-                "skin" => {
-                    parser.accept();
-                    session.skin_color = if let Some(value) = parse_rgb(&mut parser) { value } else { return None; };
-                },
-                "sclera" => {
-                    parser.accept();
-                    session.sclera_color = if let Some(value) = parse_rgb(&mut parser) { value } else { return None; };
-                },
-                "iris" => {
-                    parser.accept();
-                    session.iris_color = if let Some(value) = parse_rgb(&mut parser) { value } else { return None; };
-                },
-                _ => {
-                    println!("line {}: invalid key {}",parser.linenr,line.key);
-                    return None;
+                    defaults = spec;
+                }
+                else {
+                    specs.push((name,spec));
+                }
+            },
+            Err(error) => {
+                let message = error.to_string();
+                match locate_session_error(&yaml,&name,&message) {
+                    Some(location) => checker.error_at(&location,&name,&message),
+                    None => checker.error(&name,&message),
                 }
+            },
+        }
+    }
+    let mut sessions: Vec<Session> = Vec::new();
+    for (name,_) in specs.iter() {
+
+        // names starting with . only hold settings for other sessions
+        if name.starts_with('.') {
+            continue;
+        }
+        if let Some(spec) = resolve_spec(&mut checker,name,&specs,&defaults) {
+            sessions.push(build_session(&mut checker,name,&spec));
+        }
+    }
+    if checker.errors.is_empty() {
+        Ok(sessions)
+    }
+    else {
+        Err(checker.errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    fn spec(yaml: &str) -> SessionSpec {
+        serde_yaml::from_str::<SessionSpec>(yaml).unwrap()
+    }
+
+    fn constant(spec: &Option<DistributionSpec>) -> Option<f32> {
+        match spec {
+            Some(DistributionSpec::Constant(value)) => Some(*value),
+            _ => None,
+        }
+    }
+
+    fn checker() -> Checker {
+        Checker { file: "test.yaml".to_string(),locations: BTreeMap::new(),errors: Vec::new(), }
+    }
+
+    #[test]
+    fn inherit_components() {
+        let base = spec("count: 10\nhead:\n  pos:\n    x: 1.0\n    z: 3.0\n");
+        let child = spec("count: 20\nhead:\n  pos:\n    z: 5.0\n");
+        let spec = child.inherit(&base);
+        assert_eq!(spec.count,Some(20));
+        let pos = spec.head.unwrap().pos.unwrap();
+        assert_eq!(constant(&pos.x),Some(1.0));
+        assert_eq!(constant(&pos.y),None);
+        assert_eq!(constant(&pos.z),Some(5.0));
+    }
+
+    #[test]
+    fn inherit_joint_with_components() {
+        let base = spec("skin:\n  mvnormal: 0.8,0.7,0.6 cov 0.01,0,0,0,0.01,0,0,0,0.01\n");
+
+        // a child that sets a component does not get the joint of its base
+        let skin = spec("skin:\n  r: 0.5\n").inherit(&base).skin.unwrap();
+        assert_eq!(constant(&skin.r),Some(0.5));
+        assert!(skin.mvnormal.is_none() && skin.palette.is_none());
+
+        // a child that sets nothing, or its own joint, keeps a joint
+        let skin = spec("count: 1\n").inherit(&base).skin.unwrap();
+        assert!(skin.mvnormal.is_some());
+        let skin = spec("skin:\n  palette: colors.txt\n").inherit(&base).skin.unwrap();
+        assert!(skin.mvnormal.is_none());
+        assert_eq!(skin.palette.as_deref(),Some("colors.txt"));
+    }
+
+    #[test]
+    fn resolve_chain() {
+        let specs = vec![
+            ("a".to_string(),spec("extends: b\ncount: 1\n")),
+            ("b".to_string(),spec("extends: c\ncount: 2\nbatch: 2\n")),
+            ("c".to_string(),spec("count: 3\nbatch: 3\nworkers: 3\n")),
+        ];
+        let defaults = spec("count: 4\nbatch: 4\nworkers: 4\nframes: 4\n");
+        let mut checker = checker();
+        let spec = resolve_spec(&mut checker,"a",&specs,&defaults).unwrap();
+        assert!(checker.errors.is_empty());
+        assert_eq!((spec.count,spec.batch,spec.workers,spec.frames),(Some(1),Some(2),Some(3),Some(4)));
+        assert!(spec.extends.is_none());
+    }
+
+    #[test]
+    fn resolve_errors() {
+        let specs = vec![
+            ("a".to_string(),spec("extends: b\n")),
+            ("b".to_string(),spec("extends: a\n")),
+            ("c".to_string(),spec("extends: d\n")),
+        ];
+        let defaults = SessionSpec::default();
+        let mut checker = checker();
+        assert!(resolve_spec(&mut checker,"a",&specs,&defaults).is_none());
+        assert!(resolve_spec(&mut checker,"c",&specs,&defaults).is_none());
+        assert_eq!(checker.errors.len(),2);
+        assert_eq!(checker.errors[0].key,"a.extends");
+        assert_eq!(checker.errors[0].message,"sessions extend each other: a -> b -> a");
+        assert_eq!(checker.errors[1].key,"c.extends");
+        assert_eq!(checker.errors[1].message,"unknown session d");
+    }
+
+    #[test]
+    fn cholesky_factors() {
+        assert_eq!(cholesky(&[4.0,0.0,0.0,0.0,9.0,0.0,0.0,0.0,16.0]),Some([2.0,0.0,0.0,0.0,3.0,0.0,0.0,0.0,4.0]));
+        let c = [4.0,2.0,2.0,2.0,5.0,3.0,2.0,3.0,6.0];
+        let l = cholesky(&c).unwrap();
+        assert_eq!(l,[2.0,0.0,0.0,1.0,2.0,0.0,1.0,1.0,2.0]);
+
+        // l times its transpose gives the covariance back
+        for i in 0..3 {
+            for j in 0..3 {
+                let sum: f32 = (0..3).map(|k| l[i * 3 + k] * l[j * 3 + k]).sum();
+                assert_eq!(sum,c[i * 3 + j]);
             }
         }
-        sessions.push(session);
     }
-    Some(sessions)
+
+    #[test]
+    fn cholesky_semi_definite() {
+
+        // perfectly correlated components have a zero pivot
+        assert_eq!(cholesky(&[1.0,1.0,0.0,1.0,1.0,0.0,0.0,0.0,0.0]),Some([1.0,0.0,0.0,1.0,0.0,0.0,0.0,0.0,0.0]));
+    }
+
+    #[test]
+    fn cholesky_rejects() {
+        assert_eq!(cholesky(&[1.0,0.5,0.0,0.0,1.0,0.0,0.0,0.0,1.0]),None);  // not symmetric
+        assert_eq!(cholesky(&[1.0,2.0,0.0,2.0,1.0,0.0,0.0,0.0,1.0]),None);  // not positive semi-definite
+    }
+
+    fn yaml(text: &str) -> serde_yaml::Value {
+        serde_yaml::from_str::<serde_yaml::Value>(text).unwrap()
+    }
+
+    fn config_errors(name: &str,extension: &str,text: &str) -> (String,Vec<ConfigError>) {
+        let file = std::env::temp_dir().join(format!("commedia_{}.{}",name,extension));
+        std::fs::write(&file,text).unwrap();
+        let errors = load_config(file.to_str().unwrap(),&[]).err().unwrap_or_default();
+        std::fs::remove_file(&file).unwrap();
+        (file.to_str().unwrap().to_string(),errors)
+    }
+
+    #[test]
+    fn config_error_locations() {
+
+        // every error is reported, at the line and column of its key
+        let (file,mut errors) = config_errors("config_error_locations","yaml","s:\n  count: 4\n  style: sideways\n  head:\n    pos:\n      x: uniform 2,1\n");
+        errors.sort_by_key(|error| error.line);
+        assert_eq!(errors.len(),2);
+        assert_eq!((errors[0].line,errors[0].column,errors[0].key.as_str()),(3,3,"s.style"));
+        assert_eq!((errors[1].line,errors[1].column,errors[1].key.as_str()),(6,7,"s.head.pos.x"));
+        assert_eq!(errors[1].to_string(),format!("{}: line 6, column 7: s.head.pos.x: uniform distribution needs min < max",file));
+    }
+
+    #[test]
+    fn substitute_variables() {
+        std::env::set_var("COMMEDIA_TEST_SIZE","256,192");
+        std::env::remove_var("COMMEDIA_TEST_UNSET");
+        let text = substitute("test.yaml","size: ${COMMEDIA_TEST_SIZE}\n# ${COMMEDIA_TEST_UNSET}\ncount: ${COMMEDIA_TEST_UNSET:-5}\n").unwrap();
+        assert_eq!(text,"size: 256,192\n# ${COMMEDIA_TEST_UNSET}\ncount: 5\n");
+    }
+
+    #[test]
+    fn substitute_errors() {
+        std::env::remove_var("COMMEDIA_TEST_UNSET");
+        let errors = substitute("test.yaml","a: ${COMMEDIA_TEST_UNSET}\nb: ${OPEN").unwrap_err();
+        assert_eq!(errors.len(),2);
+        assert_eq!((errors[0].line,errors[0].column,errors[0].key.as_str(),errors[0].message.as_str()),(1,4,"COMMEDIA_TEST_UNSET","environment variable is not set"));
+        assert_eq!((errors[1].line,errors[1].column,errors[1].message.as_str()),(2,4,"unterminated ${"));
+    }
+
+    #[test]
+    fn documents_equivalent() {
+
+        // the same config in any of the three formats reads as the same document
+        let documents = [
+            ("yaml","s:\n  count: 4\n  fps: 12.5\n  mask: true\n  head:\n    pos:\n      x: uniform 1,2\n      z: -0.5\n"),
+            ("toml","[s]\ncount = 4\nfps = 12.5\nmask = true\n\n[s.head.pos]\nx = \"uniform 1,2\"\nz = -0.5\n"),
+            ("json","{ \"s\": { \"count\": 4,\"fps\": 12.5,\"mask\": true,\"head\": { \"pos\": { \"x\": \"uniform 1,2\",\"z\": -0.5 } } } }\n"),
+        ];
+        let values: Vec<serde_yaml::Value> = documents.iter().map(|(extension,text)| {
+            let file = std::env::temp_dir().join(format!("commedia_documents_equivalent.{}",extension));
+            std::fs::write(&file,text).unwrap();
+            let (value,_) = read_document(file.to_str().unwrap()).unwrap();
+            std::fs::remove_file(&file).unwrap();
+            value
+        }).collect();
+        assert_eq!(values[0],yaml("s:\n  count: 4\n  fps: 12.5\n  mask: true\n  head:\n    pos:\n      x: uniform 1,2\n      z: -0.5\n"));
+        assert_eq!(values[1],values[0]);
+        assert_eq!(values[2],values[0]);
+
+        // and errors in a TOML file are located just as well
+        let (_,errors) = config_errors("toml_error_locations","toml","[s]\ncount = 4\n\n[s.head.pos]\nx = \"uniform 2,1\"\n");
+        assert_eq!(errors.len(),1);
+        assert_eq!((errors[0].line,errors[0].column,errors[0].key.as_str()),(5,1,"s.head.pos.x"));
+    }
+
+    #[test]
+    fn merge_documents() {
+        let mut base = yaml("a:\n  x: 1\n  y: 2\nb: 1\n");
+        merge_document(&mut base,yaml("a:\n  y: 3\n  z: 4\nc: 5\n"));
+        assert_eq!(base,yaml("a:\n  x: 1\n  y: 3\n  z: 4\nb: 1\nc: 5\n"));
+
+        // anything but two mappings is replaced
+        merge_document(&mut base,yaml("a: 6\n"));
+        assert_eq!(base,yaml("a: 6\nb: 1\nc: 5\n"));
+    }
+
+    #[test]
+    fn override_keys() {
+        let mut document = yaml("s:\n  head:\n    pos:\n      x: 1\nt:\n  count: 1\ndefaults:\n  count: 2\n.base:\n  count: 3\n");
+        apply_override(&mut document,"s.head.pos.z","0.5").unwrap();
+        apply_override(&mut document,"s.path","append ./out").unwrap();
+        apply_override(&mut document,"*.count","7").unwrap();
+        assert_eq!(document,yaml("s:\n  head:\n    pos:\n      x: 1\n      z: 0.5\n  path: append ./out\n  count: 7\nt:\n  count: 7\ndefaults:\n  count: 2\n.base:\n  count: 3\n"));
+        assert_eq!(apply_override(&mut document,"s..x","1"),Err("key should be like session.key".to_string()));
+        assert_eq!(apply_override(&mut document,"s.head.pos.x.y","1"),Err("cannot set s.head.pos.x.y".to_string()));
+        assert_eq!(apply_override(&mut document,"s.head.pos.x.y.z","1"),Err("y is not a block".to_string()));
+    }
+
+    // mvnormal
+
+
+
+    #[test]
+    fn mvnormal_parse() {
+        let mut checker = checker();
+        assert!(parse_mvnormal(&mut checker,"skin.mvnormal","0.8,0.7,0.6 cov 0.01,0,0,0,0.01,0,0,0,0.01").is_some());
+        assert!(parse_mvnormal(&mut checker,"skin.mvnormal","0.8,0.7,0.6").is_none());
+        assert!(parse_mvnormal(&mut checker,"skin.mvnormal","0.8,0.7 cov 0.01,0,0,0,0.01,0,0,0,0.01").is_none());
+        assert!(parse_mvnormal(&mut checker,"skin.mvnormal","0,0,0 cov 1,2,0,2,1,0,0,0,1").is_none());
+        let messages: Vec<&str> = checker.errors.iter().map(|error| error.message.as_str()).collect();
+        assert_eq!(messages,vec![
+            "mvnormal needs a mean vector and a covariance matrix (mean cov matrix)",
+            "mvnormal mean has 3 parameters: 3 values",
+            "mvnormal covariance should be symmetric and positive semi-definite",
+        ]);
+    }
+
+    #[test]
+    fn mvnormal_correlated() {
+
+        // the first two components move together, the third has no variance
+        let mut checker = checker();
+        let joint = parse_mvnormal(&mut checker,"skin.mvnormal","0.5,0.5,0.25 cov 0.01,0.01,0,0.01,0.01,0,0,0,0").unwrap();
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        for _ in 0..100 {
+            let v = joint.instantiate(&mut rng);
+            assert!((v[0] - v[1]).abs() < 1e-6);
+            assert_eq!(v[2],0.25);
+        }
+    }
+
+    // distributions
+
+    fn distribution(checker: &mut Checker,text: &str) -> Option<SessionDistribution> {
+        parse_distribution(checker,"head.pos.x",&DistributionSpec::Text(text.to_string()))
+    }
+
+    fn samples(distribution: &SessionDistribution) -> Vec<f32> {
+        let mut rng = ChaCha8Rng::seed_from_u64(11);
+        (0..1000).map(|_| distribution.instantiate(&mut rng)).collect()
+    }
+
+    #[test]
+    fn distribution_keywords() {
+        let mut checker = checker();
+        let cases = [
+            ("0.5","constant",(0.5,0.5)),
+            ("normal 0,1","normal",(-3.0,3.0)),
+            ("uniform 1,2","uniform",(1.0,2.0)),
+            ("truncnormal 0,20,-50,60","truncnormal",(-50.0,60.0)),
+            ("beta 2,5","beta",(0.0,1.0)),
+            ("choice 1,2,3 weights 1,0,2","choice",(1.0,3.0)),
+        ];
+        for (text,kind,range) in cases.iter() {
+            let distribution = distribution(&mut checker,text).unwrap();
+            assert_eq!(distribution.kind(),*kind);
+            assert_eq!(distribution.range(),*range);
+        }
+        assert_eq!(distribution(&mut checker,"lognormal 0,0.5").unwrap().kind(),"lognormal");
+        assert!(checker.errors.is_empty());
+    }
+
+    #[test]
+    fn distribution_errors() {
+        let mut checker = checker();
+        let cases = [
+            ("uniform 2,1","uniform distribution needs min < max"),
+            ("uniform 1","uniform distribution has 2 parameters: min and max"),
+            ("truncnormal 0,1,2,1","truncnormal distribution needs min <= max"),
+            ("truncnormal 0,0,-1,1","truncnormal distribution needs stddev > 0"),
+            ("lognormal 0,-1","lognormal distribution needs sigma >= 0"),
+            ("beta 0,1","beta distribution needs alpha > 0 and beta > 0"),
+            ("choice 1,2 weights 1,-1","choice distribution needs positive weights"),
+            ("choice 1,2 weights 0,0","choice distribution needs positive weights"),
+            ("choice 1,2 weights 1","choice distribution needs as many weights as values"),
+            ("choice 1,2 weights","weight expected, found ''"),
+            ("choice","number expected, found ''"),
+            ("gamma 1,2","number or distribution expected, found 'gamma 1,2'"),
+        ];
+        for (text,message) in cases.iter() {
+            assert!(distribution(&mut checker,text).is_none(),"{}",text);
+            assert_eq!(checker.errors.last().unwrap().message,*message);
+        }
+        assert_eq!(checker.errors.len(),cases.len());
+    }
+
+    #[test]
+    fn distribution_samples_in_range() {
+        let mut checker = checker();
+        for text in ["uniform 1,2","truncnormal 0,20,-50,60","beta 2,5","choice 1,2,3 weights 1,0,2"].iter() {
+            let distribution = distribution(&mut checker,text).unwrap();
+            let (min,max) = distribution.range();
+            for value in samples(&distribution) {
+                assert!((value >= min) && (value <= max),"{}: {}",text,value);
+            }
+        }
+        let choice = samples(&distribution(&mut checker,"choice 1,2,3 weights 1,0,2").unwrap());
+        assert!(!choice.contains(&2.0) && choice.contains(&1.0) && choice.contains(&3.0));
+        assert!(samples(&distribution(&mut checker,"lognormal 0,0.5").unwrap()).iter().all(|value| *value > 0.0));
+    }
+
+    #[test]
+    fn truncnormal_far_tail() {
+
+        // 5 to 6 standard deviations out, the samples spread over the interval instead of piling up on a bound; the mean of this truncated normal is 5.18
+        let mut checker = checker();
+        for (text,sign) in [("truncnormal 0,1,5,6",1.0),("truncnormal 0,1,-6,-5",-1.0)].iter() {
+            let values = samples(&distribution(&mut checker,text).unwrap());
+            assert!(values.iter().all(|value| (*value * sign > 5.0) && (*value * sign < 6.0)));
+            let mean = values.iter().sum::<f32>() / (values.len() as f32) * sign;
+            assert!((mean > 5.1) && (mean < 5.3),"{}: {}",text,mean);
+        }
+    }
+
+    #[test]
+    fn normal_quantile_inverts_cdf() {
+        for p in [1e-20,1e-6,0.01,0.3,0.5,0.9,0.999].iter() {
+            let x = normal_quantile(*p);
+            assert!(((normal_cdf(x) - p) / p).abs() < 1e-6,"{}",p);
+        }
+    }
+
+    #[test]
+    fn ranges_tag_colors() {
+        let rgb = SessionRGB { r: SessionDistribution::Constant(0.5),g: SessionDistribution::Constant(1.5),b: SessionDistribution::Constant(-0.5),joint: None, };
+        let ranges = rgb.ranges();
+        assert!(ranges.iter().all(|range| range.color));
+        assert_eq!(ranges.iter().map(|range| (range.name,range.min,range.max)).collect::<Vec<_>>(),vec![("r",0.5,0.5),("g",1.5,1.5),("b",-0.5,-0.5)]);
+        let xyz = SessionXYZ { x: SessionDistribution::Constant(2.0),y: SessionDistribution::Constant(0.0),z: SessionDistribution::Constant(-2.0),joint: None, };
+        assert!(xyz.ranges().iter().all(|range| !range.color));
+        let ypb = SessionYPB { y: SessionDistribution::Constant(2.0),p: SessionDistribution::Constant(0.0),b: SessionDistribution::Constant(-2.0),joint: None, };
+        assert!(ypb.ranges().iter().all(|range| !range.color));
+    }
 }
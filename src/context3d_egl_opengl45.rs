@@ -0,0 +1,216 @@
+// (C) Copyright 2020, by Germans Media Technology & Services
+// Commedia
+// Headless Context3D for Linux using EGL (surfaceless or pbuffer), exposing OpenGL 4.5
+
+use std::{ffi,os,ptr,fmt};
+#[doc(no_inline)]
+extern crate gl;
+
+type EGLDisplay = *mut os::raw::c_void;
+type EGLConfig = *mut os::raw::c_void;
+type EGLContext = *mut os::raw::c_void;
+type EGLSurface = *mut os::raw::c_void;
+type EGLint = i32;
+type EGLBoolean = u32;
+type EGLenum = u32;
+
+const EGL_NONE: EGLint = 0x3038;
+const EGL_EXTENSIONS: EGLint = 0x3055;
+const EGL_SURFACE_TYPE: EGLint = 0x3033;
+const EGL_PBUFFER_BIT: EGLint = 0x0001;
+const EGL_RENDERABLE_TYPE: EGLint = 0x3040;
+const EGL_OPENGL_BIT: EGLint = 0x0008;
+const EGL_RED_SIZE: EGLint = 0x3024;
+const EGL_GREEN_SIZE: EGLint = 0x3023;
+const EGL_BLUE_SIZE: EGLint = 0x3022;
+const EGL_ALPHA_SIZE: EGLint = 0x3021;
+const EGL_DEPTH_SIZE: EGLint = 0x3025;
+const EGL_STENCIL_SIZE: EGLint = 0x3026;
+const EGL_WIDTH: EGLint = 0x3057;
+const EGL_HEIGHT: EGLint = 0x3056;
+const EGL_OPENGL_API: EGLenum = 0x30A2;
+const EGL_CONTEXT_MAJOR_VERSION: EGLint = 0x3098;
+const EGL_CONTEXT_MINOR_VERSION: EGLint = 0x30FB;
+const EGL_PLATFORM_SURFACELESS_MESA: EGLenum = 0x31DD;
+
+type EGLGetPlatformDisplayEXTProc = unsafe extern "C" fn(platform: EGLenum,native_display: *mut os::raw::c_void,attrib_list: *const EGLint) -> EGLDisplay;
+
+#[link(name = "EGL")]
+extern "C" {
+    fn eglGetProcAddress(procname: *const os::raw::c_char) -> *mut os::raw::c_void;
+    fn eglQueryString(dpy: EGLDisplay,name: EGLint) -> *const os::raw::c_char;
+    fn eglGetDisplay(display_id: *mut os::raw::c_void) -> EGLDisplay;
+    fn eglInitialize(dpy: EGLDisplay,major: *mut EGLint,minor: *mut EGLint) -> EGLBoolean;
+    fn eglChooseConfig(dpy: EGLDisplay,attrib_list: *const EGLint,configs: *mut EGLConfig,config_size: EGLint,num_config: *mut EGLint) -> EGLBoolean;
+    fn eglBindAPI(api: EGLenum) -> EGLBoolean;
+    fn eglCreateContext(dpy: EGLDisplay,config: EGLConfig,share_context: EGLContext,attrib_list: *const EGLint) -> EGLContext;
+    fn eglCreatePbufferSurface(dpy: EGLDisplay,config: EGLConfig,attrib_list: *const EGLint) -> EGLSurface;
+    fn eglMakeCurrent(dpy: EGLDisplay,draw: EGLSurface,read: EGLSurface,ctx: EGLContext) -> EGLBoolean;
+    fn eglDestroySurface(dpy: EGLDisplay,surface: EGLSurface) -> EGLBoolean;
+    fn eglDestroyContext(dpy: EGLDisplay,ctx: EGLContext) -> EGLBoolean;
+}
+
+// null when the driver does not have the function
+fn load_function(name: &str) -> *mut os::raw::c_void {
+    let newname = ffi::CString::new(name).unwrap();
+    unsafe { eglGetProcAddress(newname.as_ptr()) }
+}
+
+// true if name is one of the space separated extensions, and not just a prefix of one
+fn extension_listed(extensions: &str,name: &str) -> bool {
+    extensions.split(' ').any(|extension| extension == name)
+}
+
+fn has_extension(dpy: EGLDisplay,name: &str) -> bool {
+    let extensions = unsafe { eglQueryString(dpy,EGL_EXTENSIONS) };
+    if extensions.is_null() {
+        return false;
+    }
+    if let Ok(e) = unsafe { ffi::CStr::from_ptr(extensions) }.to_str() {
+        extension_listed(e,name)
+    }
+    else {
+        false
+    }
+}
+
+pub enum HeadlessContext3DError {
+    Display,
+    Driver,
+    Function(&'static str),  // the driver does not have this function
+}
+
+impl fmt::Debug for HeadlessContext3DError {
+    fn fmt(&self,f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HeadlessContext3DError::Display => { write!(f,"EGL display error.") },
+            HeadlessContext3DError::Driver => { write!(f,"EGL driver error.") },
+            HeadlessContext3DError::Function(name) => { write!(f,"EGL: unable to load {}",name) },
+        }
+    }
+}
+
+pub struct HeadlessContext3D {
+    display: EGLDisplay,
+    surface: EGLSurface,  // null when surfaceless
+    context: EGLContext,
+}
+
+impl HeadlessContext3D {
+    pub fn new() -> std::result::Result<HeadlessContext3D,HeadlessContext3DError> {
+
+        // prefer the surfaceless platform, which needs no display server or GPU device at all
+        let display = if has_extension(ptr::null_mut(),"EGL_MESA_platform_surfaceless") && has_extension(ptr::null_mut(),"EGL_EXT_platform_base") {
+            let pointer = load_function("eglGetPlatformDisplayEXT");
+            if pointer.is_null() {
+                return Err(HeadlessContext3DError::Function("eglGetPlatformDisplayEXT"));
+            }
+            let egl_get_platform_display: EGLGetPlatformDisplayEXTProc = unsafe { std::mem::transmute(pointer) };
+            unsafe { egl_get_platform_display(EGL_PLATFORM_SURFACELESS_MESA,ptr::null_mut(),ptr::null()) }
+        }
+        else {
+            unsafe { eglGetDisplay(ptr::null_mut()) }
+        };
+        if display.is_null() {
+            return Err(HeadlessContext3DError::Display);
+        }
+        let mut eglmaj: EGLint = 0;
+        let mut eglmin: EGLint = 0;
+        if unsafe { eglInitialize(display,&mut eglmaj as *mut EGLint,&mut eglmin as *mut EGLint) } == 0 { return Err(HeadlessContext3DError::Display); }
+        if (eglmaj * 100 + eglmin) < 104 { return Err(HeadlessContext3DError::Driver); }
+        let surfaceless = has_extension(display,"EGL_KHR_surfaceless_context");
+        let attribs = [
+            EGL_SURFACE_TYPE,    if surfaceless { 0 } else { EGL_PBUFFER_BIT },
+            EGL_RENDERABLE_TYPE, EGL_OPENGL_BIT,
+            EGL_RED_SIZE,        8,
+            EGL_GREEN_SIZE,      8,
+            EGL_BLUE_SIZE,       8,
+            EGL_ALPHA_SIZE,      8,
+            EGL_DEPTH_SIZE,      24,
+            EGL_STENCIL_SIZE,    8,
+            EGL_NONE,
+        ];
+        let mut config: EGLConfig = ptr::null_mut();
+        let mut count: EGLint = 0;
+        if unsafe { eglChooseConfig(display,attribs.as_ptr(),&mut config as *mut EGLConfig,1,&mut count as *mut EGLint) } == 0 { return Err(HeadlessContext3DError::Driver); }
+        if count == 0 { return Err(HeadlessContext3DError::Driver); }
+        if unsafe { eglBindAPI(EGL_OPENGL_API) } == 0 { return Err(HeadlessContext3DError::Driver); }
+        let context_attribs = [
+            EGL_CONTEXT_MAJOR_VERSION, 4,
+            EGL_CONTEXT_MINOR_VERSION, 5,
+            EGL_NONE,
+        ];
+        let context = unsafe { eglCreateContext(display,config,ptr::null_mut(),context_attribs.as_ptr()) };
+        if context.is_null() {
+            return Err(HeadlessContext3DError::Driver);
+        }
+
+        // all rendering goes to framebuffer objects, so a tiny pbuffer will do when surfaceless contexts are not supported
+        let surface = if surfaceless {
+            ptr::null_mut()
+        }
+        else {
+            let pbuffer_attribs = [
+                EGL_WIDTH, 1,
+                EGL_HEIGHT, 1,
+                EGL_NONE,
+            ];
+            let surface = unsafe { eglCreatePbufferSurface(display,config,pbuffer_attribs.as_ptr()) };
+            if surface.is_null() {
+                unsafe { eglDestroyContext(display,context); }
+                return Err(HeadlessContext3DError::Driver);
+            }
+            surface
+        };
+        if unsafe { eglMakeCurrent(display,surface,surface,context) } == 0 {
+            if !surface.is_null() {
+                unsafe { eglDestroySurface(display,surface); }
+            }
+            unsafe { eglDestroyContext(display,context); }
+            return Err(HeadlessContext3DError::Driver);
+        }
+        let context = HeadlessContext3D {
+            display: display,
+            surface: surface,
+            context: context,
+        };
+
+        // functions the driver does not have stay unloaded, the direct state access of OpenGL 4.5 is needed
+        gl::load_with(|symbol| load_function(&symbol));
+        if !gl::NamedFramebufferDrawBuffers::is_loaded() {
+            return Err(HeadlessContext3DError::Function("glNamedFramebufferDrawBuffers"));
+        }
+        Ok(context)
+    }
+}
+
+impl Drop for HeadlessContext3D {
+    fn drop(&mut self) {
+
+        // the display is shared by the contexts of all worker threads, so it is not terminated here
+        unsafe { eglMakeCurrent(self.display,ptr::null_mut(),ptr::null_mut(),ptr::null_mut()); }
+        if !self.surface.is_null() {
+            unsafe { eglDestroySurface(self.display,self.surface); }
+        }
+        unsafe { eglDestroyContext(self.display,self.context); }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extensions() {
+        let extensions = "EGL_EXT_platform_base EGL_KHR_surfaceless_context_extra EGL_MESA_platform_surfaceless";
+        assert!(extension_listed(extensions,"EGL_EXT_platform_base"));
+        assert!(extension_listed(extensions,"EGL_MESA_platform_surfaceless"));
+        assert!(!extension_listed(extensions,"EGL_KHR_surfaceless_context"));
+        assert!(!extension_listed("","EGL_EXT_platform_base"));
+    }
+
+    #[test]
+    fn error_names_function() {
+        assert_eq!(format!("{:?}",HeadlessContext3DError::Function("glNamedFramebufferDrawBuffers")),"EGL: unable to load glNamedFramebufferDrawBuffers");
+    }
+}
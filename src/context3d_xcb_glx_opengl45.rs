@@ -21,6 +21,11 @@ fn load_function(name: &str) -> *mut os::raw::c_void {
     pointer
 }
 
+// call once before creating contexts on several threads
+pub fn init_threads() {
+    unsafe { XInitThreads(); }
+}
+
 pub enum Context3DError {
     Connection,
     Driver,
@@ -2,59 +2,149 @@
 // Commedia
 // Face rendering
 
-use std::{fs,io,ffi};
-use std::io::prelude::*;
-
 use crate::math::*;
 
-use crate::opengl45::*;
+use crate::opengl45::VertexNormal;
+use crate::render::*;
+use crate::render_software::covers_sample;
 
 pub trait Render {
     fn render();
 }
 
-pub struct Skin {
-    skin: VertexIndexBuffer<VertexNormal,u16>,
-    full_shader: ShaderProgram,
-    spec_shader: ShaderProgram,
+// segmentation classes, rendered into the red channel of the specification (the green channel identifies the eye)
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum SegmentClass {
+    Background = 0,
+    Skin = 1,
+    Sclera = 2,
+    Iris = 3,
+    Pupil = 4,
 }
 
-impl Skin {
-    pub fn new() -> Skin {
-        let file = fs::File::open("face.obj").expect("Unable to open face.obj");
-        let reader = io::BufReader::new(file);
-        let mut vertices: Vec<VertexNormal> = Vec::new();
-        let mut indices: Vec<u16> = Vec::new();
-        for line in reader.lines() {
-            if let Ok(line) = line {
-                let mut part = line.split_whitespace();
-                if let Some(tag) = part.next() {
-                    match tag {
-                        "o" => { },
-                        "v" => {
-                            let x = part.next().unwrap().parse::<f32>().unwrap();
-                            let y = part.next().unwrap().parse::<f32>().unwrap();
-                            let z = part.next().unwrap().parse::<f32>().unwrap();
-                            vertices.push(VertexNormal {
-                                v: f32xyz::new(x,y,z),
-                                n: f32xyz::new(0.0,0.0,1.0),
-                            });
-                        },
-                        "f" => {
-                            let i0 = part.next().unwrap().parse::<u16>().unwrap() - 1;
-                            let i1 = part.next().unwrap().parse::<u16>().unwrap() - 1;
-                            let i2 = part.next().unwrap().parse::<u16>().unwrap() - 1;
-                            indices.push(i0);
-                            indices.push(i1);
-                            indices.push(i2);
-                        },
-                        _ => {
-                            println!("unknown tag: {}",tag);
+impl SegmentClass {
+    pub fn color(&self,eye: u8) -> f32rgb {
+        f32rgb { r: (*self as u8 as f32) / 255.0,g: (eye as f32) / 255.0,b: 0.0, }
+    }
+}
+
+pub const SPEC_LEFT_EYE: u8 = 1;
+pub const SPEC_RIGHT_EYE: u8 = 2;
+
+pub const SEGMENT_PALETTE: [u8; 15] = [
+    0,0,0,        // background
+    255,192,128,  // skin
+    255,255,255,  // sclera
+    0,128,255,    // iris
+    255,0,255,    // pupil
+];
+
+// named vertices of face.obj, used as facial landmarks (left is -x)
+pub const LANDMARKS: [(&str,usize); 15] = [
+    ("left_eye_outer",1101),
+    ("left_eye_inner",1089),
+    ("left_eye_top",1094),
+    ("left_eye_bottom",1108),
+    ("right_eye_inner",1081),
+    ("right_eye_outer",1069),
+    ("right_eye_top",1076),
+    ("right_eye_bottom",1062),
+    ("nose_bridge",36),
+    ("nose_tip",8),
+    ("mouth_left",393),
+    ("mouth_right",823),
+    ("upper_lip",21),
+    ("lower_lip",28),
+    ("chin",1047),
+];
+
+const SKIN_MATERIAL: Material = Material { color: f32rgb { r: 1.0,g: 1.0,b: 1.0, },specular: 0.4,shininess: 16.0, };
+const SCLERA_MATERIAL: Material = Material { color: f32rgb { r: 1.0,g: 1.0,b: 1.0, },specular: 0.0,shininess: 64.0, };
+const IRIS_MATERIAL: Material = Material { color: f32rgb { r: 1.0,g: 1.0,b: 1.0, },specular: 0.0,shininess: 1.0, };
+const PUPIL_MATERIAL: Material = Material { color: f32rgb { r: 0.0,g: 0.0,b: 0.0, },specular: 0.0,shininess: 1.0, };
+const CORNEA_MATERIAL: Material = Material { color: f32rgb { r: 0.0,g: 0.0,b: 0.0, },specular: 1.0,shininess: 64.0, };
+
+// the face mesh, built into the binary so it does not depend on the current directory
+const FACE_OBJ: &str = include_str!("../face.obj");
+
+// next field of an OBJ line
+fn obj_field<T: std::str::FromStr>(part: &mut std::str::SplitWhitespace,line: usize,what: &str) -> std::result::Result<T,String> {
+    let field = part.next().ok_or_else(|| format!("face.obj: line {}: {} missing",line,what))?;
+    field.parse::<T>().map_err(|_| format!("face.obj: line {}: {} expected, found '{}'",line,what,field))
+}
+
+// vertices and triangles of an OBJ file with only v and f lines
+fn parse_obj(text: &str) -> std::result::Result<(Vec<VertexNormal>,Vec<u16>),String> {
+    let mut vertices: Vec<VertexNormal> = Vec::new();
+    let mut indices: Vec<u16> = Vec::new();
+    for (i,line) in text.lines().enumerate() {
+        let mut part = line.split_whitespace();
+        if let Some(tag) = part.next() {
+            match tag {
+                "o" => { },
+                "v" => {
+                    let x = obj_field::<f32>(&mut part,i + 1,"x")?;
+                    let y = obj_field::<f32>(&mut part,i + 1,"y")?;
+                    let z = obj_field::<f32>(&mut part,i + 1,"z")?;
+                    vertices.push(VertexNormal {
+                        v: f32xyz::new(x,y,z),
+                        n: f32xyz::new(0.0,0.0,1.0),
+                    });
+                },
+                "f" => {
+                    for _ in 0..3 {
+                        let index = obj_field::<u16>(&mut part,i + 1,"vertex index")?;
+                        if index == 0 {
+                            return Err(format!("face.obj: line {}: vertex indices start at 1",i + 1));
                         }
+                        indices.push(index - 1);
                     }
+                },
+                _ => {
+                    println!("unknown tag: {}",tag);
                 }
             }
         }
+    }
+
+    // faces may come before their vertices, so the indices are checked at the end
+    if let Some(index) = indices.iter().find(|index| (**index as usize) >= vertices.len()) {
+        return Err(format!("face.obj: vertex index {} out of range, there are {} vertices",*index as usize + 1,vertices.len()));
+    }
+    Ok((vertices,indices))
+}
+
+// the triangles of a mesh, kept on the CPU to check coverage without rendering
+struct Outline {
+    positions: Vec<f32xyz>,
+    triangles: Vec<u16>,
+}
+
+impl Outline {
+    fn new(mesh: &Mesh) -> Outline {
+        Outline {
+            positions: mesh.vertices.iter().map(|vertex| vertex.v).collect(),
+            triangles: mesh.indices.clone(),
+        }
+    }
+
+    fn covers_sample(&self,matrix: f32m4x4,size: usizexy,offsets: &[f32xy]) -> bool {
+        covers_sample(&self.positions,&self.triangles,matrix,size,offsets)
+    }
+}
+
+pub struct Skin {
+    skin: usize,
+    landmarks: Vec<(&'static str,f32xyz)>,
+    outline: Outline,
+}
+
+impl Skin {
+    pub fn new(backend: &mut dyn RenderBackend) -> std::result::Result<Skin,String> {
+        let (mut vertices,indices) = parse_obj(FACE_OBJ)?;
+        if let Some((name,index)) = LANDMARKS.iter().find(|(_,index)| *index >= vertices.len()) {
+            return Err(format!("face.obj: landmark {} is vertex {}, there are {} vertices",name,index,vertices.len()));
+        }
         let mut normals: Vec<f32xyz> = Vec::new();
         for i in 0..indices.len() / 3 {
             let a = indices[i * 3];
@@ -78,114 +168,50 @@ impl Skin {
             }
             vertices[i].n = n / total;
         }
-        let vs = VertexShader::new(r#"
-            #version 420 core
-
-            uniform mat4 u_projection;
-            uniform mat4 u_modelview;
-            uniform mat3 u_normal;
-
-            layout(location = 0) in vec3 i_pos;
-            layout(location = 1) in vec3 i_normal;
-
-            out vec3 v_pos;
-            out vec3 v_normal;
-
-            void main() {
-                v_pos = (u_modelview * vec4(i_pos,1.0)).xyz;
-                v_normal = u_normal * i_normal;
-                gl_Position = u_projection * vec4(v_pos,1.0);
-            }
-        "#).expect("Unable to create vertex shader.");
-        let fs_full = FragmentShader::new(r#"
-            #version 420 core
-
-            uniform vec2 u_depth_map;
-            uniform vec4 u_ambient_color;
-            uniform vec4 u_skin_color;
-            uniform vec4 u_light_color;
-            uniform vec3 u_light_dir;
-
-            in vec3 v_pos;
-            in vec3 v_normal;
-
-            out vec4 o_frag;
-
-            void main(void)
-            {
-                vec3 n = normalize(v_normal);
-                vec3 pu = -v_pos;
-                vec3 pun = normalize(pu);
-                vec3 rn = reflect(-u_light_dir,n);
-                float s = max(pow(dot(rn,pun),16.0),0.0);
-                float d = clamp(dot(n,u_light_dir),0.0,1.0);
-                vec3 res = u_ambient_color.xyz * u_skin_color.xyz + d * u_light_color.xyz * u_skin_color.xyz + 0.4 * s * u_light_color.xyz;
-                float z = gl_FragCoord.z / gl_FragCoord.w;
-                float a = u_depth_map.y * z + u_depth_map.x;
-                o_frag = vec4(res,a);
-            }
-        "#).expect("Unable to create fragment shader.");
-        let fs_spec = FragmentShader::new(r#"
-            #version 420 core
-
-            uniform vec4 u_skin_color;
-
-            in vec3 v_pos;
-            in vec3 v_normal;
+        let mut landmarks: Vec<(&'static str,f32xyz)> = Vec::new();
+        for (name,index) in LANDMARKS.iter() {
+            landmarks.push((*name,vertices[*index].v));
+        }
+        let mesh = Mesh { vertices: vertices,indices: indices, };
+        Ok(Skin {
+            outline: Outline::new(&mesh),
+            skin: backend.create_mesh(mesh),
+            landmarks: landmarks,
+        })
+    }
 
-            out vec4 o_frag;
+    pub fn render_full(&self,backend: &mut dyn RenderBackend,projection_matrix: f32m4x4,modelview_matrix: f32m4x4,light_dir: f32xyz,light_color: f32rgb,ambient_color: f32rgb,skin_color: f32rgb,depth_map: f32xy) {
+        backend.draw(self.skin,projection_matrix,modelview_matrix,&Shading::Full {
+            material: Material { color: skin_color,..SKIN_MATERIAL },
+            light_dir: light_dir,
+            light_color: light_color,
+            ambient_color: ambient_color,
+            depth_map: depth_map,
+            additive: false,
+        });
+    }
 
-            void main(void)
-            {
-                o_frag = u_skin_color;
-            }
-        "#).expect("Unable to create fragment shader.");
-        Skin {
-            skin: VertexIndexBuffer::from(vertices,indices),
-            full_shader: ShaderProgram::new(&vs,None,&fs_full).expect("Unable to create skin shader program."),
-            spec_shader: ShaderProgram::new(&vs,None,&fs_spec).expect("Unable to create skin shader program."),
-        }
+    pub fn render_spec(&self,backend: &mut dyn RenderBackend,projection_matrix: f32m4x4,modelview_matrix: f32m4x4) {
+        backend.draw(self.skin,projection_matrix,modelview_matrix,&Shading::Spec(SegmentClass::Skin.color(0)));
     }
 
-    pub fn render_full(&self,projection_matrix: f32m4x4,modelview_matrix: f32m4x4,light_dir: f32xyz,light_color: f32rgb,ambient_color: f32rgb,skin_color: f32rgb,depth_map: f32xy) {
-        let normal_matrix = f32m3x3::normal_from(modelview_matrix);
-        unsafe {
-            self.full_shader.bind();
-            self.full_shader.set_uniform("u_projection",projection_matrix);
-            self.full_shader.set_uniform("u_modelview",modelview_matrix);
-            self.full_shader.set_uniform("u_normal",normal_matrix);
-            self.full_shader.set_uniform("u_ambient_color",ambient_color);
-            self.full_shader.set_uniform("u_skin_color",skin_color);
-            self.full_shader.set_uniform("u_light_color",light_color);
-            self.full_shader.set_uniform("u_light_dir",light_dir);
-            self.full_shader.set_uniform("u_depth_map",depth_map);
-            self.skin.bind();
-            gl::DrawElements(gl::TRIANGLES,self.skin.indices() as i32,gl::UNSIGNED_SHORT as u32,0 as *const ffi::c_void);
-        }        
+    pub fn landmarks(&self) -> &Vec<(&'static str,f32xyz)> {
+        &self.landmarks
     }
 
-    pub fn render_spec(&self,projection_matrix: f32m4x4,modelview_matrix: f32m4x4,skin_color: f32rgb) {
-        let normal_matrix = f32m3x3::normal_from(modelview_matrix);
-        unsafe {
-            self.spec_shader.bind();
-            self.spec_shader.set_uniform("u_projection",projection_matrix);
-            self.spec_shader.set_uniform("u_modelview",modelview_matrix);
-            self.spec_shader.set_uniform("u_normal",normal_matrix);
-            self.spec_shader.set_uniform("u_skin_color",skin_color);
-            self.skin.bind();
-            gl::DrawElements(gl::TRIANGLES,self.skin.indices() as i32,gl::UNSIGNED_SHORT as u32,0 as *const ffi::c_void);
-        }        
+    // true if the skin covers at least one sample of a render target of this size
+    pub fn covers_sample(&self,projection_matrix: f32m4x4,modelview_matrix: f32m4x4,size: usizexy,offsets: &[f32xy]) -> bool {
+        self.outline.covers_sample(projection_matrix * modelview_matrix,size,offsets)
     }
 }
 
 pub struct Sclera {
-    sclera: VertexIndexBuffer<VertexNormal,u16>,
-    full_shader: ShaderProgram,
-    spec_shader: ShaderProgram,
+    sclera: usize,
+    outline: Outline,
 }
 
 impl Sclera {
-    pub fn new() -> Sclera {
+    pub fn new(backend: &mut dyn RenderBackend) -> Sclera {
         let mut vertices: Vec<VertexNormal> = Vec::new();
         let mut indices: Vec<u16> = Vec::new();
         for i in 0..9 {
@@ -212,111 +238,36 @@ impl Sclera {
             indices.push(i * 32);
             indices.push(i * 32 + 32);
         }
-        let vs = VertexShader::new(r#"
-            #version 420 core
-
-            uniform mat4 u_projection;
-            uniform mat4 u_modelview;
-            uniform mat3 u_normal;
-
-            layout(location = 0) in vec3 i_pos;
-            layout(location = 1) in vec3 i_normal;
-
-            out vec3 v_pos;
-            out vec3 v_normal;
-
-            void main() {
-                v_pos = (u_modelview * vec4(i_pos,1.0)).xyz;
-                v_normal = u_normal * i_normal;
-                gl_Position = u_projection * vec4(v_pos,1.0);
-            }
-        "#).expect("Unable to create vertex shader.");
-        let fs_full = FragmentShader::new(r#"
-            #version 420 core
-
-            uniform vec4 u_ambient_color;
-            uniform vec4 u_sclera_color;
-            uniform vec4 u_light_color;
-            uniform vec3 u_light_dir;
-            uniform vec2 u_depth_map;
-
-            in vec3 v_pos;
-            in vec3 v_normal;
-
-            out vec4 o_frag;
-
-            void main(void)
-            {
-                vec3 n = normalize(v_normal);
-                vec3 pu = -v_pos;
-                vec3 pun = normalize(pu);
-                vec3 rn = reflect(-u_light_dir,n);
-                float s = max(pow(dot(rn,pun),64.0),0.0);
-                float d = clamp(dot(n,u_light_dir),0.0,1.0);
-                vec3 res = u_ambient_color.xyz * u_sclera_color.xyz + d * u_light_color.xyz * u_sclera_color.xyz;
-                float z = gl_FragCoord.z / gl_FragCoord.w;
-                float a = u_depth_map.y * z + u_depth_map.x;
-                o_frag = vec4(res,a);
-            }
-        "#).expect("Unable to create fragment shader.");
-        let fs_spec = FragmentShader::new(r#"
-            #version 420 core
-
-            in vec3 v_pos;
-            in vec3 v_normal;
-
-            out vec4 o_frag;
-
-            void main(void)
-            {
-                o_frag = vec4(1.0,1.0,1.0,1.0);
-            }
-        "#).expect("Unable to create fragment shader.");
+        let mesh = Mesh { vertices: vertices,indices: indices, };
         Sclera {
-            sclera: VertexIndexBuffer::from(vertices,indices),
-            full_shader: ShaderProgram::new(&vs,None,&fs_full).expect("Unable to create eye shader program."),
-            spec_shader: ShaderProgram::new(&vs,None,&fs_spec).expect("Unable to create eye shader program."),
+            outline: Outline::new(&mesh),
+            sclera: backend.create_mesh(mesh),
         }
     }
 
-    pub fn render_full(&self,projection_matrix: f32m4x4,modelview_matrix: f32m4x4,light_dir: f32xyz,light_color: f32rgb,ambient_color: f32rgb,sclera_color: f32rgb,depth_map: f32xy) {
-        let normal_matrix = f32m3x3::normal_from(modelview_matrix);
-        unsafe {
-            self.full_shader.bind();
-            self.full_shader.set_uniform("u_projection",projection_matrix);
-            self.full_shader.set_uniform("u_modelview",modelview_matrix);
-            self.full_shader.set_uniform("u_normal",normal_matrix);
-            self.full_shader.set_uniform("u_ambient_color",ambient_color);
-            self.full_shader.set_uniform("u_sclera_color",sclera_color);
-            self.full_shader.set_uniform("u_light_color",light_color);
-            self.full_shader.set_uniform("u_light_dir",light_dir);
-            self.full_shader.set_uniform("u_depth_map",depth_map);
-            self.sclera.bind();
-            gl::DrawElements(gl::TRIANGLES,self.sclera.indices() as i32,gl::UNSIGNED_SHORT as u32,0 as *const ffi::c_void);
-        }        
+    pub fn render_full(&self,backend: &mut dyn RenderBackend,projection_matrix: f32m4x4,modelview_matrix: f32m4x4,light_dir: f32xyz,light_color: f32rgb,ambient_color: f32rgb,sclera_color: f32rgb,depth_map: f32xy) {
+        backend.draw(self.sclera,projection_matrix,modelview_matrix,&Shading::Full {
+            material: Material { color: sclera_color,..SCLERA_MATERIAL },
+            light_dir: light_dir,
+            light_color: light_color,
+            ambient_color: ambient_color,
+            depth_map: depth_map,
+            additive: false,
+        });
     }
 
-    pub fn render_spec(&self,projection_matrix: f32m4x4,modelview_matrix: f32m4x4) {
-        let normal_matrix = f32m3x3::normal_from(modelview_matrix);
-        unsafe {
-            self.spec_shader.bind();
-            self.spec_shader.set_uniform("u_projection",projection_matrix);
-            self.spec_shader.set_uniform("u_modelview",modelview_matrix);
-            self.spec_shader.set_uniform("u_normal",normal_matrix);
-            self.sclera.bind();
-            gl::DrawElements(gl::TRIANGLES,self.sclera.indices() as i32,gl::UNSIGNED_SHORT as u32,0 as *const ffi::c_void);
-        }        
+    pub fn render_spec(&self,backend: &mut dyn RenderBackend,projection_matrix: f32m4x4,modelview_matrix: f32m4x4,eye: u8) {
+        backend.draw(self.sclera,projection_matrix,modelview_matrix,&Shading::Spec(SegmentClass::Sclera.color(eye)));
     }
 }
 
 pub struct Iris {
-    iris: VertexIndexBuffer<VertexNormal,u16>,
-    full_shader: ShaderProgram,
-    spec_shader: ShaderProgram,
+    iris: usize,
+    outline: Outline,
 }
 
 impl Iris {
-    pub fn new() -> Iris {
+    pub fn new(backend: &mut dyn RenderBackend) -> Iris {
         let mut vertices: Vec<VertexNormal> = Vec::new();
         let mut indices: Vec<u16> = Vec::new();
         let opening: f32 = TAU / 12.0;
@@ -344,108 +295,38 @@ impl Iris {
         indices.push(63);
         indices.push(0);
         indices.push(1);
-        let vs = VertexShader::new(r#"
-            #version 420 core
-
-            uniform mat4 u_projection;
-            uniform mat4 u_modelview;
-            uniform mat3 u_normal;
-
-            layout(location = 0) in vec3 i_pos;
-            layout(location = 1) in vec3 i_normal;
-
-            out vec3 v_pos;
-            out vec3 v_normal;
-
-            void main() {
-                v_pos = (u_modelview * vec4(i_pos,1.0)).xyz;
-                v_normal = u_normal * i_normal;
-                gl_Position = u_projection * vec4(v_pos,1.0);
-            }
-        "#).expect("Unable to create vertex shader.");
-        let fs_full = FragmentShader::new(r#"
-            #version 420 core
-
-            uniform vec4 u_ambient_color;
-            uniform vec4 u_iris_color;
-            uniform vec4 u_light_color;
-            uniform vec3 u_light_dir;
-            uniform vec2 u_depth_map;
-
-            in vec3 v_pos;
-            in vec3 v_normal;
-
-            out vec4 o_frag;
-
-            void main(void)
-            {
-                vec3 n = normalize(v_normal);
-                float d = clamp(dot(n,u_light_dir),0.0,1.0);
-                vec3 res = u_ambient_color.xyz * u_iris_color.xyz + d * u_light_color.xyz * u_iris_color.xyz;
-                float z = gl_FragCoord.z / gl_FragCoord.w;
-                float a = u_depth_map.y * z + u_depth_map.x;
-                o_frag = vec4(res,a);
-            }
-        "#).expect("Unable to create fragment shader.");
-        let fs_spec = FragmentShader::new(r#"
-            #version 420 core
-
-            in vec3 v_pos;
-            in vec3 v_normal;
-
-            out vec4 o_frag;
-
-            void main(void)
-            {
-                o_frag = vec4(1.0,1.0,1.0,1.0);
-            }
-        "#).expect("Unable to create fragment shader.");
+        let mesh = Mesh { vertices: vertices,indices: indices, };
         Iris {
-            iris: VertexIndexBuffer::from(vertices,indices),
-            full_shader: ShaderProgram::new(&vs,None,&fs_full).expect("Unable to create eye shader program."),
-            spec_shader: ShaderProgram::new(&vs,None,&fs_spec).expect("Unable to create eye shader program."),
+            outline: Outline::new(&mesh),
+            iris: backend.create_mesh(mesh),
         }
     }
 
-    pub fn render_full(&self,projection_matrix: f32m4x4,modelview_matrix: f32m4x4,light_dir: f32xyz,light_color: f32rgb,ambient_color: f32rgb,iris_color: f32rgb,depth_map: f32xy) {
-        let normal_matrix = f32m3x3::normal_from(modelview_matrix);
-        unsafe {
-            self.full_shader.bind();
-            self.full_shader.set_uniform("u_projection",projection_matrix);
-            self.full_shader.set_uniform("u_modelview",modelview_matrix);
-            self.full_shader.set_uniform("u_normal",normal_matrix);
-            self.full_shader.set_uniform("u_ambient_color",ambient_color);
-            self.full_shader.set_uniform("u_iris_color",iris_color);
-            self.full_shader.set_uniform("u_light_color",light_color);
-            self.full_shader.set_uniform("u_light_dir",light_dir);
-            self.full_shader.set_uniform("u_depth_map",depth_map);
-            self.iris.bind();
-            gl::DrawElements(gl::TRIANGLES,self.iris.indices() as i32,gl::UNSIGNED_SHORT as u32,0 as *const ffi::c_void);
-        }        
+    pub fn render_full(&self,backend: &mut dyn RenderBackend,projection_matrix: f32m4x4,modelview_matrix: f32m4x4,light_dir: f32xyz,light_color: f32rgb,ambient_color: f32rgb,iris_color: f32rgb,depth_map: f32xy) {
+        backend.draw(self.iris,projection_matrix,modelview_matrix,&Shading::Full {
+            material: Material { color: iris_color,..IRIS_MATERIAL },
+            light_dir: light_dir,
+            light_color: light_color,
+            ambient_color: ambient_color,
+            depth_map: depth_map,
+            additive: false,
+        });
     }
 
-    pub fn render_spec(&self,projection_matrix: f32m4x4,modelview_matrix: f32m4x4) {
-        let normal_matrix = f32m3x3::normal_from(modelview_matrix);
-        unsafe {
-            self.spec_shader.bind();
-            self.spec_shader.set_uniform("u_projection",projection_matrix);
-            self.spec_shader.set_uniform("u_modelview",modelview_matrix);
-            self.spec_shader.set_uniform("u_normal",normal_matrix);
-            self.iris.bind();
-            gl::DrawElements(gl::TRIANGLES,self.iris.indices() as i32,gl::UNSIGNED_SHORT as u32,0 as *const ffi::c_void);
-        }        
+    pub fn render_spec(&self,backend: &mut dyn RenderBackend,projection_matrix: f32m4x4,modelview_matrix: f32m4x4,eye: u8) {
+        backend.draw(self.iris,projection_matrix,modelview_matrix,&Shading::Spec(SegmentClass::Iris.color(eye)));
     }
 }
 
 pub struct Pupil {
-    pupil: VertexBuffer<f32xyz>,
-    full_shader: ShaderProgram,
-    spec_shader: ShaderProgram,
+    pupil: usize,
+    outline: Outline,
 }
 
 impl Pupil {
-    pub fn new() -> Pupil {
-        let mut vertices: Vec<f32xyz> = Vec::new();
+    pub fn new(backend: &mut dyn RenderBackend) -> Pupil {
+        let mut vertices: Vec<VertexNormal> = Vec::new();
+        let mut indices: Vec<u16> = Vec::new();
         let opening: f32 = TAU / 12.0;
         let dist: f32 = opening.cos();
         let outer: f32 = opening.sin();
@@ -453,87 +334,43 @@ impl Pupil {
         for k in 0..32 {
             let theta: f32 = ((k as f32) * TAU) / 32.0;
             let vi = f32xyz { x: inner * theta.cos(),y: inner * theta.sin(),z: dist, };
-            vertices.push(vi);
+            vertices.push(VertexNormal { v: vi,n: f32xyz { x: 0.0,y: 0.0,z: 1.0, }});
         }
-        let vs = VertexShader::new(r#"
-            #version 420 core
-
-            uniform mat4 u_projection;
-            uniform mat4 u_modelview;
-
-            layout(location = 0) in vec3 i_pos;
-
-            out vec3 v_pos;
-
-            void main() {
-                v_pos = (u_modelview * vec4(i_pos,1.0)).xyz;
-                gl_Position = u_projection * vec4(v_pos,1.0);
-            }
-        "#).expect("Unable to create vertex shader.");
-        let fs_full = FragmentShader::new(r#"
-            #version 420 core
-
-            uniform vec2 u_depth_map;
-
-            in vec3 v_pos;
-
-            out vec4 o_frag;
-
-            void main(void)
-            {
-                float z = gl_FragCoord.z / gl_FragCoord.w;
-                float a = u_depth_map.y * z + u_depth_map.x;
-                o_frag = vec4(0.0,0.0,0.0,a);
-            }
-        "#).expect("Unable to create fragment shader.");
-        let fs_spec = FragmentShader::new(r#"
-            #version 420 core
-
-            in vec3 v_pos;
-
-            out vec4 o_frag;
-
-            void main(void)
-            {
-                o_frag = vec4(1.0,1.0,1.0,1.0);
-            }
-        "#).expect("Unable to create fragment shader.");
+        for k in 1..31 {
+            indices.push(0);
+            indices.push(k);
+            indices.push(k + 1);
+        }
+        let mesh = Mesh { vertices: vertices,indices: indices, };
         Pupil {
-            pupil: VertexBuffer::from(vertices),
-            full_shader: ShaderProgram::new(&vs,None,&fs_full).expect("Unable to create eye shader program."),
-            spec_shader: ShaderProgram::new(&vs,None,&fs_spec).expect("Unable to create eye shader program."),
+            outline: Outline::new(&mesh),
+            pupil: backend.create_mesh(mesh),
         }
     }
 
-    pub fn render_full(&self,projection_matrix: f32m4x4,modelview_matrix: f32m4x4,depth_map: f32xy) {
-        unsafe {
-            self.full_shader.bind();
-            self.full_shader.set_uniform("u_projection",projection_matrix);
-            self.full_shader.set_uniform("u_modelview",modelview_matrix);
-            self.full_shader.set_uniform("u_depth_map",depth_map);
-            self.pupil.bind();
-            gl::DrawArrays(gl::TRIANGLE_FAN,0,self.pupil.vertices() as i32);
-        }        
+    pub fn render_full(&self,backend: &mut dyn RenderBackend,projection_matrix: f32m4x4,modelview_matrix: f32m4x4,depth_map: f32xy) {
+        backend.draw(self.pupil,projection_matrix,modelview_matrix,&Shading::Full {
+            material: PUPIL_MATERIAL,
+            light_dir: f32xyz { x: 0.0,y: 0.0,z: 1.0, },
+            light_color: f32rgb { r: 0.0,g: 0.0,b: 0.0, },
+            ambient_color: f32rgb { r: 0.0,g: 0.0,b: 0.0, },
+            depth_map: depth_map,
+            additive: false,
+        });
     }
 
-    pub fn render_spec(&self,projection_matrix: f32m4x4,modelview_matrix: f32m4x4) {
-        unsafe {
-            self.spec_shader.bind();
-            self.spec_shader.set_uniform("u_projection",projection_matrix);
-            self.spec_shader.set_uniform("u_modelview",modelview_matrix);
-            self.pupil.bind();
-            gl::DrawArrays(gl::TRIANGLE_FAN,0,self.pupil.vertices() as i32);
-        }        
+    pub fn render_spec(&self,backend: &mut dyn RenderBackend,projection_matrix: f32m4x4,modelview_matrix: f32m4x4,eye: u8) {
+        backend.draw(self.pupil,projection_matrix,modelview_matrix,&Shading::Spec(SegmentClass::Pupil.color(eye)));
     }
 }
 
 pub struct Cornea {
-    cornea: VertexIndexBuffer<VertexNormal,u16>,
-    shader: ShaderProgram,
+    cornea: usize,
+    outline: Outline,
 }
 
 impl Cornea {
-    pub fn new() -> Cornea {
+    pub fn new(backend: &mut dyn RenderBackend) -> Cornea {
         let mut vertices: Vec<VertexNormal> = Vec::new();
         let mut indices: Vec<u16> = Vec::new();
         let border: f32 = TAU / 12.0;
@@ -573,69 +410,22 @@ impl Cornea {
         indices.push(63);
         indices.push(1);
         indices.push(64);
-        let vs = VertexShader::new(r#"
-            #version 420 core
-
-            uniform mat4 u_projection;
-            uniform mat4 u_modelview;
-            uniform mat3 u_normal;
-
-            layout(location = 0) in vec3 i_pos;
-            layout(location = 1) in vec3 i_normal;
-
-            out vec3 v_pos;
-            out vec3 v_normal;
-
-            void main() {
-                v_pos = (u_modelview * vec4(i_pos,1.0)).xyz;
-                v_normal = u_normal * i_normal;
-                gl_Position = u_projection * vec4(v_pos,1.0);
-            }
-        "#).expect("Unable to create vertex shader.");
-        let fs = FragmentShader::new(r#"
-            #version 420 core
-
-            uniform vec4 u_light_color;
-            uniform vec3 u_light_dir;
-            uniform vec2 u_depth_map;
-
-            in vec3 v_pos;
-            in vec3 v_normal;
-
-            out vec4 o_frag;
-
-            void main(void)
-            {
-                vec3 n = normalize(v_normal);
-                vec3 pu = -v_pos;
-                vec3 pun = normalize(pu);
-                vec3 rn = reflect(-u_light_dir,n);
-                float s = max(pow(dot(rn,pun),64.0),0.0);
-                vec3 res = s * u_light_color.xyz;
-                float z = gl_FragCoord.z / gl_FragCoord.w;
-                float a = u_depth_map.y * z + u_depth_map.x;
-                o_frag = vec4(res,a);
-            }
-        "#).expect("Unable to create fragment shader.");
+        let mesh = Mesh { vertices: vertices,indices: indices, };
         Cornea {
-            cornea: VertexIndexBuffer::from(vertices,indices),
-            shader: ShaderProgram::new(&vs,None,&fs).expect("Unable to create eye shader program."),
+            outline: Outline::new(&mesh),
+            cornea: backend.create_mesh(mesh),
         }
     }
 
-    pub fn render_full(&self,projection_matrix: f32m4x4,modelview_matrix: f32m4x4,light_dir: f32xyz,light_color: f32rgb,depth_map: f32xy) {
-        let normal_matrix = f32m3x3::normal_from(modelview_matrix);
-        unsafe {
-            self.shader.bind();
-            self.shader.set_uniform("u_projection",projection_matrix);
-            self.shader.set_uniform("u_modelview",modelview_matrix);
-            self.shader.set_uniform("u_normal",normal_matrix);
-            self.shader.set_uniform("u_light_color",light_color);
-            self.shader.set_uniform("u_light_dir",light_dir);
-            self.shader.set_uniform("u_depth_map",depth_map);
-            self.cornea.bind();
-            gl::DrawElements(gl::TRIANGLES,self.cornea.indices() as i32,gl::UNSIGNED_SHORT as u32,0 as *const ffi::c_void);
-        }        
+    pub fn render_full(&self,backend: &mut dyn RenderBackend,projection_matrix: f32m4x4,modelview_matrix: f32m4x4,light_dir: f32xyz,light_color: f32rgb,depth_map: f32xy) {
+        backend.draw(self.cornea,projection_matrix,modelview_matrix,&Shading::Full {
+            material: CORNEA_MATERIAL,
+            light_dir: light_dir,
+            light_color: light_color,
+            ambient_color: f32rgb { r: 0.0,g: 0.0,b: 0.0, },
+            depth_map: depth_map,
+            additive: true,
+        });
     }
 }
 
@@ -647,30 +437,63 @@ pub struct Eye {
 }
 
 impl Eye {
-    pub fn new() -> Eye {
+    pub fn new(backend: &mut dyn RenderBackend) -> Eye {
         Eye {
-            sclera: Sclera::new(),
-            iris: Iris::new(),
-            pupil: Pupil::new(),
-            cornea: Cornea::new(),
+            sclera: Sclera::new(backend),
+            iris: Iris::new(backend),
+            pupil: Pupil::new(backend),
+            cornea: Cornea::new(backend),
         }
     }
 
-    pub fn render_full(&self,projection_matrix: f32m4x4,modelview_matrix: f32m4x4,light_dir: f32xyz,light_color: f32rgb,ambient_color: f32rgb,sclera_color: f32rgb,iris_color: f32rgb,depth_map: f32xy) {
-        unsafe {
-            self.sclera.render_full(projection_matrix,modelview_matrix,light_dir,light_color,ambient_color,sclera_color,depth_map);
-            self.iris.render_full(projection_matrix,modelview_matrix,light_dir,light_color,ambient_color,iris_color,depth_map);
-            self.pupil.render_full(projection_matrix,modelview_matrix,depth_map);
-            gl::Enable(gl::BLEND);
-            gl::BlendFuncSeparate(gl::ONE,gl::ONE,gl::ONE,gl::ZERO);
-            self.cornea.render_full(projection_matrix,modelview_matrix,light_dir,light_color,depth_map);
-            gl::Disable(gl::BLEND);
-        }
+    pub fn render_full(&self,backend: &mut dyn RenderBackend,projection_matrix: f32m4x4,modelview_matrix: f32m4x4,light_dir: f32xyz,light_color: f32rgb,ambient_color: f32rgb,sclera_color: f32rgb,iris_color: f32rgb,depth_map: f32xy) {
+        self.sclera.render_full(backend,projection_matrix,modelview_matrix,light_dir,light_color,ambient_color,sclera_color,depth_map);
+        self.iris.render_full(backend,projection_matrix,modelview_matrix,light_dir,light_color,ambient_color,iris_color,depth_map);
+        self.pupil.render_full(backend,projection_matrix,modelview_matrix,depth_map);
+        self.cornea.render_full(backend,projection_matrix,modelview_matrix,light_dir,light_color,depth_map);
     }
 
-    pub fn render_spec(&self,projection_matrix: f32m4x4,modelview_matrix: f32m4x4) {
-        self.sclera.render_spec(projection_matrix,modelview_matrix);
-        self.iris.render_spec(projection_matrix,modelview_matrix);
-        self.pupil.render_spec(projection_matrix,modelview_matrix);
+    // true if any part of the eye covers at least one sample of a render target of this size
+    pub fn covers_sample(&self,projection_matrix: f32m4x4,modelview_matrix: f32m4x4,size: usizexy,offsets: &[f32xy]) -> bool {
+        let matrix = projection_matrix * modelview_matrix;
+        self.sclera.outline.covers_sample(matrix,size,offsets) || self.iris.outline.covers_sample(matrix,size,offsets) || self.pupil.outline.covers_sample(matrix,size,offsets) || self.cornea.outline.covers_sample(matrix,size,offsets)
+    }
+
+    pub fn render_spec(&self,backend: &mut dyn RenderBackend,projection_matrix: f32m4x4,modelview_matrix: f32m4x4,eye: u8) {
+        self.sclera.render_spec(backend,projection_matrix,modelview_matrix,eye);
+        self.iris.render_spec(backend,projection_matrix,modelview_matrix,eye);
+        self.pupil.render_spec(backend,projection_matrix,modelview_matrix,eye);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn obj_parse() {
+        let (vertices,indices) = parse_obj("o face\nv 0 0 0\nv 1 0 0.5\n\nv 0 1 0\nf 1 2 3\n").unwrap();
+        assert_eq!(vertices.len(),3);
+        assert_eq!((vertices[1].v.x,vertices[1].v.y,vertices[1].v.z),(1.0,0.0,0.5));
+        assert_eq!(indices,vec![0,1,2]);
+
+        // the built-in mesh
+        let (vertices,indices) = parse_obj(FACE_OBJ).unwrap();
+        assert!(LANDMARKS.iter().all(|(_,index)| *index < vertices.len()));
+        assert_eq!(indices.len() % 3,0);
+    }
+
+    #[test]
+    fn obj_errors() {
+        for (text,error) in [
+            ("v 0 0\n","face.obj: line 1: z missing"),
+            ("v 0 0 0\nv 0 x 0\n","face.obj: line 2: y expected, found 'x'"),
+            ("v 0 0 0\nf 1 1\n","face.obj: line 2: vertex index missing"),
+            ("v 0 0 0\nf 1 1 -1\n","face.obj: line 2: vertex index expected, found '-1'"),
+            ("v 0 0 0\nf 0 1 1\n","face.obj: line 2: vertex indices start at 1"),
+            ("v 0 0 0\nf 1 1 2\n","face.obj: vertex index 2 out of range, there are 1 vertices"),
+        ].iter() {
+            assert_eq!(parse_obj(text).err().as_deref(),Some(*error),"{}",text);
+        }
     }
 }
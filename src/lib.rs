@@ -0,0 +1,1054 @@
+// (C) Copyright 2020, by Germans Media Technology & Services
+// Commedia
+// Library: render faces and their labels in-process
+
+use std::fs;
+use std::io::prelude::*;
+use std::collections::VecDeque;
+
+extern crate rand;
+use rand::Rng;
+use rand::SeedableRng;
+use rand::seq::SliceRandom;
+
+extern crate rand_chacha;
+use rand_chacha::ChaCha8Rng;
+
+extern crate rand_distr;
+use rand_distr::*;
+
+extern crate math;
+use math::*;
+
+extern crate image;
+use image::*;
+
+mod context3d_xcb_glx_opengl45;
+pub use context3d_xcb_glx_opengl45::*;
+
+mod context3d_egl_opengl45;
+pub use context3d_egl_opengl45::*;
+
+mod opengl45;
+pub use opengl45::*;
+
+mod face;
+pub use face::*;
+
+mod config;
+pub use config::*;
+
+mod tfrecord;
+pub use tfrecord::*;
+
+mod png_encoder;
+pub use png_encoder::*;
+
+mod render;
+pub use render::*;
+
+mod render_opengl45;
+pub use render_opengl45::*;
+
+mod render_software;
+pub use render_software::*;
+
+#[cfg(feature = "python")]
+mod python;
+
+#[derive(Clone,Copy)]
+pub enum ContextBackend {
+    GLX,  // hidden window on an X display
+    EGL,  // headless, without X display
+    Software,  // on the CPU, without OpenGL
+}
+
+pub struct Context {
+    pub backend: Box<dyn RenderBackend>,
+    pub skin: Skin,
+    pub eye: Eye,
+}
+
+impl Context {
+    pub fn new(backend: ContextBackend,size: usizexy,antialias: Antialias,layers: usize,float_depth: bool) -> std::result::Result<Context,String> {
+        let mut backend: Box<dyn RenderBackend> = match backend {
+            ContextBackend::GLX => {
+                let ctx = Context3D::new().map_err(|error| format!("Unable to create 3D context: {:?}",error))?;
+                Box::new(OpenGLBackend::new(PlatformContext::GLX(ctx),size,antialias,layers,float_depth)?)
+            },
+            ContextBackend::EGL => {
+                let ctx = HeadlessContext3D::new().map_err(|error| format!("Unable to create headless 3D context: {:?}",error))?;
+                Box::new(OpenGLBackend::new(PlatformContext::EGL(ctx),size,antialias,layers,float_depth)?)
+            },
+            ContextBackend::Software => Box::new(SoftwareBackend::new(size,antialias,layers)),
+        };
+        let skin = Skin::new(&mut *backend)?;
+        let eye = Eye::new(&mut *backend);
+        Ok(Context {
+            backend: backend,
+            skin: skin,
+            eye: eye,
+        })
+    }
+
+    // with enough layers for a batch of the session, and the linear depth map if needed
+    pub fn for_session(backend: ContextBackend,session: &Session) -> std::result::Result<Context,String> {
+        let float_depth = !matches!(session.depth,SessionDepth::None);
+        let passes = if spec_needed(session) { 2 } else { 1 };
+        Context::new(backend,session.size,session.antialias,session.batch * passes,float_depth)
+    }
+}
+
+fn crop_upside_down(source: &Image<ARGB8>,r: usizer) -> Image<ARGB8> {
+    let mut image = Image::<ARGB8>::new(r.s);
+    for y in 0..r.s.y {
+        for x in 0..r.s.x {
+            *image.pixel_mut(usizexy { x: x,y: r.s.y - y - 1, }) = *source.pixel(r.o + usizexy { x: x,y: y, });
+        }
+    }
+    image
+}
+
+#[derive(Clone,Copy)]
+pub enum InstanceBackground {
+    Color(f32rgb),
+    Image(usize,usizexy),  // index of the background image, and position of the crop
+}
+
+#[derive(Clone)]
+pub struct Instance {
+    pub seed: u64,
+    pub head_pos: f32xyz,
+    pub head_dir: f32ypb,
+    pub lefteye: f32ypb,
+    pub righteye: f32ypb,
+    pub light_dir: f32ypb,
+    pub light_color: f32rgb,
+    pub background: InstanceBackground,
+    pub ambient_color: f32rgb,
+    pub skin_color: f32rgb,
+    pub sclera_color: f32rgb,
+    pub iris_color: f32rgb,
+}
+
+#[derive(Clone,Copy)]
+pub struct InstanceMotion {
+    pub head_vel: f32xyz,
+    pub head_angvel: f32ypb,
+    pub lefteye_angvel: f32ypb,
+    pub righteye_angvel: f32ypb,
+    pub light_angvel: f32ypb,
+}
+
+fn ypb_rate(from: f32ypb,to: f32ypb,duration: f32) -> f32ypb {
+    f32ypb {
+        y: (to.y - from.y) / duration,
+        p: (to.p - from.p) / duration,
+        b: (to.b - from.b) / duration,
+    }
+}
+
+fn ypb_integrate(dir: f32ypb,angvel: f32ypb,dt: f32) -> f32ypb {
+    f32ypb {
+        y: dir.y + angvel.y * dt,
+        p: dir.p + angvel.p * dt,
+        b: dir.b + angvel.b * dt,
+    }
+}
+
+impl Instance {
+    pub fn advance(&mut self,motion: &InstanceMotion,dt: f32) {
+        self.head_pos = self.head_pos + motion.head_vel * dt;
+        self.head_dir = ypb_integrate(self.head_dir,motion.head_angvel,dt);
+        self.lefteye = ypb_integrate(self.lefteye,motion.lefteye_angvel,dt);
+        self.righteye = ypb_integrate(self.righteye,motion.righteye_angvel,dt);
+        self.light_dir = ypb_integrate(self.light_dir,motion.light_angvel,dt);
+    }
+
+    pub fn head_matrix(&self) -> f32m4x4 {
+        f32m4x4::translate(self.head_pos) * f32m4x4::yaw(self.head_dir.y) * f32m4x4::pitch(self.head_dir.p)
+    }
+
+    pub fn lefteye_matrix(&self) -> f32m4x4 {
+        f32m4x4::translate(LEFT_EYE_POS) * f32m4x4::yaw(self.lefteye.y) * f32m4x4::pitch(self.lefteye.p) * f32m4x4::scale(EYE_SIZE)
+    }
+
+    pub fn righteye_matrix(&self) -> f32m4x4 {
+        f32m4x4::translate(RIGHT_EYE_POS) * f32m4x4::yaw(self.righteye.y) * f32m4x4::pitch(self.righteye.p) * f32m4x4::scale(EYE_SIZE)
+    }
+}
+
+pub struct Landmark {
+    pub name: &'static str,
+    pub screen: f32xy,
+    pub depth: f32,
+    pub visible: bool,
+}
+
+pub struct EyeLabel {
+    pub center: f32xyz,  // eye center in camera space
+    pub gaze: f32xyz,  // unit gaze direction in camera space
+    pub pupil: f32xy,  // pupil center in screen coordinates
+}
+
+// pixel-accurate bounding boxes in screen coordinates, None if not visible
+pub struct BoundingBoxes {
+    pub face: Option<usizer>,
+    pub lefteye: Option<usizer>,
+    pub righteye: Option<usizer>,
+}
+
+// per-image ground truth, next to the instance parameters
+pub struct Labels {
+    pub num: usize,  // instance number
+    pub frame: usize,  // frame number, 0 for still styles
+    pub instance: Instance,  // parameters of this image or frame
+    pub motion: Option<InstanceMotion>,  // velocities, for moving styles
+    pub lefteye: EyeLabel,
+    pub righteye: EyeLabel,
+    pub depth_range: Option<f32xy>,
+    pub landmarks: Vec<Landmark>,
+    pub boxes: Option<BoundingBoxes>,
+    pub depth_map: Option<Vec<f32>>,  // linear depth per pixel, if the session has depth
+    pub mask: Option<Vec<u8>>,  // segmentation class per pixel, if the session has mask
+    pub depth16: Option<Vec<u16>>,  // depth from the alpha channel at 16 bits, for png16
+}
+
+const LEFT_EYE_POS: f32xyz = f32xyz { x: -0.031,y: 0.026,z: 0.023, };
+const RIGHT_EYE_POS: f32xyz = f32xyz { x: 0.031,y: 0.026,z: 0.023, };
+const EYE_SIZE: f32xyz = f32xyz { x: 0.0115,y: 0.0115,z: 0.0115, };
+
+// draw the final image into the selected layer
+fn draw_full(ctx: &mut Context,session: &Session,backgrounds: &Vec<Image<ARGB8>>,instance: &Instance) {
+
+    // prepare matrices
+    let light_matrix = f32m3x3::yaw(instance.light_dir.y) * f32m3x3::pitch(instance.light_dir.p) * f32m3x3::roll(instance.light_dir.b);
+    let light_dir = light_matrix * f32xyz::new(0.0,1.0,0.0);
+    let head_matrix = instance.head_matrix();
+    let lefteye_matrix = instance.lefteye_matrix();
+    let righteye_matrix = instance.righteye_matrix();
+    let depth_map = match session.style {
+        SessionStyle::Still => {
+            f32xy { x: 1.0,y: 0.0, }
+        },
+        SessionStyle::StillDepth(scale,offset) => {
+            f32xy { x: offset,y: scale, }
+        },
+        SessionStyle::Moving => {
+            f32xy { x: 1.0,y: 0.0, }
+        },
+        SessionStyle::MovingDepth(scale,offset) => {
+            f32xy { x: offset,y: scale, }
+        },
+    };
+
+    // clear or draw background
+    match &instance.background {
+        InstanceBackground::Color(color) => ctx.backend.clear(*color),
+        InstanceBackground::Image(index,pos) => {
+            let image = crop_upside_down(&backgrounds[*index],usizer { o: *pos,s: ctx.backend.size(), });
+            ctx.backend.clear_image(&image);
+        },
+    }
+
+    // draw eyes and face
+    ctx.eye.render_full(&mut *ctx.backend,session.projection,head_matrix * lefteye_matrix,light_dir,instance.light_color,instance.ambient_color,instance.sclera_color,instance.iris_color,depth_map);
+    ctx.eye.render_full(&mut *ctx.backend,session.projection,head_matrix * righteye_matrix,light_dir,instance.light_color,instance.ambient_color,instance.sclera_color,instance.iris_color,depth_map);
+    ctx.skin.render_full(&mut *ctx.backend,session.projection,head_matrix,light_dir,instance.light_color,instance.ambient_color,instance.skin_color,depth_map);
+
+    // and antialias down to the session size
+    ctx.backend.resolve(false);
+}
+
+pub fn render_full(ctx: &mut Context,session: &Session,backgrounds: &Vec<Image<ARGB8>>,instance: &Instance) -> Image<ARGB8> {
+    draw_full(ctx,session,backgrounds,instance);
+    ctx.backend.finish();
+    ctx.backend.grab()
+}
+
+// draw the segmentation specification into the selected layer
+fn draw_spec(ctx: &mut Context,session: &Session,instance: &Instance) {
+
+    // prepare matrices
+    let head_matrix = instance.head_matrix();
+    let lefteye_matrix = instance.lefteye_matrix();
+    let righteye_matrix = instance.righteye_matrix();
+
+    ctx.backend.clear(SegmentClass::Background.color(0));
+    ctx.eye.render_spec(&mut *ctx.backend,session.projection,head_matrix * lefteye_matrix,SPEC_LEFT_EYE);
+    ctx.eye.render_spec(&mut *ctx.backend,session.projection,head_matrix * righteye_matrix,SPEC_RIGHT_EYE);
+    ctx.skin.render_spec(&mut *ctx.backend,session.projection,head_matrix);
+    ctx.backend.resolve(true);
+}
+
+// masks, boxes and the depth range of moving_depth need the specification next to the final image
+fn spec_needed(session: &Session) -> bool {
+    let moving_depth = matches!(session.style,SessionStyle::MovingDepth(_,_));
+    session.mask || session.boxes || moving_depth
+}
+
+fn depth_range(alpha: &[u16],spec_image: &Image<ARGB8>,scale: f32,offset: f32) -> f32xy {
+
+    // decode the 16-bit alpha channel back to depth wherever the face or eyes are visible in the specification
+    let mut min = f32::MAX;
+    let mut max = f32::MIN;
+    for i in 0..spec_image.size.y {
+        for k in 0..spec_image.size.x {
+            let spec = *spec_image.pixel(usizexy { x: k,y: i });
+            if (spec.r != 0) || (spec.g != 0) || (spec.b != 0) {
+                let a = (alpha[i * spec_image.size.x + k] as f32) / 65535.0;
+                let z = (a - offset) / scale;
+                min = min.min(z);
+                max = max.max(z);
+            }
+        }
+    }
+
+    // face not visible in this frame
+    if min > max {
+        return f32xy { x: f32::NAN,y: f32::NAN, };
+    }
+    f32xy { x: min,y: max, }
+}
+
+fn bounding_boxes(spec_image: &Image<ARGB8>) -> BoundingBoxes {
+
+    // minimum and maximum pixel coordinates of face, left eye and right eye
+    let mut bounds = [(usize::MAX,usize::MAX,0usize,0usize); 3];
+    for i in 0..spec_image.size.y {
+        for k in 0..spec_image.size.x {
+            let pix = *spec_image.pixel(usizexy { x: k,y: i });
+            let part = if pix.r == SegmentClass::Background as u8 {
+                continue;
+            }
+            else if pix.g == SPEC_LEFT_EYE {
+                1
+            }
+            else if pix.g == SPEC_RIGHT_EYE {
+                2
+            }
+            else {
+                0
+            };
+            for index in [0,part].iter() {
+                let b = &mut bounds[*index];
+                b.0 = b.0.min(k);
+                b.1 = b.1.min(i);
+                b.2 = b.2.max(k);
+                b.3 = b.3.max(i);
+            }
+        }
+    }
+
+    // convert to top-down
+    let height = spec_image.size.y;
+    let to_box = |b: (usize,usize,usize,usize)| -> Option<usizer> {
+        if b.0 > b.2 {
+            return None;
+        }
+        let o = usizexy { x: b.0,y: height - 1 - b.3, };
+        let e = usizexy { x: b.2,y: height - 1 - b.1, };
+        Some(usizer { o: o,s: usizexy { x: e.x - o.x + 1,y: e.y - o.y + 1, }, })
+    };
+    BoundingBoxes {
+        face: to_box(bounds[0]),
+        lefteye: to_box(bounds[1]),
+        righteye: to_box(bounds[2]),
+    }
+}
+
+// head positions tried before an instance is given up on, so a session that never shows the face fails instead of hanging
+const HEAD_POS_ATTEMPTS: usize = 1000;
+
+// true if the face or an eye covers at least one sample of the render target, so at least 1 pixel of the image shows part of the face
+fn face_in_view(ctx: &Context,session: &Session,instance: &Instance,offsets: &[f32xy]) -> bool {
+    let size = ctx.backend.size();
+    let head_matrix = instance.head_matrix();
+    ctx.skin.covers_sample(session.projection,head_matrix,size,offsets) ||
+        ctx.eye.covers_sample(session.projection,head_matrix * instance.lefteye_matrix(),size,offsets) ||
+        ctx.eye.covers_sample(session.projection,head_matrix * instance.righteye_matrix(),size,offsets)
+}
+
+pub fn build_instance(rng: &mut ChaCha8Rng,ctx: &mut Context,session: &Session,backgrounds: &Vec<Image<ARGB8>>,seed: u64) -> std::result::Result<Instance,String> {
+
+    // build instance
+    let mut instance = Instance {
+        seed: seed,
+        head_pos: session.head_pos.instantiate(rng),
+        head_dir: session.head_dir.instantiate(rng),
+        lefteye: session.lefteye.instantiate(rng),
+        righteye: session.righteye.instantiate(rng),
+        light_dir: session.light_dir.instantiate(rng),
+        light_color: session.light_color.instantiate(rng),
+        background: match &session.background {
+            SessionBackground::Color(color) => InstanceBackground::Color(color.instantiate(rng)),
+            SessionBackground::Image(_path) => {
+                let target = ctx.backend.size();
+                let indices: Vec<usize> = (0..backgrounds.len()).filter(|index| (backgrounds[*index].size.x >= target.x) && (backgrounds[*index].size.y >= target.y)).collect();
+                let index = *indices.choose(rng).ok_or_else(|| format!("no background image of session {} is at least {}x{} pixels",session.name,target.x,target.y))?;
+                let cropspace = backgrounds[index].size - ctx.backend.size();
+                let pos = usizexy { x: (rng.gen::<f32>() * (cropspace.x as f32)) as usize,y: (rng.gen::<f32>() * (cropspace.y as f32)) as usize, };
+                InstanceBackground::Image(index,pos)
+            },
+        },
+        ambient_color: session.ambient_color.instantiate(rng),
+        skin_color: session.skin_color.instantiate(rng),
+        sclera_color: session.sclera_color.instantiate(rng),
+        iris_color: session.iris_color.instantiate(rng),
+    };
+
+    // make sure head_pos is inside the projection frustum, and at least 1 pixel of the face is visible
+    // checked on the CPU with the coverage rules of the software backend, so building an instance does not wait for the GPU, and every backend builds the same instances
+    let offsets = sample_offsets(session.antialias);
+    for _ in 0..HEAD_POS_ATTEMPTS {
+        instance.head_pos = session.head_pos.instantiate(rng);
+        let pos = session.projection * f32xyzw { x: instance.head_pos.x,y: instance.head_pos.y,z: instance.head_pos.z,w: 1.0, };
+        if (pos.x > -pos.w) && (pos.x < pos.w) && (pos.y > -pos.w) && (pos.y < pos.w) && face_in_view(ctx,session,&instance,&offsets) {
+            return Ok(instance);
+        }
+    }
+    Err(format!("none of {} sampled head positions shows the face of session {}, check head_pos and projection",HEAD_POS_ATTEMPTS,session.name))
+}
+
+pub fn build_motion(rng: &mut ChaCha8Rng,session: &Session,instance: &Instance) -> InstanceMotion {
+
+    // velocities are sampled from vel/angvel, if specified
+    // otherwise, the instance moves from its initial pose towards a second pose sampled from the same distributions, over the entire duration of the sequence
+    let duration = if session.frames > 1 { ((session.frames - 1) as f32) / session.fps } else { 1.0 };
+    InstanceMotion {
+        head_vel: match &session.head_vel {
+            Some(vel) => vel.instantiate(rng),
+            None => (session.head_pos.instantiate(rng) - instance.head_pos) / duration,
+        },
+        head_angvel: match &session.head_angvel {
+            Some(angvel) => angvel.instantiate(rng),
+            None => ypb_rate(instance.head_dir,session.head_dir.instantiate(rng),duration),
+        },
+        lefteye_angvel: match &session.lefteye_angvel {
+            Some(angvel) => angvel.instantiate(rng),
+            None => ypb_rate(instance.lefteye,session.lefteye.instantiate(rng),duration),
+        },
+        righteye_angvel: match &session.righteye_angvel {
+            Some(angvel) => angvel.instantiate(rng),
+            None => ypb_rate(instance.righteye,session.righteye.instantiate(rng),duration),
+        },
+        light_angvel: match &session.light_angvel {
+            Some(angvel) => angvel.instantiate(rng),
+            None => ypb_rate(instance.light_dir,session.light_dir.instantiate(rng),duration),
+        },
+    }
+}
+
+pub fn project_ndc(session: &Session,pos: f32xyz) -> f32xyz {
+    let hom = session.projection * f32xyzw { x: pos.x,y: pos.y,z: pos.z,w: 1.0, };
+    f32xyz {
+        x: hom.x / hom.w,
+        y: hom.y / hom.w,
+        z: hom.z / hom.w,
+    }
+}
+
+pub fn ndc_to_screen(session: &Session,ndc: f32xyz) -> f32xy {
+    f32xy {
+        x: 0.5 * (1.0 + ndc.x) * (session.size.x as f32),
+        y: 0.5 * (1.0 - ndc.y) * (session.size.y as f32),
+    }
+}
+
+fn eye_label(session: &Session,head_matrix: f32m4x4,eye_matrix: f32m4x4,pos: f32xyz) -> EyeLabel {
+    let matrix = head_matrix * eye_matrix;
+    let center = head_matrix * f32xyzw { x: pos.x,y: pos.y,z: pos.z,w: 1.0, };
+    let gaze = matrix * f32xyzw { x: 0.0,y: 0.0,z: 1.0,w: 0.0, };
+    let pupil = matrix * f32xyzw { x: 0.0,y: 0.0,z: (TAU / 12.0).cos(),w: 1.0, };  // the pupil is in the iris plane
+    EyeLabel {
+        center: f32xyz { x: center.x,y: center.y,z: center.z, },
+        gaze: f32xyz { x: gaze.x,y: gaze.y,z: gaze.z, }.norm(),
+        pupil: ndc_to_screen(session,project_ndc(session,f32xyz { x: pupil.x,y: pupil.y,z: pupil.z, })),
+    }
+}
+
+// maximum difference between landmark depth and depth buffer to still count as visible, in window depth units
+const LANDMARK_DEPTH_TOLERANCE: f32 = 0.0005;
+
+fn project_landmarks(ctx: &Context,session: &Session,instance: &Instance,depth: &Vec<f32>) -> Vec<Landmark> {
+
+    // against the nearest window depth of each resolved pixel
+    let head_matrix = instance.head_matrix();
+    let mut landmarks: Vec<Landmark> = Vec::new();
+    for (name,pos) in ctx.skin.landmarks() {
+        let eye = head_matrix * f32xyzw { x: pos.x,y: pos.y,z: pos.z,w: 1.0, };
+        let eye = f32xyz { x: eye.x,y: eye.y,z: eye.z, };
+        let ndc = project_ndc(session,eye);
+        let visible = if (ndc.x > -1.0) && (ndc.x < 1.0) && (ndc.y > -1.0) && (ndc.y < 1.0) {
+            let pixel = usizexy {
+                x: (0.5 * (1.0 + ndc.x) * (session.size.x as f32)) as usize,
+                y: (0.5 * (1.0 + ndc.y) * (session.size.y as f32)) as usize,
+            };
+            depth[pixel.y * session.size.x + pixel.x] >= 0.5 * (ndc.z + 1.0) - LANDMARK_DEPTH_TOLERANCE
+        }
+        else {
+            false
+        };
+        landmarks.push(Landmark {
+            name: *name,
+            screen: ndc_to_screen(session,ndc),
+            depth: -eye.z,
+            visible: visible,
+        });
+    }
+    landmarks
+}
+
+// an image or frame to render
+#[derive(Clone)]
+pub struct RenderJob {
+    pub num: usize,  // instance number
+    pub frame: usize,
+    pub instance: Instance,
+    pub motion: Option<InstanceMotion>,
+}
+
+// the image, or the frames, of an instance
+pub fn instance_jobs(rng: &mut ChaCha8Rng,session: &Session,num: usize,instance: Instance) -> Vec<RenderJob> {
+    match session.style {
+        SessionStyle::Still | SessionStyle::StillDepth(_,_) => {
+            vec![RenderJob {
+                num: num,
+                frame: 0,
+                instance: instance,
+                motion: None,
+            }]
+        },
+        SessionStyle::Moving | SessionStyle::MovingDepth(_,_) => {
+            let motion = build_motion(rng,session,&instance);
+            let dt = 1.0 / session.fps;
+            let mut instance = instance;
+            let mut jobs: Vec<RenderJob> = Vec::with_capacity(session.frames);
+            for frame in 0..session.frames {
+                jobs.push(RenderJob {
+                    num: num,
+                    frame: frame,
+                    instance: instance.clone(),
+                    motion: Some(motion),
+                });
+
+                // and move on to the next frame
+                instance.advance(&motion,dt);
+            }
+            jobs
+        },
+    }
+}
+
+// derive the image and labels of a job from its read back layers
+fn finish_sample(ctx: &Context,session: &Session,job: &RenderJob,full: Layer,spec: Option<Layer>) -> (Image<ARGB8>,Labels) {
+    let image = full.color;
+
+    // the resolve already averaged the linear depth
+    let depth_map = match session.depth {
+        SessionDepth::None => None,
+        _ => Some(full.depth_map),
+    };
+
+    // project landmarks against the window depth, if needed
+    let landmarks = if session.landmarks { project_landmarks(ctx,session,&job.instance,&full.depth) } else { Vec::new() };
+
+    // measure the depth range of the visible face pixels
+    let spec_image = spec.map(|layer| layer.color);
+    let range = if let SessionStyle::MovingDepth(scale,offset) = session.style {
+        Some(depth_range(&full.alpha,spec_image.as_ref().unwrap(),scale,offset))
+    }
+    else {
+        None
+    };
+
+    // segmentation mask, the resolve voted on the class of each pixel
+    let mask = if session.mask {
+        Some(spec_image.as_ref().unwrap().data.iter().map(|pix| pix.r).collect())
+    }
+    else {
+        None
+    };
+
+    // the resolved alpha has more precision than 8 bits
+    let depth16 = if let SessionFormat::PNG16 = session.format { Some(full.alpha) } else { None };
+
+    let instance = &job.instance;
+    let head_matrix = instance.head_matrix();
+    let labels = Labels {
+        num: job.num,
+        frame: job.frame,
+        instance: instance.clone(),
+        motion: job.motion,
+        lefteye: eye_label(session,head_matrix,instance.lefteye_matrix(),LEFT_EYE_POS),
+        righteye: eye_label(session,head_matrix,instance.righteye_matrix(),RIGHT_EYE_POS),
+        depth_range: range,
+        landmarks: landmarks,
+        boxes: if session.boxes { Some(bounding_boxes(spec_image.as_ref().unwrap())) } else { None },
+        depth_map: depth_map,
+        mask: mask,
+        depth16: depth16,
+    };
+    (image,labels)
+}
+
+fn finish_batch(ctx: &mut Context,session: &Session,jobs: &[RenderJob],samples: &mut Vec<(Image<ARGB8>,Labels)>) {
+    let mut layers = ctx.backend.finish_readback().into_iter();
+    for job in jobs {
+        let full = layers.next().expect("layer missing");
+        let spec = if spec_needed(session) { layers.next() } else { None };
+        samples.push(finish_sample(ctx,session,job,full,spec));
+    }
+}
+
+// render images or frames in batches that fill the layers of the context, and derive their labels
+pub fn render_samples(ctx: &mut Context,session: &Session,backgrounds: &Vec<Image<ARGB8>>,jobs: &[RenderJob]) -> Vec<(Image<ARGB8>,Labels)> {
+
+    // each job takes one layer, or two with specification
+    let spec = spec_needed(session);
+    let passes = if spec { 2 } else { 1 };
+    let batch = (ctx.backend.layers() / passes).max(1);
+
+    let mut samples: Vec<(Image<ARGB8>,Labels)> = Vec::with_capacity(jobs.len());
+    let mut pending: Option<&[RenderJob]> = None;
+    for chunk in jobs.chunks(batch) {
+
+        // draw the batch, without waiting for the GPU
+        for (i,job) in chunk.iter().enumerate() {
+            ctx.backend.select_layer(i * passes);
+            draw_full(ctx,session,backgrounds,&job.instance);
+            if spec {
+                ctx.backend.select_layer(i * passes + 1);
+                draw_spec(ctx,session,&job.instance);
+            }
+        }
+        ctx.backend.start_readback(chunk.len() * passes,session.landmarks);
+
+        // and finish the previous batch, while the GPU works on this one
+        if let Some(previous) = pending {
+            finish_batch(ctx,session,previous,&mut samples);
+        }
+        pending = Some(chunk);
+    }
+    if let Some(previous) = pending {
+        finish_batch(ctx,session,previous,&mut samples);
+    }
+    ctx.backend.select_layer(0);
+    samples
+}
+
+// derive the seed of an instance from the session seed, so each instance can be regenerated on its own
+pub fn instance_seed(session_seed: u64,num: usize) -> u64 {
+
+    // splitmix64 finalizer
+    let mut z = session_seed.wrapping_add((num as u64 + 1).wrapping_mul(0x9E3779B97F4A7C15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+}
+
+pub fn load_backgrounds(session: &Session) -> std::result::Result<Vec<Image<ARGB8>>,String> {
+    let mut backgrounds: Vec<Image<ARGB8>> = Vec::new();
+    if let SessionBackground::Image(path) = &session.background {
+        let mut entries: Vec<String> = Vec::new();
+        let dir = fs::read_dir(path).map_err(|error| format!("unable to read from backgrounds directory {}: {}",path,error))?;
+        for entry in dir {
+            let entry = entry.map_err(|error| format!("invalid entry in {}: {}",path,error))?;
+            let name = entry.file_name().into_string().map_err(|name| format!("unable to convert file name {:?} in {}",name,path))?;
+            entries.push(name);
+        }
+        entries.sort();  // directory order is not stable, and seeds should select the same background
+        for entry in entries {
+            let name = format!("{}{}",path,entry);
+            let mut file = fs::File::open(&name).map_err(|error| format!("cannot open {}: {}",name,error))?;
+            let mut buffer: Vec<u8> = Vec::new();
+            file.read_to_end(&mut buffer).map_err(|error| format!("unable to read {}: {}",name,error))?;
+            let image = decode(&buffer).map_err(|_| format!("unable to decode {}",name))?;
+            backgrounds.push(image);
+        }
+
+        // backgrounds are cropped at the size of the render target, smaller images are never selected
+        let target = session.antialias.scale() * session.size;
+        if backgrounds.is_empty() {
+            return Err(format!("background directory {} is empty",path));
+        }
+        if !backgrounds.iter().any(|image| (image.size.x >= target.x) && (image.size.y >= target.y)) {
+            return Err(format!("no image in background directory {} is at least {}x{} pixels, the size of the render target",path,target.x,target.y));
+        }
+    }
+    Ok(backgrounds)
+}
+
+// renders the instances of a session in-process, as an iterator over images and their labels
+pub struct Generator {
+    session: Session,
+    ctx: Context,
+    backgrounds: Vec<Image<ARGB8>>,
+    seed: u64,
+    num: usize,  // next instance to build
+    end: usize,
+    jobs: VecDeque<RenderJob>,  // built, but not rendered yet
+    samples: VecDeque<(Image<ARGB8>,Labels)>,  // rendered, but not returned yet
+    error: Option<String>,  // why the generator stopped before the end
+}
+
+impl Generator {
+    pub fn new(session: Session,backend: ContextBackend) -> std::result::Result<Generator,String> {
+        let ctx = Context::for_session(backend,&session)?;
+        let backgrounds = load_backgrounds(&session)?;
+        let seed = match session.seed {
+            Some(seed) => seed,
+            None => rand::thread_rng().gen::<u64>(),
+        };
+        let end = session.count;
+        Ok(Generator {
+            session: session,
+            ctx: ctx,
+            backgrounds: backgrounds,
+            seed: seed,
+            num: 0,
+            end: end,
+            jobs: VecDeque::new(),
+            samples: VecDeque::new(),
+            error: None,
+        })
+    }
+
+    // override the session seed, the same seed renders the same instances as the command line
+    pub fn with_seed(mut self,seed: u64) -> Generator {
+        self.seed = seed;
+        self
+    }
+
+    // only render the instances in this range, instead of 0..count
+    pub fn with_instances(mut self,instances: std::ops::Range<usize>) -> Generator {
+        self.num = instances.start;
+        self.end = instances.end;
+        self.jobs.clear();
+        self.samples.clear();
+        self
+    }
+
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    // set when an instance could not be built, the iterator ends there
+    pub fn error(&self) -> Option<&String> {
+        self.error.as_ref()
+    }
+}
+
+impl Iterator for Generator {
+    type Item = (Image<ARGB8>,Labels);
+
+    fn next(&mut self) -> Option<(Image<ARGB8>,Labels)> {
+        if self.samples.is_empty() {
+
+            // build instances for two batches, so one is read back while the other renders
+            let count = 2 * self.session.batch;
+            while (self.jobs.len() < count) && (self.num < self.end) && self.error.is_none() {
+                let seed = instance_seed(self.seed,self.num);
+                let mut rng = ChaCha8Rng::seed_from_u64(seed);
+                let instance = match build_instance(&mut rng,&mut self.ctx,&self.session,&self.backgrounds,seed) {
+                    Ok(instance) => instance,
+                    Err(error) => {
+                        self.error = Some(error);
+                        break;
+                    },
+                };
+                self.jobs.extend(instance_jobs(&mut rng,&self.session,self.num,instance));
+                self.num += 1;
+            }
+            let jobs: Vec<RenderJob> = self.jobs.drain(..count.min(self.jobs.len())).collect();
+            self.samples.extend(render_samples(&mut self.ctx,&self.session,&self.backgrounds,&jobs));
+        }
+        self.samples.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load_session(name: &str,yaml: &str) -> Session {
+        let file = std::env::temp_dir().join(format!("commedia_{}.yaml",name));
+        fs::write(&file,yaml).unwrap();
+        let sessions = load_config(file.to_str().unwrap(),&[]).unwrap();
+        fs::remove_file(&file).unwrap();
+        sessions.into_iter().next().unwrap()
+    }
+
+    fn sample_instance(session: &Session,seed: u64) -> Instance {
+        let mut ctx = Context::for_session(ContextBackend::Software,session).unwrap();
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+        build_instance(&mut rng,&mut ctx,session,&Vec::new(),seed).unwrap()
+    }
+
+    // the images or frames of one instance, rendered on the CPU
+    fn render_instance(session: &Session,seed: u64) -> Vec<(Image<ARGB8>,Labels)> {
+        let mut ctx = Context::for_session(ContextBackend::Software,session).unwrap();
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+        let instance = build_instance(&mut rng,&mut ctx,session,&Vec::new(),seed).unwrap();
+        let jobs = instance_jobs(&mut rng,session,0,instance);
+        render_samples(&mut ctx,session,&Vec::new(),&jobs)
+    }
+
+    #[test]
+    fn moving_frames() {
+
+        // without vel, the head moves from its initial position to a second one sampled from head.pos, and gets there on the last frame
+        let session = load_session("moving_frames","s:\n  style: moving\n  frames: 3\n  fps: 10\n  size: 32,24\n  antialias: none\n  head:\n    pos:\n      x: uniform -0.02,0.02\n      z: -0.5\n");
+        let instance = sample_instance(&session,1);
+        let mut rng = ChaCha8Rng::seed_from_u64(2);
+        let target = session.head_pos.instantiate(&mut rng.clone());
+        let jobs = instance_jobs(&mut rng,&session,7,instance.clone());
+        assert_eq!(jobs.len(),3);
+        for (frame,job) in jobs.iter().enumerate() {
+            assert_eq!((job.num,job.frame),(7,frame));
+            assert!(job.motion.is_some());
+            assert_eq!(job.instance.head_pos.z,-0.5);
+        }
+        assert_eq!(jobs[0].instance.head_pos.x,instance.head_pos.x);
+        assert!((jobs[2].instance.head_pos.x - target.x).abs() < 1e-6);
+
+        // a still session renders a single image without motion
+        let session = load_session("still_frames","s:\n  style: still\n  frames: 3\n  size: 32,24\n  antialias: none\n  head:\n    pos:\n      z: -0.5\n");
+        let jobs = instance_jobs(&mut rng,&session,7,instance);
+        assert_eq!(jobs.len(),1);
+        assert!((jobs[0].frame == 0) && jobs[0].motion.is_none());
+    }
+
+    #[test]
+    fn moving_velocities() {
+
+        // vel and angvel set the motion directly, and each frame advances by 1/fps
+        let session = load_session("moving_velocities","s:\n  style: moving\n  frames: 3\n  fps: 10\n  size: 32,24\n  antialias: none\n  head:\n    pos:\n      z: -0.5\n    vel:\n      x: 0.3\n  lefteye:\n    angvel:\n      y: 10\n");
+        let instance = sample_instance(&session,1);
+        let mut rng = ChaCha8Rng::seed_from_u64(2);
+        let jobs = instance_jobs(&mut rng,&session,0,instance.clone());
+        let motion = jobs[0].motion.unwrap();
+        assert_eq!((motion.head_vel.x,motion.head_vel.y,motion.head_vel.z),(0.3,0.0,0.0));
+        assert_eq!((motion.lefteye_angvel.y,motion.righteye_angvel.y),(10.0,0.0));
+        assert!((jobs[2].instance.head_pos.x - (instance.head_pos.x + 0.06)).abs() < 1e-6);
+        assert!((jobs[2].instance.lefteye.y - (instance.lefteye.y + 2.0)).abs() < 1e-5);
+        assert_eq!(jobs[2].instance.righteye.y,instance.righteye.y);
+    }
+
+    #[test]
+    fn moving_depth_ranges() {
+
+        // every frame has its own depth range, that follows the head as it moves away
+        let session = load_session("moving_depth_ranges","s:\n  style: moving_depth 1,0\n  frames: 3\n  fps: 10\n  size: 32,24\n  antialias: none\n  head:\n    pos:\n      z: -0.5\n    vel:\n      z: -1\n");
+        let samples = render_instance(&session,1);
+        assert_eq!(samples.len(),3);
+        let ranges: Vec<f32xy> = samples.iter().map(|(_,labels)| labels.depth_range.unwrap()).collect();
+        for range in ranges.iter() {
+            assert!((range.x > 0.0) && (range.x < range.y) && (range.y < 1.0),"{} .. {}",range.x,range.y);
+        }
+        assert!((ranges[0].x < ranges[1].x) && (ranges[1].x < ranges[2].x));
+
+        // still styles have no depth range
+        let session = load_session("still_depth_ranges","s:\n  size: 32,24\n  antialias: none\n  head:\n    pos:\n      z: -0.5\n");
+        assert!(render_instance(&session,1)[0].1.depth_range.is_none());
+    }
+
+    #[test]
+    fn landmark_projection() {
+        let session = load_session("landmark_projection","s:\n  size: 32,24\n  antialias: none\n  landmarks: true\n  head:\n    pos:\n      z: -0.5\n");
+
+        // screen coordinates have y down, from the top left corner
+        for (ndc,screen) in [((0.0,0.0),(16.0,12.0)),((-1.0,1.0),(0.0,0.0)),((1.0,-1.0),(32.0,24.0))].iter() {
+            let p = ndc_to_screen(&session,f32xyz::new(ndc.0,ndc.1,0.0));
+            assert_eq!((p.x,p.y),*screen);
+        }
+        let center = project_ndc(&session,f32xyz::new(0.0,0.0,-0.5));
+        assert!((center.x.abs() < 1e-6) && (center.y.abs() < 1e-6));
+        assert!((project_ndc(&session,f32xyz::new(0.0,0.0,-0.1)).z + 1.0).abs() < 1e-4);  // near plane
+        assert!((project_ndc(&session,f32xyz::new(0.0,0.0,-100.0)).z - 1.0).abs() < 1e-3);  // far plane
+
+        // a face looking into the camera shows landmarks inside the image, at about the distance of the head
+        let samples = render_instance(&session,1);
+        let landmarks = &samples[0].1.landmarks;
+        assert!(!landmarks.is_empty());
+        assert!(landmarks.iter().any(|landmark| landmark.visible));
+        for landmark in landmarks.iter().filter(|landmark| landmark.visible) {
+            assert!((landmark.screen.x >= 0.0) && (landmark.screen.x <= 32.0) && (landmark.screen.y >= 0.0) && (landmark.screen.y <= 24.0),"{}",landmark.name);
+            assert!((landmark.depth > 0.3) && (landmark.depth < 0.7),"{}: {}",landmark.name,landmark.depth);
+        }
+    }
+
+    #[test]
+    fn eye_labels() {
+        let session = load_session("eye_labels","s:\n  size: 32,24\n  antialias: none\n  head:\n    pos:\n      z: -0.5\n  righteye:\n    y: 20\n    p: 10\n");
+        let instance = sample_instance(&session,1);
+        let head_matrix = instance.head_matrix();
+
+        // an eye looking straight ahead gazes into the camera, and its pupil is in front of its center
+        let label = eye_label(&session,head_matrix,instance.lefteye_matrix(),LEFT_EYE_POS);
+        assert!((label.center.x + 0.031).abs() < 1e-6 && (label.center.y - 0.026).abs() < 1e-6 && (label.center.z + 0.477).abs() < 1e-6);
+        assert!((label.gaze.x.abs() < 1e-6) && (label.gaze.y.abs() < 1e-6) && ((label.gaze.z - 1.0).abs() < 1e-6));
+        let pupil = ndc_to_screen(&session,project_ndc(&session,f32xyz::new(label.center.x,label.center.y,label.center.z + EYE_SIZE.z * (TAU / 12.0).cos())));
+        assert!(((label.pupil.x - pupil.x).abs() < 1e-4) && ((label.pupil.y - pupil.y).abs() < 1e-4));
+
+        // a turned eye keeps a unit gaze, that no longer points straight ahead
+        let label = eye_label(&session,head_matrix,instance.righteye_matrix(),RIGHT_EYE_POS);
+        let gaze = label.gaze;
+        assert!(((gaze.x * gaze.x + gaze.y * gaze.y + gaze.z * gaze.z) - 1.0).abs() < 1e-5);
+        assert!((gaze.x.abs() > 0.01) && (gaze.y.abs() > 0.01) && (gaze.z < 1.0));
+    }
+
+    #[test]
+    fn depth_range_16_bits() {
+
+        // the face covers the first two pixels, their depths are closer together than 8 bits of alpha can tell apart
+        let mut spec_image = Image::<ARGB8>::new(usizexy { x: 3,y: 1, });
+        spec_image.data = vec![ARGB8::new_rgba(1,0,0,255),ARGB8::new_rgba(3,SPEC_LEFT_EYE,0,255),ARGB8::new_rgba(0,0,0,255)];
+        let alpha = [32768u16,32868,65535];
+        let range = depth_range(&alpha,&spec_image,0.5,0.25);
+        assert!((range.x - (32768.0 / 65535.0 - 0.25) / 0.5).abs() < 1e-6);
+        assert!((range.y - (32868.0 / 65535.0 - 0.25) / 0.5).abs() < 1e-6);
+        assert!(range.y > range.x);
+
+        // no face in the frame
+        spec_image.data = vec![ARGB8::new_rgba(0,0,0,255); 3];
+        let range = depth_range(&alpha,&spec_image,0.5,0.25);
+        assert!(range.x.is_nan() && range.y.is_nan());
+    }
+
+    #[test]
+    fn bounding_boxes_top_down() {
+
+        // rows bottom-up: skin on the bottom row, the eyes above it, nothing on the top row
+        let bg = ARGB8::new_rgba(0,0,0,255);
+        let skin = ARGB8::new_rgba(1,0,0,255);
+        let mut spec_image = Image::<ARGB8>::new(usizexy { x: 4,y: 3, });
+        spec_image.data = vec![
+            bg,skin,skin,bg,
+            bg,ARGB8::new_rgba(3,SPEC_LEFT_EYE,0,255),bg,ARGB8::new_rgba(3,SPEC_RIGHT_EYE,0,255),
+            bg,bg,bg,bg,
+        ];
+        let rect = |r: Option<usizer>| r.map(|r| (r.o.x,r.o.y,r.s.x,r.s.y));
+        let boxes = bounding_boxes(&spec_image);
+        assert_eq!(rect(boxes.face),Some((1,1,3,2)));  // the eyes are part of the face
+        assert_eq!(rect(boxes.lefteye),Some((1,1,1,1)));
+        assert_eq!(rect(boxes.righteye),Some((3,1,1,1)));
+
+        // nothing visible
+        spec_image.data = vec![bg; 12];
+        let boxes = bounding_boxes(&spec_image);
+        assert!(boxes.face.is_none() && boxes.lefteye.is_none() && boxes.righteye.is_none());
+    }
+
+    #[test]
+    fn instance_seeds() {
+
+        // seeds depend on the session seed and instance number only, and do not repeat
+        assert_eq!(instance_seed(7,3),instance_seed(7,3));
+        let mut seeds: Vec<u64> = (0..1000).map(|num| instance_seed(7,num)).chain((0..1000).map(|num| instance_seed(8,num))).collect();
+        seeds.sort();
+        seeds.dedup();
+        assert_eq!(seeds.len(),2000);
+
+        // so an instance can be regenerated on its own
+        let session = load_session("instance_seeds","s:\n  size: 32,24\n  antialias: none\n  head:\n    pos:\n      x: uniform -0.02,0.02\n      z: -0.5\n  skin:\n    r: uniform 0.5,1\n");
+        let a = sample_instance(&session,instance_seed(7,3));
+        let b = sample_instance(&session,instance_seed(7,3));
+        let c = sample_instance(&session,instance_seed(7,4));
+        assert_eq!(a.seed,instance_seed(7,3));
+        assert_eq!((a.head_pos.x,a.skin_color.r),(b.head_pos.x,b.skin_color.r));
+        assert_ne!((a.head_pos.x,a.skin_color.r),(c.head_pos.x,c.skin_color.r));
+    }
+
+    #[test]
+    fn background_sizes() {
+        let dir = std::env::temp_dir().join("commedia_background_sizes");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = format!("{}/",dir.to_str().unwrap());
+        let session = load_session("background_sizes",&format!("s:\n  size: 32,24\n  antialias: none\n  head:\n    pos:\n      z: -0.5\n  background: image {}\n",path));
+        assert_eq!(load_backgrounds(&session).err(),Some(format!("background directory {} is empty",path)));
+
+        // images smaller than the render target cannot be cropped from
+        let write = |name: &str,size: usizexy| fs::write(dir.join(name),bmp::encode(&Image::<ARGB8>::new(size)).unwrap()).unwrap();
+        write("a.bmp",usizexy { x: 16,y: 16, });
+        assert_eq!(load_backgrounds(&session).err(),Some(format!("no image in background directory {} is at least 32x24 pixels, the size of the render target",path)));
+
+        // and are never selected
+        write("b.bmp",usizexy { x: 40,y: 30, });
+        let backgrounds = load_backgrounds(&session).unwrap();
+        assert_eq!(backgrounds.len(),2);
+        let mut ctx = Context::for_session(ContextBackend::Software,&session).unwrap();
+        for seed in 0..20 {
+            let mut rng = ChaCha8Rng::seed_from_u64(seed);
+            match build_instance(&mut rng,&mut ctx,&session,&backgrounds,seed).unwrap().background {
+                InstanceBackground::Image(index,pos) => assert!((index == 1) && (pos.x <= 8) && (pos.y <= 6)),
+                InstanceBackground::Color(_) => panic!("image background expected"),
+            }
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn pixels(image: &Image<ARGB8>) -> Vec<(u8,u8,u8,u8)> {
+        image.data.iter().map(|pix| (pix.r,pix.g,pix.b,pix.a)).collect()
+    }
+
+    #[test]
+    fn generator_seeds() {
+
+        // the same seed renders the same images, and a range of instances renders them on its own
+        let yaml = "s:\n  count: 3\n  size: 32,24\n  antialias: none\n  head:\n    pos:\n      x: uniform -0.02,0.02\n      z: -0.5\n  skin:\n    r: uniform 0.5,1\n";
+        let generate = |name: &str| -> Vec<(Image<ARGB8>,Labels)> {
+            Generator::new(load_session(name,yaml),ContextBackend::Software).unwrap().with_seed(5).collect()
+        };
+        let a = generate("generator_seeds_a");
+        let b = generate("generator_seeds_b");
+        assert_eq!(a.len(),3);
+        for ((image_a,labels_a),(image_b,labels_b)) in a.iter().zip(b.iter()) {
+            assert_eq!((labels_a.num,labels_a.instance.seed),(labels_b.num,labels_b.instance.seed));
+            assert_eq!(pixels(image_a),pixels(image_b));
+        }
+        assert_ne!(pixels(&a[0].0),pixels(&a[1].0));
+        let mut generator = Generator::new(load_session("generator_seeds_c",yaml),ContextBackend::Software).unwrap().with_seed(5).with_instances(1..2);
+        let (image,labels) = generator.next().unwrap();
+        assert!(generator.next().is_none() && generator.error().is_none());
+        assert_eq!((labels.num,labels.instance.seed),(1,instance_seed(5,1)));
+        assert_eq!(pixels(&image),pixels(&a[1].0));
+    }
+
+    #[test]
+    fn batch_size_independent() {
+
+        // batches only change how many layers are in flight, not the images or labels, also across a partial last batch
+        let generate = |name: &str,batch: usize| -> Vec<(Image<ARGB8>,Labels)> {
+            let yaml = format!("s:\n  count: 4\n  batch: {}\n  size: 32,24\n  antialias: supersample 2\n  boxes: true\n  landmarks: true\n  head:\n    pos:\n      x: uniform -0.02,0.02\n      z: -0.5\n",batch);
+            Generator::new(load_session(name,&yaml),ContextBackend::Software).unwrap().with_seed(9).collect()
+        };
+        let single = generate("batch_size_single",1);
+        let batched = generate("batch_size_batched",3);
+        assert_eq!(single.len(),4);
+        assert_eq!(batched.len(),4);
+        let rect = |r: Option<usizer>| r.map(|r| (r.o.x,r.o.y,r.s.x,r.s.y));
+        for ((image_a,labels_a),(image_b,labels_b)) in single.iter().zip(batched.iter()) {
+            assert_eq!(labels_a.num,labels_b.num);
+            assert_eq!(pixels(image_a),pixels(image_b));
+            let (boxes_a,boxes_b) = (labels_a.boxes.as_ref().unwrap(),labels_b.boxes.as_ref().unwrap());
+            assert_eq!(rect(boxes_a.face),rect(boxes_b.face));
+            assert_eq!(labels_a.landmarks.iter().map(|landmark| landmark.visible).collect::<Vec<bool>>(),labels_b.landmarks.iter().map(|landmark| landmark.visible).collect::<Vec<bool>>());
+        }
+    }
+
+    #[test]
+    fn generator_error() {
+
+        // a head behind the camera is never visible, the generator stops and tells why
+        let session = load_session("generator_error","s:\n  count: 3\n  size: 32,24\n  antialias: none\n  head:\n    pos:\n      z: 5\n");
+        let mut generator = Generator::new(session,ContextBackend::Software).unwrap();
+        assert!(generator.next().is_none());
+        assert_eq!(generator.error().map(|error| error.as_str()),Some("none of 1000 sampled head positions shows the face of session s, check head_pos and projection"));
+    }
+}
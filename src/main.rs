@@ -3,13 +3,17 @@
 
 use std::fs;
 use std::io::prelude::*;
+use std::sync::{Arc,Mutex,mpsc};
+use std::sync::atomic::{AtomicUsize,Ordering};
+use std::thread;
+use std::collections::{BTreeMap,BTreeSet};
 
 extern crate rand;
 use rand::Rng;
-use rand::seq::SliceRandom;
+use rand::SeedableRng;
 
-extern crate rand_distr;
-use rand_distr::*;
+extern crate rand_chacha;
+use rand_chacha::ChaCha8Rng;
 
 extern crate math;
 use math::*;
@@ -17,379 +21,499 @@ use math::*;
 extern crate image;
 use image::*;
 
-mod context3d_xcb_glx_opengl45;
-use context3d_xcb_glx_opengl45::*;
+use commedia::*;
 
-mod opengl45;
-use opengl45::*;
-
-mod face;
-use face::*;
-
-mod config;
-use config::*;
-
-struct Context {
-    _ctx: Context3D,
-    framebuffer: Framebuffer<ARGB8>,
-    skin: Skin,
-    eye: Eye,
+fn save_image(session: &Session,image: Image<ARGB8>,depth: Option<Vec<u16>>,name: &str) {
+    let data = match session.format {
+        SessionFormat::PNG => encode_png(&image).expect("Unable to encode image as PNG."),
+        SessionFormat::PNG16 => encode_png16(&image,&depth.expect("16-bit depth missing")).expect("Unable to encode image as 16-bit PNG."),
+        _ => bmp::encode(&image).expect("Unable to encode image as BMP."),
+    };
+    let mut file = fs::File::create(name).expect("Unable to create file.");
+    file.write_all(&data).expect("Unable to write image.");
 }
 
-impl Context {
-    pub fn new(size: usizexy) -> Context {
-        let ctx = Context3D::new().expect("Unable to create 3D context.");
-        let framebuffer = Framebuffer::<ARGB8>::new(4 * size).expect("Unable to create framebuffer object.");
-        framebuffer.bind();
-        Context {
-            _ctx: ctx,
-            framebuffer: framebuffer,
-            skin: Skin::new(),
-            eye: Eye::new(),
+// all features of the record, except the encoded image and mask, which are added by the encoder threads
+fn build_example(name: &str,size: usizexy,labels: &Labels) -> Example {
+    let instance = &labels.instance;
+    let mut example = Example::new();
+    example.add_bytes("image/format",b"bmp");
+    example.add_bytes("image/filename",name.as_bytes());
+    example.add_int64s("image/width",&[size.x as i64]);
+    example.add_int64s("image/height",&[size.y as i64]);
+    example.add_int64s("seed",&[instance.seed as i64]);
+    example.add_floats("head_pos",&[instance.head_pos.x,instance.head_pos.y,instance.head_pos.z]);
+    example.add_floats("head_dir",&[instance.head_dir.y,instance.head_dir.p,instance.head_dir.b]);
+    example.add_floats("lefteye",&[instance.lefteye.y,instance.lefteye.p,instance.lefteye.b]);
+    example.add_floats("righteye",&[instance.righteye.y,instance.righteye.p,instance.righteye.b]);
+    example.add_floats("light_dir",&[instance.light_dir.y,instance.light_dir.p,instance.light_dir.b]);
+    example.add_floats("light_color",&[instance.light_color.r,instance.light_color.g,instance.light_color.b]);
+    if let InstanceBackground::Color(color) = &instance.background {
+        example.add_floats("background_color",&[color.r,color.g,color.b]);
+    }
+    example.add_floats("ambient_color",&[instance.ambient_color.r,instance.ambient_color.g,instance.ambient_color.b]);
+    example.add_floats("skin_color",&[instance.skin_color.r,instance.skin_color.g,instance.skin_color.b]);
+    example.add_floats("sclera_color",&[instance.sclera_color.r,instance.sclera_color.g,instance.sclera_color.b]);
+    example.add_floats("iris_color",&[instance.iris_color.r,instance.iris_color.g,instance.iris_color.b]);
+    if let Some(motion) = &labels.motion {
+        example.add_floats("head_vel",&[motion.head_vel.x,motion.head_vel.y,motion.head_vel.z]);
+        example.add_floats("head_angvel",&[motion.head_angvel.y,motion.head_angvel.p,motion.head_angvel.b]);
+        example.add_floats("lefteye_angvel",&[motion.lefteye_angvel.y,motion.lefteye_angvel.p,motion.lefteye_angvel.b]);
+        example.add_floats("righteye_angvel",&[motion.righteye_angvel.y,motion.righteye_angvel.p,motion.righteye_angvel.b]);
+        example.add_floats("light_angvel",&[motion.light_angvel.y,motion.light_angvel.p,motion.light_angvel.b]);
+    }
+    example.add_floats("lefteye/center",&[labels.lefteye.center.x,labels.lefteye.center.y,labels.lefteye.center.z]);
+    example.add_floats("lefteye/gaze",&[labels.lefteye.gaze.x,labels.lefteye.gaze.y,labels.lefteye.gaze.z]);
+    example.add_floats("lefteye/pupil",&[labels.lefteye.pupil.x,labels.lefteye.pupil.y]);
+    example.add_floats("righteye/center",&[labels.righteye.center.x,labels.righteye.center.y,labels.righteye.center.z]);
+    example.add_floats("righteye/gaze",&[labels.righteye.gaze.x,labels.righteye.gaze.y,labels.righteye.gaze.z]);
+    example.add_floats("righteye/pupil",&[labels.righteye.pupil.x,labels.righteye.pupil.y]);
+    if let Some(depth) = labels.depth_range {
+        example.add_floats("depth_range",&[depth.x,depth.y]);
+    }
+    if let Some(boxes) = &labels.boxes {
+        for (name,b) in [("face",boxes.face),("lefteye",boxes.lefteye),("righteye",boxes.righteye)].iter() {
+            if let Some(r) = b {
+                example.add_int64s(&format!("{}/bbox",name),&[r.o.x as i64,r.o.y as i64,r.s.x as i64,r.s.y as i64]);
+            }
         }
     }
+    for landmark in &labels.landmarks {
+        example.add_floats(&format!("landmark/{}",landmark.name),&[landmark.screen.x,landmark.screen.y,if landmark.visible { 1.0 } else { 0.0 },landmark.depth]);
+    }
+    if let Some(depth_map) = &labels.depth_map {
+        example.add_floats("depth_map",depth_map);
+    }
+    example
 }
 
-fn downsample4(image: Image<ARGB8>) -> Image<ARGB8> {
-    let mut dst = Image::<ARGB8>::new(image.size / 4);
-    for y in 0..image.size.y / 4 {
-        for x in 0..image.size.x / 4 {
-            let mut r = 0usize;
-            let mut g = 0usize;
-            let mut b = 0usize;
-            let mut a = 0usize;
-            for i in 0..4 {
-                for k in 0..4 {
-                    let pix = image.pixel(usizexy::new(x * 4 + k,y * 4 + i));
-                    r += pix.r as usize;
-                    g += pix.g as usize;
-                    b += pix.b as usize;
-                    a += pix.a as usize;
+fn save_depth_map(session: &Session,size: usizexy,depth_map: &Vec<f32>,name: &str) {
+    let (data,extension) = match session.depth {
+        SessionDepth::PNG16(scale,offset) => {
+            let mut values: Vec<u16> = Vec::with_capacity(depth_map.len());
+            for z in depth_map {
+                values.push((scale * z + offset).max(0.0).min(65535.0) as u16);
+            }
+            (encode_png16_gray(size,&values).expect("Unable to encode depth map as PNG."),"png")
+        },
+        SessionDepth::PFM => {
+
+            // PFM rows are bottom-up, just like the framebuffer
+            let mut data = format!("Pf\n{} {}\n-1.0\n",size.x,size.y).into_bytes();
+            for z in depth_map {
+                data.extend_from_slice(&z.to_le_bytes());
+            }
+            (data,"pfm")
+        },
+        SessionDepth::Raw => {
+            let mut data: Vec<u8> = Vec::with_capacity(depth_map.len() * 4);
+            for y in (0..size.y).rev() {
+                for x in 0..size.x {
+                    data.extend_from_slice(&depth_map[y * size.x + x].to_le_bytes());
                 }
             }
-            r /= 16;
-            g /= 16;
-            b /= 16;
-            a /= 16;
-            *dst.pixel_mut(usizexy::new(x,y)) = ARGB8::new_rgba(r as u8,g as u8,b as u8,a as u8);
-        }
-    }
-    dst
+            (data,"raw")
+        },
+        SessionDepth::None => {
+            return;
+        },
+    };
+    let mut file = fs::File::create(format!("{}.{}",name,extension)).expect("Unable to create file.");
+    file.write_all(&data).expect("Unable to write depth map.");
 }
 
-fn crop_upside_down(source: &Image<ARGB8>,r: usizer) -> Image<ARGB8> {
-    let mut image = Image::<ARGB8>::new(r.s);
-    for y in 0..r.s.y {
-        for x in 0..r.s.x {
-            *image.pixel_mut(usizexy { x: x,y: r.s.y - y - 1, }) = *source.pixel(r.o + usizexy { x: x,y: y, });
-        }
-    }
-    image
+fn save_mask(size: usizexy,mask: &Vec<u8>,name: &str) {
+    let data = encode_png_indexed(size,mask,&SEGMENT_PALETTE).expect("Unable to encode mask as PNG.");
+    let mut file = fs::File::create(name).expect("Unable to create file.");
+    file.write_all(&data).expect("Unable to write mask.");
 }
 
-enum InstanceBackground {
-    Color(f32rgb),
-    Image(Image<ARGB8>),
+fn session_dir(session: &Session) -> &str {
+    match &session.path {
+        SessionPath::Replace(path) => path,
+        SessionPath::Append(path) => path,
+    }
 }
 
-struct Instance {
-    head_pos: f32xyz,
-    head_dir: f32ypb,
-    lefteye: f32ypb,
-    righteye: f32ypb,
-    light_dir: f32ypb,
-    light_color: f32rgb,
-    background: InstanceBackground,
-    ambient_color: f32rgb,
-    skin_color: f32rgb,
-    sclera_color: f32rgb,
-    iris_color: f32rgb,
+fn image_extension(session: &Session) -> &'static str {
+    match session.format {
+        SessionFormat::BMP => "bmp",
+        SessionFormat::PNG => "png",
+        SessionFormat::PNG16 => "png",
+        SessionFormat::ProtoBuf => "bmp",  // name of the encoded image inside the record
+    }
 }
 
-const LEFT_EYE_POS: f32xyz = f32xyz { x: -0.031,y: 0.026,z: 0.023, };
-const RIGHT_EYE_POS: f32xyz = f32xyz { x: 0.031,y: 0.026,z: 0.023, };
-const EYE_SIZE: f32xyz = f32xyz { x: 0.0115,y: 0.0115,z: 0.0115, };
-
-fn render_full(rng: &mut rand::rngs::ThreadRng,ctx: &Context,session: &Session,instance: &Instance) -> Image<ARGB8> {
-
-    // prepare matrices
-    let light_matrix = f32m3x3::yaw(instance.light_dir.y) * f32m3x3::pitch(instance.light_dir.p) * f32m3x3::roll(instance.light_dir.b);
-    let light_dir = light_matrix * f32xyz::new(0.0,1.0,0.0);
-    let head_matrix = f32m4x4::translate(instance.head_pos) * f32m4x4::yaw(instance.head_dir.y) * f32m4x4::pitch(instance.head_dir.p);
-    let lefteye_matrix = f32m4x4::translate(LEFT_EYE_POS) * f32m4x4::yaw(instance.lefteye.y) * f32m4x4::pitch(instance.lefteye.p) * f32m4x4::scale(EYE_SIZE);
-    let righteye_matrix = f32m4x4::translate(RIGHT_EYE_POS) * f32m4x4::yaw(instance.righteye.y) * f32m4x4::pitch(instance.righteye.p) * f32m4x4::scale(EYE_SIZE);
-    let depth_map = match session.style {
-        SessionStyle::Still => {
-            f32xy { x: 1.0,y: 0.0, }
-        },
-        SessionStyle::StillDepth(scale,offset) => {
-            f32xy { x: offset,y: scale, }
-        },
-        SessionStyle::Moving => {
-            f32xy { x: 1.0,y: 0.0, }
-        },
-        SessionStyle::MovingDepth(scale,offset) => {
-            f32xy { x: offset,y: scale, }
-        },
-    };
+fn csv_line(session: &Session,name: &str,labels: &Labels) -> String {
+    let instance = &labels.instance;
 
-    // clear or draw background
-    match &instance.background {
-        InstanceBackground::Color(color) => {
-            ctx.framebuffer.bind();
-            unsafe {
-                gl::ClearColor(color.r,color.g,color.b,1.0);
-                gl::ClearDepth(1.0);
-                gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
-            }
-        },
-        InstanceBackground::Image(image) => {
-            ctx.framebuffer.bind();
-            unsafe {
-                gl::ClearDepth(1.0);
-                gl::Clear(gl::DEPTH_BUFFER_BIT);
-            }
-            ctx.framebuffer.unbind();
-            ctx.framebuffer.set(&image);
-            ctx.framebuffer.bind();
-        },
+    // calculate NDC and screen coordinates of the head
+    let ndc = project_ndc(session,instance.head_pos);
+    let screen = ndc_to_screen(session,ndc);
+
+    // build line for CSV file
+    let mut line = format!("\"{}\", {},{},{}, {},{}, {},{},{}, {},{}, {},{}, {},{},{}, {},{},{}, {},{},{}",name,
+        instance.head_pos.x,instance.head_pos.y,instance.head_pos.z,
+        instance.head_dir.y,instance.head_dir.p,
+        ndc.x,ndc.y,ndc.z,
+        screen.x,screen.y,
+        instance.light_dir.y,instance.light_dir.p,
+        instance.light_color.r,instance.light_color.g,instance.light_color.b,
+        instance.ambient_color.r,instance.ambient_color.g,instance.ambient_color.b,
+        instance.skin_color.r,instance.skin_color.g,instance.skin_color.b,
+    );
+
+    // moving styles also record the velocities
+    if let Some(motion) = &labels.motion {
+        line += &format!(", {},{},{}, {},{},{}, {},{},{}, {},{},{}, {},{},{}",
+            motion.head_vel.x,motion.head_vel.y,motion.head_vel.z,
+            motion.head_angvel.y,motion.head_angvel.p,motion.head_angvel.b,
+            motion.lefteye_angvel.y,motion.lefteye_angvel.p,motion.lefteye_angvel.b,
+            motion.righteye_angvel.y,motion.righteye_angvel.p,motion.righteye_angvel.b,
+            motion.light_angvel.y,motion.light_angvel.p,motion.light_angvel.b,
+        );
     }
 
-    // draw eyes and face
-    unsafe {
-        gl::Enable(gl::DEPTH_TEST);
-        ctx.eye.render_full(session.projection,head_matrix * lefteye_matrix,light_dir,instance.light_color,instance.ambient_color,instance.sclera_color,instance.iris_color,depth_map);
-        ctx.eye.render_full(session.projection,head_matrix * righteye_matrix,light_dir,instance.light_color,instance.ambient_color,instance.sclera_color,instance.iris_color,depth_map);
-        ctx.skin.render_full(session.projection,head_matrix,light_dir,instance.light_color,instance.ambient_color,instance.skin_color,depth_map);
-        gl::Disable(gl::DEPTH_TEST);
-        gl::Finish();
-        gl::Flush();
+    // moving_depth also records the depth range of the frame
+    if let Some(depth) = labels.depth_range {
+        line += &format!(", {},{}",depth.x,depth.y);
     }
-    ctx.framebuffer.unbind();
 
-    ctx.framebuffer.grab()
+    // bounding boxes of face, left eye and right eye
+    if let Some(boxes) = &labels.boxes {
+        for b in [boxes.face,boxes.lefteye,boxes.righteye].iter() {
+            let r = b.unwrap_or(usizer { o: usizexy { x: 0,y: 0, },s: usizexy { x: 0,y: 0, }, });
+            line += &format!(", {},{},{},{}",r.o.x,r.o.y,r.s.x,r.s.y);
+        }
+    }
+
+    // screen coordinates, visibility and depth of each landmark
+    for landmark in &labels.landmarks {
+        line += &format!(", {},{},{},{}",landmark.screen.x,landmark.screen.y,if landmark.visible { 1 } else { 0 },landmark.depth);
+    }
+
+    // sampled direction, center, gaze and pupil center of each eye, after the older columns so their positions stay the same
+    for (dir,eye) in [(instance.lefteye,&labels.lefteye),(instance.righteye,&labels.righteye)].iter() {
+        line += &format!(", {},{},{}, {},{},{}, {},{},{}, {},{}",
+            dir.y,dir.p,dir.b,
+            eye.center.x,eye.center.y,eye.center.z,
+            eye.gaze.x,eye.gaze.y,eye.gaze.z,
+            eye.pupil.x,eye.pupil.y,
+        );
+    }
+
+    // seed of the instance, to regenerate it
+    line += &format!(", {}\n",instance.seed);
+    line
 }
 
-fn render_spec(ctx: &Context,session: &Session,instance: &Instance) -> Image<ARGB8> {
-
-    // prepare matrices
-    let head_matrix = f32m4x4::translate(instance.head_pos) * f32m4x4::yaw(instance.head_dir.y) * f32m4x4::pitch(instance.head_dir.p);
-    let lefteye_matrix = f32m4x4::translate(LEFT_EYE_POS) * f32m4x4::yaw(instance.lefteye.y) * f32m4x4::pitch(instance.lefteye.p) * f32m4x4::scale(EYE_SIZE);
-    let righteye_matrix = f32m4x4::translate(RIGHT_EYE_POS) * f32m4x4::yaw(instance.righteye.y) * f32m4x4::pitch(instance.righteye.p) * f32m4x4::scale(EYE_SIZE);
-
-    ctx.framebuffer.bind();
-    unsafe {
-        gl::ClearColor(0.0,0.0,0.0,1.0);
-        gl::ClearDepth(1.0);
-        gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
-        gl::Enable(gl::DEPTH_TEST);
-        ctx.eye.render_spec(session.projection,head_matrix * lefteye_matrix);
-        ctx.eye.render_spec(session.projection,head_matrix * righteye_matrix);
-        ctx.skin.render_spec(session.projection,head_matrix,f32rgb { r: 0.5,g: 0.5,b: 0.5, });
-        gl::Disable(gl::DEPTH_TEST);
-        gl::Finish();
-        gl::Flush();
-    }
-    ctx.framebuffer.unbind();
-
-    ctx.framebuffer.grab()
+// a rendered image or frame, on its way from a render worker to the encoder threads
+struct Frame {
+    seq: usize,  // position in the CSV file
+    shard: usize,
+    stem: String,
+    image: Image<ARGB8>,
+    depth16: Option<Vec<u16>>,
+    depth_map: Option<Vec<f32>>,
+    mask: Option<Vec<u8>>,
+    example: Option<Example>,
+    line: String,
 }
 
-fn save_image(image: Image<ARGB8>,name: &str) {
-    let data = bmp::encode(&image).expect("Unable to encode image as BMP.");
-    let mut file = fs::File::create(name).expect("Unable to create file.");
-    file.write_all(&data).expect("Unable to write BMP.");
+// an encoded image or frame, on its way from an encoder thread to the writer
+struct Output {
+    seq: usize,
+    shard: usize,
+    line: String,
+    record: Option<Vec<u8>>,
 }
 
-fn process(rng: &mut rand::rngs::ThreadRng,ctx: &Context,session: &Session,backgrounds: &Vec<Image<ARGB8>>,csv: &mut fs::File,num: usize) {
+// holds outputs back until all outputs before them have arrived
+struct OutputOrder {
+    pending: BTreeMap<usize,Output>,
+    seq: usize,  // position of the next output to write
+}
 
-    // get image filename and full filename
-    let name = match session.format {
-        SessionFormat::BMP => format!("{:05}.bmp",num),
-        SessionFormat::PNG => format!("{:05}.png",num),
-        SessionFormat::ProtoBuf => format!("{:05}.todo",num),
-    };
-    let full_name = match &session.path {
-        SessionPath::Replace(path) => format!("{}/{}",path,name),
-        SessionPath::Append(path) => format!("{}/{}",path,name),
-    };
+impl OutputOrder {
+    fn new() -> OutputOrder {
+        OutputOrder {
+            pending: BTreeMap::new(),
+            seq: 0,
+        }
+    }
 
-    // build instance
-    let mut instance = Instance {
-        head_pos: f32xyz {
-            x: session.head_pos.x.instantiate(rng),
-            y: session.head_pos.y.instantiate(rng),
-            z: session.head_pos.z.instantiate(rng),
-        },
-        head_dir: f32ypb {
-            y: session.head_dir.y.instantiate(rng),
-            p: session.head_dir.p.instantiate(rng),
-            b: session.head_dir.b.instantiate(rng),
-        },
-        lefteye: f32ypb {
-            y: session.lefteye.y.instantiate(rng),
-            p: session.lefteye.p.instantiate(rng),
-            b: session.lefteye.b.instantiate(rng),
-        },
-        righteye: f32ypb {
-            y: session.righteye.y.instantiate(rng),
-            p: session.righteye.p.instantiate(rng),
-            b: session.righteye.b.instantiate(rng),
-        },
-        light_dir: f32ypb {
-            y: session.light_dir.y.instantiate(rng),
-            p: session.light_dir.p.instantiate(rng),
-            b: session.light_dir.b.instantiate(rng),
-        },
-        light_color: f32rgb {
-            r: session.light_color.r.instantiate(rng),
-            g: session.light_color.g.instantiate(rng),
-            b: session.light_color.b.instantiate(rng),
-        },
-        background: match &session.background {
-            SessionBackground::Color(color) => InstanceBackground::Color(f32rgb {
-                r: color.r.instantiate(rng),
-                g: color.g.instantiate(rng),
-                b: color.b.instantiate(rng),
-            }),
-            SessionBackground::Image(path) => InstanceBackground::Image({
-                let mut background = backgrounds.choose(rng).expect("unable to select background from set");
-                while (background.size.x < ctx.framebuffer.size.x) || (background.size.y < ctx.framebuffer.size.y) {
-                    background = backgrounds.choose(rng).expect("unable to select background from set");
-                }
-                let cropspace = background.size - ctx.framebuffer.size;
-                let pos = usizexy { x: (rng.gen::<f32>() * (cropspace.x as f32)) as usize,y: (rng.gen::<f32>() * (cropspace.y as f32)) as usize, };
-                crop_upside_down(background,usizer { o: pos,s: ctx.framebuffer.size, })            
-            }),
-        },
-        ambient_color: f32rgb {
-            r: session.ambient_color.r.instantiate(rng),
-            g: session.ambient_color.g.instantiate(rng),
-            b: session.ambient_color.b.instantiate(rng),
-        },
-        skin_color: f32rgb {
-            r: session.skin_color.r.instantiate(rng),
-            g: session.skin_color.g.instantiate(rng),
-            b: session.skin_color.b.instantiate(rng),
-        },
-        sclera_color: f32rgb {
-            r: session.sclera_color.r.instantiate(rng),
-            g: session.sclera_color.g.instantiate(rng),
-            b: session.sclera_color.b.instantiate(rng),
+    // the outputs that can be written now, in order
+    fn push(&mut self,output: Output) -> Vec<Output> {
+        self.pending.insert(output.seq,output);
+        let mut outputs: Vec<Output> = Vec::new();
+        while let Some(output) = self.pending.remove(&self.seq) {
+            outputs.push(output);
+            self.seq += 1;
+        }
+        outputs
+    }
+}
+
+fn encode_frame(session: &Session,frame: Frame) -> Output {
+    let size = frame.image.size;
+    let record = match frame.example {
+        Some(mut example) => {
+            example.add_bytes("image/encoded",&bmp::encode(&frame.image).expect("Unable to encode image as BMP."));
+            if let Some(mask) = &frame.mask {
+                example.add_bytes("mask/encoded",&encode_png_indexed(size,mask,&SEGMENT_PALETTE).expect("Unable to encode mask as PNG."));
+            }
+            Some(example.encode())
         },
-        iris_color: f32rgb {
-            r: session.iris_color.r.instantiate(rng),
-            g: session.iris_color.g.instantiate(rng),
-            b: session.iris_color.b.instantiate(rng),
+        None => {
+            save_image(session,frame.image,frame.depth16,&format!("{}/{}.{}",session_dir(session),frame.stem,image_extension(session)));
+            if let Some(depth_map) = &frame.depth_map {
+                save_depth_map(session,size,depth_map,&format!("{}/{}_depth",session_dir(session),frame.stem));
+            }
+            if let Some(mask) = &frame.mask {
+                save_mask(size,mask,&format!("{}/{}_mask.png",session_dir(session),frame.stem));
+            }
+            None
         },
     };
+    Output {
+        seq: frame.seq,
+        shard: frame.shard,
+        line: frame.line,
+        record: record,
+    }
+}
 
-    // make sure at least 1 pixel of the face is visible
-    let mut visible = false;
-    while !visible {
+fn process_image(session: &Session,image: Image<ARGB8>,mut labels: Labels,frames: &mpsc::SyncSender<Frame>,seq: usize,stem: &str) {
 
-        // and also that head_pos is inside the projection frustum
-        loop {
-            instance.head_pos = f32xyz {
-                x: session.head_pos.x.instantiate(rng),
-                y: session.head_pos.y.instantiate(rng),
-                z: session.head_pos.z.instantiate(rng),
-            };
-            let pos = session.projection * f32xyzw { x: instance.head_pos.x,y: instance.head_pos.y,z: instance.head_pos.z,w: 1.0, };
-            if (pos.x > -pos.w) && (pos.x < pos.w) && (pos.y > -pos.w) && (pos.y < pos.w) {
-                break;
+    // get image filename
+    let name = format!("{}.{}",stem,image_extension(session));
+
+    // and hand the image over to the encoder threads
+    let example = if let SessionFormat::ProtoBuf = session.format {
+        Some(build_example(&name,image.size,&labels))
+    }
+    else {
+        None
+    };
+    let line = csv_line(session,&name,&labels);
+    frames.send(Frame {
+        seq: seq,
+        shard: labels.num,
+        stem: stem.to_string(),
+        image: image,
+        depth16: labels.depth16.take(),
+        depth_map: labels.depth_map.take(),
+        mask: labels.mask.take(),
+        example: example,
+        line: line,
+    }).expect("Unable to pass frame to encoder.");
+}
+
+fn process(ctx: &mut Context,session: &Session,backgrounds: &Vec<Image<ARGB8>>,frames: &mpsc::SyncSender<Frame>,nums: &[usize],first: usize,session_seed: u64) -> std::result::Result<(),String> {
+
+    let moving = match session.style {
+        SessionStyle::Moving | SessionStyle::MovingDepth(_,_) => true,
+        _ => false,
+    };
+
+    // build the instances
+    let mut jobs: Vec<RenderJob> = Vec::new();
+    for num in nums {
+        let seed = instance_seed(session_seed,*num);
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+        let instance = build_instance(&mut rng,ctx,session,backgrounds,seed)?;
+
+        // each movie gets its own directory of numbered frames
+        if moving {
+            match session.format {
+                SessionFormat::ProtoBuf => { },
+                _ => {
+                    match fs::create_dir(format!("{}/{:05}",session_dir(session),num)) { _ => { }, };
+                },
             }
         }
+        jobs.extend(instance_jobs(&mut rng,session,*num,instance));
+    }
 
-        // render specification
-        let spec_image = render_spec(&ctx,session,&instance);
-
-        // check image to see if 0.5,0.5,0.5 appeared (face pixels)
-        for i in 0..spec_image.size.y {
-            for k in 0..spec_image.size.x {
-                let r = (*spec_image.pixel(usizexy { x: k,y: i })).r;
-                let g = (*spec_image.pixel(usizexy { x: k,y: i })).g;
-                let b = (*spec_image.pixel(usizexy { x: k,y: i })).b;
-                if (r >= 0x70) && (r < 0x90) && (g >= 0x70) && (g < 0x90) && (b >= 0x70) && (b < 0x90) {
-                    visible = true;
-                    break;
-                }
-            }
-            if visible {
-                break;
-            }
+    // render them in batches
+    for (image,labels) in render_samples(ctx,session,backgrounds,&jobs) {
+        let (seq,stem) = if moving {
+            ((labels.num - first) * session.frames + labels.frame,format!("{:05}/{:05}",labels.num,labels.frame))
         }
+        else {
+            (labels.num - first,format!("{:05}",labels.num))
+        };
+        process_image(session,image,labels,frames,seq,&stem);
     }
+    Ok(())
+}
+
+#[derive(Clone,Copy)]
+enum Command {
+    Render,  // render all sessions
+    Validate,  // check the config and report the parameter ranges
+    Sample,  // print sampled instances as JSON
+    Preview,  // render a few instances to PNG
+    Info,  // summarize the sessions
+}
 
-    // render final image
-    let image = render_full(rng,&ctx,session,&instance);
+struct Options {
+    command: Command,
+    file: String,
+    seed: Option<u64>,
+    only: Option<usize>,
+    count: Option<usize>,
+    out: String,
+    backend: ContextBackend,
+    overrides: Vec<(String,String)>,
+}
 
-    // downsample 4x to synthesize subpixel accuracy
-    let image = downsample4(image);
-    
-    // and save the image
-    save_image(image,&full_name);
+fn usage() {
+    println!("usage:");
+    println!("");
+    println!("    commedia [<command>] [<options>] <file>");
+    println!("");
+    println!("where <file> is the config file and <command> is one of:");
+    println!("");
+    println!("    render    render all sessions (default)");
+    println!("    validate  check the config and report the parameter ranges of each session");
+    println!("    sample    print sampled instances as JSON, without OpenGL");
+    println!("    preview   render a few instances of each session to PNG files");
+    println!("    info      summarize the sessions");
+    println!("");
+    println!("options:");
+    println!("");
+    println!("    --seed <seed>                     override the seed of each session");
+    println!("    --instance <number>               only this instance of each session");
+    println!("    --count <count>                   number of instances for sample and preview (default 1)");
+    println!("    --out <dir>                       directory for the preview images (default .)");
+    println!("    --context <glx|egl|software>      render on an X display, headless or on the CPU");
+    println!("    --set <key>=<value>               override a key of the config file, like --set my_session.count=1000");
+}
 
-    // calculate NDC coordinates of the head
-    let hom = session.projection * f32xyzw { x: instance.head_pos.x,y: instance.head_pos.y,z: instance.head_pos.z,w: 1.0, };
-    let ndc = f32xyz {
-        x: hom.x / hom.w,
-        y: hom.y / hom.w,
-        z: hom.z / hom.w,
-    };
+fn parse_args() -> Option<Options> {
+    let args: Vec<String> = std::env::args().collect();
+    let mut options = Options {
+        command: Command::Render,
+        file: String::new(),
+        seed: None,
+        only: None,
+        count: None,
+        out: ".".to_string(),
 
-    // calculate screen coordinates of the head
-    let screen = f32xy {
-        x: 0.5 * (1.0 + ndc.x) * (session.size.x as f32),
-        y: 0.5 * (1.0 - ndc.y) * (session.size.y as f32),
+        // without X display, render headless
+        backend: if std::env::var("DISPLAY").is_ok() { ContextBackend::GLX } else { ContextBackend::EGL },
+        overrides: Vec::new(),
     };
+    let mut i = 1;
 
-    // write line to CSV file
-    let line = format!("\"{}\", {},{},{}, {},{}, {},{},{}, {},{}, {},{}, {},{},{}, {},{},{}, {},{},{}\n",name,
-        instance.head_pos.x,instance.head_pos.y,instance.head_pos.z,
-        instance.head_dir.y,instance.head_dir.p,
-        ndc.x,ndc.y,ndc.z,
-        screen.x,screen.y,
-        instance.light_dir.y,instance.light_dir.p,
-        instance.light_color.r,instance.light_color.g,instance.light_color.b,
-        instance.ambient_color.r,instance.ambient_color.g,instance.ambient_color.b,
-        instance.skin_color.r,instance.skin_color.g,instance.skin_color.b,
-    );
-    csv.write_all(line.as_bytes()).expect("Unable to write to CSV file.");
+    // without command, render
+    if i < args.len() {
+        let command = match args[i].as_str() {
+            "render" => Some(Command::Render),
+            "validate" => Some(Command::Validate),
+            "sample" => Some(Command::Sample),
+            "preview" => Some(Command::Preview),
+            "info" => Some(Command::Info),
+            _ => None,
+        };
+        if let Some(command) = command {
+            options.command = command;
+            i += 1;
+        }
+    }
+    let mut file: Option<String> = None;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--seed" if i + 1 < args.len() => {
+                options.seed = Some(args[i + 1].parse::<u64>().ok().or_else(|| { println!("invalid seed {}",args[i + 1]); None })?);
+                i += 1;
+            },
+            "--context" if i + 1 < args.len() => {
+                options.backend = match args[i + 1].as_str() {
+                    "glx" => ContextBackend::GLX,
+                    "egl" => ContextBackend::EGL,
+                    "software" => ContextBackend::Software,
+                    _ => {
+                        println!("invalid context {} (should be glx, egl or software)",args[i + 1]);
+                        return None;
+                    },
+                };
+                i += 1;
+            },
+            "--instance" if i + 1 < args.len() => {
+                options.only = Some(args[i + 1].parse::<usize>().ok().or_else(|| { println!("invalid instance number {}",args[i + 1]); None })?);
+                i += 1;
+            },
+            "--count" if i + 1 < args.len() => {
+                options.count = Some(args[i + 1].parse::<usize>().ok().or_else(|| { println!("invalid count {}",args[i + 1]); None })?);
+                i += 1;
+            },
+            "--out" if i + 1 < args.len() => {
+                options.out = args[i + 1].clone();
+                i += 1;
+            },
+            "--set" if i + 1 < args.len() => {
+                if let Some(index) = args[i + 1].find('=') {
+                    options.overrides.push((args[i + 1][..index].to_string(),args[i + 1][index + 1..].to_string()));
+                }
+                else {
+                    println!("invalid --set {} (should be key=value)",args[i + 1]);
+                    return None;
+                }
+                i += 1;
+            },
+            arg if arg.starts_with("--") => {
+                println!("invalid option {}",arg);
+                return None;
+            },
+            _ => {
+                file = Some(args[i].clone());
+            },
+        }
+        i += 1;
+    }
+    options.file = file?;
+    Some(options)
 }
 
-fn main() {
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() < 2 {
-        println!("usage:");
-        println!("");
-        println!("    commedia <file>");
-        println!("");
-        println!("where <file> is the config file.");
-        return;
-    }
-    let mut rng = rand::thread_rng();
-    let sessions = load_config(&args[1]).expect("unable to load config file");
+// command line seed overrides session seed, otherwise pick one at random
+fn session_seed(options: &Options,session: &Session) -> u64 {
+    match (options.seed,session.seed) {
+        (Some(seed),_) => seed,
+        (None,Some(seed)) => seed,
+        (None,None) => rand::thread_rng().gen::<u64>(),
+    }
+}
+
+fn print_projection(session: &Session) {
+    println!("    projection matrix:");
+    println!("        {:10.7} {:10.7} {:10.7} {:10.7}",session.projection.x.x,session.projection.x.y,session.projection.x.z,session.projection.x.w);
+    println!("        {:10.7} {:10.7} {:10.7} {:10.7}",session.projection.y.x,session.projection.y.y,session.projection.y.z,session.projection.y.w);
+    println!("        {:10.7} {:10.7} {:10.7} {:10.7}",session.projection.z.x,session.projection.z.y,session.projection.z.z,session.projection.z.w);
+    println!("        {:10.7} {:10.7} {:10.7} {:10.7}",session.projection.w.x,session.projection.w.y,session.projection.w.z,session.projection.w.w);
+}
+
+// report an error that stops a session, and stop
+fn exit_with(session: &Session,error: &str) -> ! {
+    eprintln!("{}: {}",session.name,error);
+    std::process::exit(1);
+}
+
+fn render(options: &Options,sessions: Vec<Session>) {
+    if let ContextBackend::GLX = options.backend {
+        init_threads();
+    }
     for session in sessions {
 
         println!("session: {}",session.name);
 
+        let session_seed = session_seed(options,&session);
+        println!("    seed: {}",session_seed);
+
         // read backgrounds, if any
-        let mut backgrounds: Vec<Image<ARGB8>> = Vec::new();
-        if let SessionBackground::Image(path) = &session.background {
-            println!("    loading backgrounds...");
-            for entry in fs::read_dir(path).expect("unable to read from backgrounds directory") {
-                let entry = entry.expect("invalid entry").file_name().into_string().expect("unable to convert");
-                let mut file = fs::File::open(format!("{}{}",path,entry)).expect("cannot open file");
-                let mut buffer: Vec<u8> = Vec::new();
-                file.read_to_end(&mut buffer).expect("unable to read file");
-                let image = decode(&buffer).expect("unable to decode");
-                backgrounds.push(image);
-            }
-        }
+        let backgrounds = load_backgrounds(&session).unwrap_or_else(|error| exit_with(&session,&error));
 
-        // create context
-        let ctx = Context::new(session.size);
         match session.style {
             SessionStyle::Still => {
                 println!("    generating {} images",session.count);
@@ -398,38 +522,511 @@ fn main() {
                 println!("    generating {} images with depth",session.count);
             },
             SessionStyle::Moving => {
-                println!("    generating {} movies",session.count);
+                println!("    generating {} movies of {} frames at {} fps",session.count,session.frames,session.fps);
             },
             SessionStyle::MovingDepth(_scale,_offset) => {
-                println!("    generating {} movies with depth",session.count);
+                println!("    generating {} movies of {} frames at {} fps with depth",session.count,session.frames,session.fps);
             }
         }
+        println!("    using {} workers",session.workers);
+        if session.batch > 1 {
+            println!("    rendering batches of {} images",session.batch);
+        }
 
-        // create/clear path
+        // create/clear path, but keep what is there when regenerating a single instance
         match &session.path {
-            SessionPath::Replace(path) => {
+            SessionPath::Replace(path) if options.only.is_none() => {
                 match fs::remove_dir_all(path) { _ => { }, };
                 match fs::create_dir(path) { _ => { }, };
             },
-            SessionPath::Append(path) => {
+            SessionPath::Replace(path) | SessionPath::Append(path) => {
                 match fs::create_dir(path) { _ => { }, };
             },
         }
 
         // open CSV
-        let mut csv = fs::File::create(&session.csv).expect("unable to create CSV file");
+        let mut csv = if options.only.is_some() {
+            fs::OpenOptions::new().create(true).append(true).open(&session.csv).expect("unable to open CSV file")
+        }
+        else {
+            fs::File::create(&session.csv).expect("unable to create CSV file")
+        };
 
-        // main loop
-        for i in 0..session.count {
-            println!("        {} / {}",i,session.count);
-            process(&mut rng,&ctx,&session,&backgrounds,&mut csv,i);
-        }        
+        // open TFRecord shards, appended to for append sessions
+        // a single instance goes into a file of its own, the shards already hold its examples
+        let mut records = match session.format {
+            SessionFormat::ProtoBuf => Some(match options.only {
+                Some(num) => TFRecordWriter::new(&format!("{}/{}_{:05}",session_dir(&session),session.name,num),1,false),
+                None => TFRecordWriter::new(&format!("{}/{}",session_dir(&session),session.name),session.shards,matches!(session.path,SessionPath::Append(_))),
+            }.expect("unable to create TFRecord files")),
+            _ => None,
+        };
+
+        // render workers each own a context and pull instance numbers, encoder threads encode and save the results
+        let session = Arc::new(session);
+        let backgrounds = Arc::new(backgrounds);
+        let (first,end) = if let Some(num) = options.only { (num,num + 1) } else { (0,session.count) };
+        let moving = match session.style {
+            SessionStyle::Moving | SessionStyle::MovingDepth(_,_) => true,
+            _ => false,
+        };
+        let next = Arc::new(AtomicUsize::new(first));
+        let in_flight = Arc::new(Mutex::new(BTreeSet::<usize>::new()));  // pulled by a render worker, but not rendered yet
+        let (frame_sender,frame_receiver) = mpsc::sync_channel::<Frame>(4 * session.workers);
+        let frame_receiver = Arc::new(Mutex::new(frame_receiver));
+        let (output_sender,output_receiver) = mpsc::channel::<Output>();
+        let mut threads: Vec<thread::JoinHandle<std::result::Result<(),String>>> = Vec::new();
+        let backend = options.backend;
+        for _ in 0..session.workers {
+            let session = session.clone();
+            let backgrounds = backgrounds.clone();
+            let next = next.clone();
+            let in_flight = in_flight.clone();
+            let frames = frame_sender.clone();
+            threads.push(thread::spawn(move || {
+                let mut ctx = Context::for_session(backend,&session)?;
+
+                // still images are pulled two batches at a time, so one batch is read back while the other renders, movies fill batches with their frames
+                let instances = if moving { 1 } else { 2 * session.batch };
+                loop {
+                    let mut nums: Vec<usize> = Vec::with_capacity(instances);
+                    while nums.len() < instances {
+                        let num = next.fetch_add(1,Ordering::SeqCst);
+                        if num >= end {
+                            break;
+                        }
+                        println!("        {} / {}",num,session.count);
+                        nums.push(num);
+                    }
+                    if nums.is_empty() {
+                        break;
+                    }
+                    in_flight.lock().unwrap().extend(nums.iter());
+                    process(&mut ctx,&session,&backgrounds,&frames,&nums,first,session_seed)?;
+                    let mut in_flight = in_flight.lock().unwrap();
+                    for num in &nums {
+                        in_flight.remove(num);
+                    }
+                }
+                Ok(())
+            }));
+        }
+        for _ in 0..session.workers {
+            let session = session.clone();
+            let frames = frame_receiver.clone();
+            let outputs = output_sender.clone();
+            threads.push(thread::spawn(move || {
+                loop {
+                    let frame = match frames.lock().unwrap().recv() {
+                        Ok(frame) => frame,
+                        Err(_) => break,  // all render workers are done
+                    };
+                    outputs.send(encode_frame(&session,frame)).expect("Unable to pass output to writer.");
+                }
+                Ok(())
+            }));
+        }
+        drop(frame_sender);
+        drop(output_sender);
+
+        // write CSV lines and records in instance order, whatever order they were finished in
+        let mut order = OutputOrder::new();
+        for output in output_receiver {
+            for output in order.push(output) {
+                csv.write_all(output.line.as_bytes()).expect("Unable to write to CSV file.");
+                if let (Some(writer),Some(record)) = (&mut records,&output.record) {
+                    writer.write(output.shard,record).expect("Unable to write TFRecord.");
+                }
+            }
+        }
+        let mut failed = 0;
+        for thread in threads {
+            match thread.join() {
+                Ok(Ok(())) => { },
+                Ok(Err(error)) => {
+                    eprintln!("{}: {}",session.name,error);
+                    failed += 1;
+                },
+                Err(_) => {
+                    failed += 1;
+                },
+            }
+        }
+        if failed > 0 {
+
+            // the instances of a failed render worker never arrive, so the writer stopped at the first of them
+            let mut lost: Vec<usize> = in_flight.lock().unwrap().iter().cloned().collect();
+            lost.extend(next.load(Ordering::SeqCst).min(end)..end);
+            eprintln!("{}: {} worker thread(s) failed",session.name,failed);
+            if !lost.is_empty() {
+                eprintln!("{}: instances not rendered: {:?}",session.name,lost);
+            }
+            if !order.pending.is_empty() {
+                eprintln!("{}: {} rendered CSV line(s) not written after the first {}",session.name,order.pending.len(),order.seq);
+            }
+            std::process::exit(1);
+        }
 
         // print projection parameters, if any
-        println!("    projection matrix:");
-        println!("        {:10.7} {:10.7} {:10.7} {:10.7}",session.projection.x.x,session.projection.x.y,session.projection.x.z,session.projection.x.w);
-        println!("        {:10.7} {:10.7} {:10.7} {:10.7}",session.projection.y.x,session.projection.y.y,session.projection.y.z,session.projection.y.w);
-        println!("        {:10.7} {:10.7} {:10.7} {:10.7}",session.projection.z.x,session.projection.z.y,session.projection.z.z,session.projection.z.w);
-        println!("        {:10.7} {:10.7} {:10.7} {:10.7}",session.projection.w.x,session.projection.w.y,session.projection.w.z,session.projection.w.w);
+        print_projection(&session);
+    }
+}
+
+// every XYZ, YPB and RGB block of a session, with its name
+fn session_blocks(session: &Session) -> Vec<(String,Vec<ComponentRange>)> {
+    let mut blocks: Vec<(String,Vec<ComponentRange>)> = vec![
+        ("head.pos".to_string(),session.head_pos.ranges()),
+        ("head.dir".to_string(),session.head_dir.ranges()),
+    ];
+    if let Some(vel) = &session.head_vel {
+        blocks.push(("head.vel".to_string(),vel.ranges()));
+    }
+    if let Some(angvel) = &session.head_angvel {
+        blocks.push(("head.angvel".to_string(),angvel.ranges()));
+    }
+    blocks.push(("lefteye".to_string(),session.lefteye.ranges()));
+    if let Some(angvel) = &session.lefteye_angvel {
+        blocks.push(("lefteye.angvel".to_string(),angvel.ranges()));
+    }
+    blocks.push(("righteye".to_string(),session.righteye.ranges()));
+    if let Some(angvel) = &session.righteye_angvel {
+        blocks.push(("righteye.angvel".to_string(),angvel.ranges()));
+    }
+    blocks.push(("light.dir".to_string(),session.light_dir.ranges()));
+    blocks.push(("light.color".to_string(),session.light_color.ranges()));
+    if let Some(angvel) = &session.light_angvel {
+        blocks.push(("light.angvel".to_string(),angvel.ranges()));
+    }
+    if let SessionBackground::Color(color) = &session.background {
+        blocks.push(("background.color".to_string(),color.ranges()));
+    }
+    blocks.push(("ambient".to_string(),session.ambient_color.ranges()));
+    blocks.push(("skin".to_string(),session.skin_color.ranges()));
+    blocks.push(("sclera".to_string(),session.sclera_color.ranges()));
+    blocks.push(("iris".to_string(),session.iris_color.ranges()));
+    blocks
+}
+
+// returns false if the sessions cannot be rendered
+fn validate(sessions: &Vec<Session>) -> bool {
+    let mut valid = true;
+    for session in sessions {
+        println!("session: {}",session.name);
+        for (name,ranges) in session_blocks(session) {
+            for range in ranges {
+                let warning = if range.color && ((range.min < 0.0) || (range.max > 1.0)) { "  (colors outside 0..1 are clamped)" } else { "" };
+                println!("    {}.{}: {} {} .. {}{}",name,range.name,range.kind,range.min,range.max,warning);
+            }
+        }
+
+        // the backgrounds are read like when rendering, so a set without an image as large as the render target fails here
+        if let Err(error) = load_backgrounds(session) {
+            println!("    error: {}",error);
+            valid = false;
+        }
+    }
+    valid
+}
+
+fn instance_json(session: &Session,num: usize,instance: &Instance,motion: Option<&InstanceMotion>) -> serde_json::Value {
+    let mut value = serde_json::json!({
+        "session": session.name,
+        "instance": num,
+        "seed": instance.seed,
+        "head_pos": [instance.head_pos.x,instance.head_pos.y,instance.head_pos.z],
+        "head_dir": [instance.head_dir.y,instance.head_dir.p,instance.head_dir.b],
+        "lefteye": [instance.lefteye.y,instance.lefteye.p,instance.lefteye.b],
+        "righteye": [instance.righteye.y,instance.righteye.p,instance.righteye.b],
+        "light_dir": [instance.light_dir.y,instance.light_dir.p,instance.light_dir.b],
+        "light_color": [instance.light_color.r,instance.light_color.g,instance.light_color.b],
+        "ambient_color": [instance.ambient_color.r,instance.ambient_color.g,instance.ambient_color.b],
+        "skin_color": [instance.skin_color.r,instance.skin_color.g,instance.skin_color.b],
+        "sclera_color": [instance.sclera_color.r,instance.sclera_color.g,instance.sclera_color.b],
+        "iris_color": [instance.iris_color.r,instance.iris_color.g,instance.iris_color.b],
+    });
+    if let InstanceBackground::Color(color) = &instance.background {
+        value["background_color"] = serde_json::json!([color.r,color.g,color.b]);
+    }
+    if let Some(motion) = motion {
+        value["head_vel"] = serde_json::json!([motion.head_vel.x,motion.head_vel.y,motion.head_vel.z]);
+        value["head_angvel"] = serde_json::json!([motion.head_angvel.y,motion.head_angvel.p,motion.head_angvel.b]);
+        value["lefteye_angvel"] = serde_json::json!([motion.lefteye_angvel.y,motion.lefteye_angvel.p,motion.lefteye_angvel.b]);
+        value["righteye_angvel"] = serde_json::json!([motion.righteye_angvel.y,motion.righteye_angvel.p,motion.righteye_angvel.b]);
+        value["light_angvel"] = serde_json::json!([motion.light_angvel.y,motion.light_angvel.p,motion.light_angvel.b]);
+    }
+    value
+}
+
+// the instance numbers to sample or preview
+fn instance_numbers(options: &Options) -> std::ops::Range<usize> {
+    if let Some(num) = options.only {
+        num..num + 1
+    }
+    else {
+        0..options.count.unwrap_or(1)
+    }
+}
+
+fn sample(options: &Options,sessions: &Vec<Session>) {
+    for session in sessions {
+
+        // instances do not depend on the backend, so the software backend is enough and needs no OpenGL
+        let mut ctx = Context::new(ContextBackend::Software,session.size,session.antialias,1,false).unwrap_or_else(|error| exit_with(session,&error));
+        let backgrounds = load_backgrounds(session).unwrap_or_else(|error| exit_with(session,&error));
+        let session_seed = session_seed(options,session);
+        for num in instance_numbers(options) {
+            let seed = instance_seed(session_seed,num);
+            let mut rng = ChaCha8Rng::seed_from_u64(seed);
+            let instance = build_instance(&mut rng,&mut ctx,session,&backgrounds,seed).unwrap_or_else(|error| exit_with(session,&error));
+            let motion = match session.style {
+                SessionStyle::Moving | SessionStyle::MovingDepth(_,_) => Some(build_motion(&mut rng,session,&instance)),
+                _ => None,
+            };
+            println!("{}",instance_json(session,num,&instance,motion.as_ref()));
+        }
+    }
+}
+
+fn preview(options: &Options,sessions: &Vec<Session>) {
+    match fs::create_dir_all(&options.out) { _ => { }, };
+    for session in sessions {
+        let mut ctx = Context::new(options.backend,session.size,session.antialias,1,false).unwrap_or_else(|error| exit_with(session,&error));
+        let backgrounds = load_backgrounds(session).unwrap_or_else(|error| exit_with(session,&error));
+        let session_seed = session_seed(options,session);
+        for num in instance_numbers(options) {
+            let seed = instance_seed(session_seed,num);
+            let mut rng = ChaCha8Rng::seed_from_u64(seed);
+            let instance = build_instance(&mut rng,&mut ctx,session,&backgrounds,seed).unwrap_or_else(|error| exit_with(session,&error));
+            let mut image = render_full(&mut ctx,session,&backgrounds,&instance);
+
+            // depth in the alpha channel is hard to look at
+            for pixel in image.data.iter_mut() {
+                pixel.a = 255;
+            }
+            let name = format!("{}/{}_{:05}.png",options.out,session.name,num);
+            let mut file = fs::File::create(&name).expect("Unable to create file.");
+            file.write_all(&encode_png(&image).expect("Unable to encode image as PNG.")).expect("Unable to write image.");
+            println!("{}",name);
+        }
+    }
+}
+
+fn info(sessions: &Vec<Session>) {
+    let mut total = 0;
+    for session in sessions {
+        println!("session: {}",session.name);
+        let images = match session.style {
+            SessionStyle::Still => {
+                println!("    {} images",session.count);
+                session.count
+            },
+            SessionStyle::StillDepth(scale,offset) => {
+                println!("    {} images with depth (scale {}, offset {})",session.count,scale,offset);
+                session.count
+            },
+            SessionStyle::Moving => {
+                println!("    {} movies of {} frames at {} fps",session.count,session.frames,session.fps);
+                session.count * session.frames
+            },
+            SessionStyle::MovingDepth(scale,offset) => {
+                println!("    {} movies of {} frames at {} fps with depth (scale {}, offset {})",session.count,session.frames,session.fps,scale,offset);
+                session.count * session.frames
+            },
+        };
+        total += images;
+        println!("    size: {}x{}",session.size.x,session.size.y);
+        let format = match session.format {
+            SessionFormat::BMP => "bmp".to_string(),
+            SessionFormat::PNG => "png".to_string(),
+            SessionFormat::PNG16 => "png16".to_string(),
+            SessionFormat::ProtoBuf => format!("protobuf in {} shards",session.shards),
+        };
+        match &session.path {
+            SessionPath::Replace(path) => println!("    output: {} to {} (replaced)",format,path),
+            SessionPath::Append(path) => println!("    output: {} to {} (appended)",format,path),
+        }
+        println!("    csv: {}",session.csv);
+        match session.seed {
+            Some(seed) => println!("    seed: {}",seed),
+            None => println!("    seed: random"),
+        }
+        println!("    workers: {}",session.workers);
+        println!("    batch: {}",session.batch);
+        match session.antialias {
+            Antialias::Supersample(1,_) => println!("    antialias: none"),
+            Antialias::Supersample(factor,filter) => println!("    antialias: {}x supersampling, {:?} filter",factor,filter),
+            Antialias::MSAA(samples) => println!("    antialias: {}x MSAA",samples),
+        }
+        let mut extras: Vec<&str> = Vec::new();
+        match session.depth {
+            SessionDepth::None => { },
+            SessionDepth::PNG16(_,_) => extras.push("depth map (png16)"),
+            SessionDepth::PFM => extras.push("depth map (pfm)"),
+            SessionDepth::Raw => extras.push("depth map (raw)"),
+        }
+        if session.mask {
+            extras.push("mask");
+        }
+        if session.landmarks {
+            extras.push("landmarks");
+        }
+        if session.boxes {
+            extras.push("boxes");
+        }
+        if !extras.is_empty() {
+            println!("    labels: {}",extras.join(", "));
+        }
+        match &session.background {
+            SessionBackground::Color(_) => println!("    background: color"),
+            SessionBackground::Image(path) => println!("    background: images from {}",path),
+        }
+        print_projection(session);
+    }
+    println!("total: {} images in {} sessions",total,sessions.len());
+}
+
+fn main() {
+    let options = if let Some(options) = parse_args() {
+        options
+    }
+    else {
+        usage();
+        std::process::exit(1);
+    };
+    let sessions = match load_config(&options.file,&options.overrides) {
+        Ok(sessions) => sessions,
+        Err(errors) => {
+            for error in &errors {
+                eprintln!("{}",error);
+            }
+            eprintln!("{}: {} error(s)",options.file,errors.len());
+            std::process::exit(1);
+        },
+    };
+    match options.command {
+        Command::Render => render(&options,sessions),
+        Command::Validate => {
+            if validate(&sessions) {
+                println!("{}: ok",options.file);
+            }
+            else {
+                std::process::exit(1);
+            }
+        },
+        Command::Sample => sample(&options,&sessions),
+        Command::Preview => preview(&options,&sessions),
+        Command::Info => info(&sessions),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load_session(name: &str,yaml: &str) -> Session {
+        let file = std::env::temp_dir().join(format!("commedia_{}.yaml",name));
+        fs::write(&file,yaml).unwrap();
+        let sessions = load_config(file.to_str().unwrap(),&[]).unwrap();
+        fs::remove_file(&file).unwrap();
+        sessions.into_iter().next().unwrap()
+    }
+
+    // the image or frames of instance 0, rendered on the CPU
+    fn render_instance(session: &Session) -> Vec<(Image<ARGB8>,Labels)> {
+        let mut ctx = Context::for_session(ContextBackend::Software,session).unwrap();
+        let seed = instance_seed(1,0);
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+        let instance = build_instance(&mut rng,&mut ctx,session,&Vec::new(),seed).unwrap();
+        let jobs = instance_jobs(&mut rng,session,0,instance);
+        render_samples(&mut ctx,session,&Vec::new(),&jobs)
+    }
+
+    fn split_columns(line: &str) -> Vec<String> {
+        line.trim_end().split(',').map(|column| column.trim().to_string()).collect()
+    }
+
+    #[test]
+    fn csv_columns() {
+
+        // name, head position, head direction, NDC, screen, light direction and color, ambient, skin, then both eyes and the seed
+        let session = load_session("csv_columns","s:\n  size: 32,24\n  antialias: none\n  head:\n    pos:\n      z: -0.5\n  lefteye:\n    y: 5\n");
+        let (_,labels) = render_instance(&session).remove(0);
+        let line = csv_line(&session,"00000.bmp",&labels);
+        assert!(line.ends_with('\n'));
+        let columns = split_columns(&line);
+        assert_eq!(columns.len(),22 + 2 * 11 + 1);
+        assert_eq!(columns[0],"\"00000.bmp\"");
+        assert_eq!(columns[3],"-0.5");
+        assert_eq!(columns[22],"5");
+        assert_eq!(columns[25],labels.lefteye.center.x.to_string());
+        assert_eq!(columns[33],labels.righteye.center.x.to_string());
+        assert_eq!(columns[44],labels.instance.seed.to_string());
+
+        // boxes and landmarks go between the older columns and the eyes
+        let session = load_session("csv_columns_labels","s:\n  size: 32,24\n  antialias: none\n  boxes: true\n  landmarks: true\n  head:\n    pos:\n      z: -0.5\n  lefteye:\n    y: 5\n");
+        let (_,labels) = render_instance(&session).remove(0);
+        let columns = split_columns(&csv_line(&session,"00000.bmp",&labels));
+        let eyes = 22 + 3 * 4 + 4 * labels.landmarks.len();
+        assert_eq!(columns.len(),eyes + 2 * 11 + 1);
+        assert_eq!(columns[eyes],"5");
+        assert_eq!(columns[eyes + 22],labels.instance.seed.to_string());
+    }
+
+    fn output(seq: usize) -> Output {
+        Output {
+            seq: seq,
+            shard: 0,
+            line: format!("{}\n",seq),
+            record: None,
+        }
+    }
+
+    #[test]
+    fn output_order() {
+        let mut order = OutputOrder::new();
+        assert!(order.push(output(2)).is_empty());
+        assert_eq!(order.push(output(0)).iter().map(|output| output.seq).collect::<Vec<usize>>(),vec![0]);
+        assert_eq!(order.push(output(1)).iter().map(|output| output.seq).collect::<Vec<usize>>(),vec![1,2]);
+        assert!(order.push(output(4)).is_empty());
+        assert_eq!((order.pending.len(),order.seq),(1,3));
+    }
+
+    #[test]
+    fn process_positions() {
+
+        // still images take the position of their instance, counted from the first instance of the run
+        let (sender,receiver) = mpsc::sync_channel::<Frame>(16);
+        let session = load_session("process_still","s:\n  size: 32,24\n  antialias: none\n  head:\n    pos:\n      z: -0.5\n");
+        let mut ctx = Context::for_session(ContextBackend::Software,&session).unwrap();
+        process(&mut ctx,&session,&Vec::new(),&sender,&[5,3],3,1).unwrap();
+        let frames: Vec<(usize,String)> = receiver.try_iter().map(|frame| (frame.seq,frame.stem)).collect();
+        assert_eq!(frames,vec![(2,"00005".to_string()),(0,"00003".to_string())]);
+
+        // frames of a movie follow each other
+        let dir = std::env::temp_dir().join("commedia_process_moving");
+        fs::create_dir_all(&dir).unwrap();
+        let session = load_session("process_moving",&format!("s:\n  path: replace {}\n  style: moving\n  frames: 2\n  size: 32,24\n  antialias: none\n  head:\n    pos:\n      z: -0.5\n",dir.to_str().unwrap()));
+        let mut ctx = Context::for_session(ContextBackend::Software,&session).unwrap();
+        process(&mut ctx,&session,&Vec::new(),&sender,&[4],3,1).unwrap();
+        let frames: Vec<(usize,String)> = receiver.try_iter().map(|frame| (frame.seq,frame.stem)).collect();
+        assert_eq!(frames,vec![(2,"00004/00000".to_string()),(3,"00004/00001".to_string())]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn validate_sessions() {
+        let session = load_session("validate_sessions","s:\n  head:\n    vel:\n      x: 0.1\n  skin:\n    r: uniform 0.5,1.5\n");
+        let blocks = session_blocks(&session);
+        assert_eq!(blocks.iter().map(|(name,_)| name.as_str()).collect::<Vec<&str>>(),vec![
+            "head.pos","head.dir","head.vel","lefteye","righteye","light.dir","light.color","background.color","ambient","skin","sclera","iris",
+        ]);
+        let skin = &blocks[9].1[0];
+        assert_eq!((skin.name,skin.kind,skin.min,skin.max,skin.color),("r","uniform",0.5,1.5,true));
+        assert!(validate(&vec![session]));
+
+        // a background directory that cannot be read fails validation
+        let dir = std::env::temp_dir().join("commedia_validate_missing");
+        let session = load_session("validate_missing",&format!("s:\n  background: image {}/\n",dir.to_str().unwrap()));
+        assert!(!validate(&vec![session]));
     }
 }
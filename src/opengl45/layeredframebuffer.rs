@@ -0,0 +1,165 @@
+// (C) Copyright 2020, by Germans Media Technology & Services
+// Commedia
+// OpenGL 4.5 Layered Framebuffer, on 2D array textures
+
+use crate::math::*;
+use std::os::raw::c_void;
+use gl::types::{GLint,GLsizei,GLuint};
+use crate::*;
+
+// the resolved layers, color has 16 bits per channel so the alpha channel keeps the precision of the resolve
+pub struct LayeredFramebuffer {
+    pub size: usizexy,
+    pub layers: usize,
+    layer: usize,  // layer attached to the framebuffer object
+    tex: GLuint,
+    depth_map_tex: GLuint,  // optional linear depth, 0 if not present
+    depth_tex: GLuint,  // window depth, as a texture so it can be read back per layer
+    fbo: GLuint,
+}
+
+unsafe fn create_array(internal_format: GLuint,format: GLuint,data_type: GLuint,filter: GLuint,size: usizexy,layers: usize) -> GLuint {
+    let mut tex: GLuint = 0;
+    gl::GenTextures(1,&mut tex);
+    gl::BindTexture(gl::TEXTURE_2D_ARRAY,tex);
+    gl::TexParameteri(gl::TEXTURE_2D_ARRAY,gl::TEXTURE_WRAP_S,gl::CLAMP_TO_EDGE as i32);
+    gl::TexParameteri(gl::TEXTURE_2D_ARRAY,gl::TEXTURE_WRAP_T,gl::CLAMP_TO_EDGE as i32);
+    gl::TexParameteri(gl::TEXTURE_2D_ARRAY,gl::TEXTURE_MIN_FILTER,filter as i32);
+    gl::TexParameteri(gl::TEXTURE_2D_ARRAY,gl::TEXTURE_MAG_FILTER,filter as i32);
+    gl::TexImage3D(gl::TEXTURE_2D_ARRAY,0,internal_format as GLint,size.x as i32,size.y as i32,layers as i32,0,format,data_type,0 as *const c_void);
+    tex
+}
+
+impl LayeredFramebuffer {
+    pub fn new(size: usizexy,layers: usize,float_depth: bool) -> std::result::Result<LayeredFramebuffer,()> {
+        unsafe {
+            let mut fbo: GLuint = 0;
+            gl::GenFramebuffers(1,&mut fbo);
+            gl::BindFramebuffer(gl::FRAMEBUFFER,fbo);
+            let tex = create_array(gl::RGBA16,gl::RGBA,gl::UNSIGNED_SHORT,gl::NEAREST,size,layers);
+            let depth_map_tex = if float_depth { create_array(gl::R32F,gl::RED,gl::FLOAT,gl::NEAREST,size,layers) } else { 0 };
+            let depth_tex = create_array(gl::DEPTH_COMPONENT16,gl::DEPTH_COMPONENT,gl::UNSIGNED_SHORT,gl::NEAREST,size,layers);
+            let framebuffer = LayeredFramebuffer {
+                size: size,
+                layers: layers,
+                layer: 0,
+                tex: tex,
+                depth_map_tex: depth_map_tex,
+                depth_tex: depth_tex,
+                fbo: fbo,
+            };
+            framebuffer.attach();
+            if float_depth {
+                let buffers = [gl::COLOR_ATTACHMENT0,gl::COLOR_ATTACHMENT1];
+                gl::DrawBuffers(2,buffers.as_ptr());
+            }
+            if gl::CheckFramebufferStatus(gl::FRAMEBUFFER) != gl::FRAMEBUFFER_COMPLETE {
+                return Err(());
+            }
+            gl::BindFramebuffer(gl::FRAMEBUFFER,0);
+            Ok(framebuffer)
+        }
+    }
+
+    // attach the selected layer of each texture
+    fn attach(&self) {
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER,self.fbo);
+            gl::FramebufferTextureLayer(gl::FRAMEBUFFER,gl::COLOR_ATTACHMENT0,self.tex,0,self.layer as GLint);
+            if self.depth_map_tex != 0 {
+                gl::FramebufferTextureLayer(gl::FRAMEBUFFER,gl::COLOR_ATTACHMENT1,self.depth_map_tex,0,self.layer as GLint);
+            }
+            gl::FramebufferTextureLayer(gl::FRAMEBUFFER,gl::DEPTH_ATTACHMENT,self.depth_tex,0,self.layer as GLint);
+        }
+    }
+
+    pub fn select(&mut self,layer: usize) {
+        self.layer = layer;
+        self.attach();
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER,0);
+        }
+    }
+
+    pub fn has_depth_map(&self) -> bool {
+        self.depth_map_tex != 0
+    }
+
+    pub fn fbo(&self) -> GLuint {
+        self.fbo
+    }
+
+    pub fn bind(&self) {
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER,self.fbo);
+            gl::Viewport(0,0,self.size.x as i32,self.size.y as i32);
+            gl::Scissor(0,0,self.size.x as i32,self.size.y as i32);
+        }
+    }
+
+    pub fn unbind(&self) {
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER,0);
+        }
+    }
+
+    // average the samples of multisampled color of the same size into the selected layer
+    pub fn blit_color(&self,src: GLuint) {
+        unsafe {
+            gl::NamedFramebufferReadBuffer(src,gl::COLOR_ATTACHMENT0);
+            gl::NamedFramebufferDrawBuffer(self.fbo,gl::COLOR_ATTACHMENT0);
+            gl::BlitNamedFramebuffer(src,self.fbo,0,0,self.size.x as GLint,self.size.y as GLint,0,0,self.size.x as GLint,self.size.y as GLint,gl::COLOR_BUFFER_BIT,gl::NEAREST);
+            if self.depth_map_tex != 0 {
+                let buffers = [gl::COLOR_ATTACHMENT0,gl::COLOR_ATTACHMENT1];
+                gl::NamedFramebufferDrawBuffers(self.fbo,2,buffers.as_ptr());
+            }
+        }
+    }
+
+    // read back color of the selected layer
+    pub fn grab(&self) -> Image<ARGB8> {
+        let mut data = vec![0u16; self.size.x * self.size.y * 4];
+        unsafe {
+            gl::GetTextureSubImage(self.tex,0,0,0,self.layer as GLint,self.size.x as GLsizei,self.size.y as GLsizei,1,gl::RGBA,gl::UNSIGNED_SHORT,(data.len() * 2) as GLsizei,data.as_mut_ptr() as *mut c_void);
+        }
+        split_rgba16(self.size,&data).0
+    }
+
+    // read layers 0..count of color, linear depth or window depth into the bound pixel buffer, at offset, returns the number of bytes
+    pub fn read_color(&self,count: usize,offset: usize) -> usize {
+        let bytes = count * self.size.x * self.size.y * 8;
+        unsafe {
+            gl::GetTextureSubImage(self.tex,0,0,0,0,self.size.x as GLsizei,self.size.y as GLsizei,count as GLsizei,gl::RGBA,gl::UNSIGNED_SHORT,bytes as GLsizei,offset as *mut c_void);
+        }
+        bytes
+    }
+
+    pub fn read_depth_map(&self,count: usize,offset: usize) -> usize {
+        let bytes = count * self.size.x * self.size.y * 4;
+        unsafe {
+            gl::GetTextureSubImage(self.depth_map_tex,0,0,0,0,self.size.x as GLsizei,self.size.y as GLsizei,count as GLsizei,gl::RED,gl::FLOAT,bytes as GLsizei,offset as *mut c_void);
+        }
+        bytes
+    }
+
+    pub fn read_depth(&self,count: usize,offset: usize) -> usize {
+        let bytes = count * self.size.x * self.size.y * 4;
+        unsafe {
+            gl::GetTextureSubImage(self.depth_tex,0,0,0,0,self.size.x as GLsizei,self.size.y as GLsizei,count as GLsizei,gl::DEPTH_COMPONENT,gl::FLOAT,bytes as GLsizei,offset as *mut c_void);
+        }
+        bytes
+    }
+}
+
+impl Drop for LayeredFramebuffer {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteFramebuffers(1,&self.fbo);
+            gl::DeleteTextures(1,&self.tex);
+            gl::DeleteTextures(1,&self.depth_tex);
+            if self.depth_map_tex != 0 {
+                gl::DeleteTextures(1,&self.depth_map_tex);
+            }
+        }
+    }
+}
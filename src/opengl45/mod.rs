@@ -11,5 +11,14 @@ pub use texture2d::*;
 mod framebuffer;
 pub use framebuffer::*;
 
+mod layeredframebuffer;
+pub use layeredframebuffer::*;
+
+mod targetframebuffer;
+pub use targetframebuffer::*;
+
+mod pixelbuffer;
+pub use pixelbuffer::*;
+
 mod vertexbuffer;
 pub use vertexbuffer::*;
\ No newline at end of file
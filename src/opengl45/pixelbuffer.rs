@@ -0,0 +1,105 @@
+// (C) Copyright 2020, by Germans Media Technology & Services
+// Commedia
+// OpenGL 4.5 Pixel Buffer, for asynchronous read back
+
+use std::{mem,ptr,slice};
+use gl::types::{GLsizeiptr,GLsync,GLuint};
+
+pub struct PixelBuffer {
+    pub size: usize,  // in bytes
+    pbo: GLuint,
+    fence: GLsync,  // signaled when the reads into the buffer are done, null if none pending
+}
+
+impl PixelBuffer {
+    pub fn new(size: usize) -> PixelBuffer {
+        let mut pbo: GLuint = 0;
+        unsafe {
+            gl::GenBuffers(1,&mut pbo);
+            gl::BindBuffer(gl::PIXEL_PACK_BUFFER,pbo);
+            gl::BufferData(gl::PIXEL_PACK_BUFFER,size as GLsizeiptr,ptr::null(),gl::STREAM_READ);
+            gl::BindBuffer(gl::PIXEL_PACK_BUFFER,0);
+        }
+        PixelBuffer {
+            size: size,
+            pbo: pbo,
+            fence: ptr::null(),
+        }
+    }
+
+    // reads of pixels go into this buffer while it is bound
+    pub fn bind(&self) {
+        unsafe {
+            gl::BindBuffer(gl::PIXEL_PACK_BUFFER,self.pbo);
+        }
+    }
+
+    pub fn unbind(&self) {
+        unsafe {
+            gl::BindBuffer(gl::PIXEL_PACK_BUFFER,0);
+        }
+    }
+
+    // mark the end of the reads into this buffer, without waiting for them
+    pub fn fence(&mut self) {
+        unsafe {
+            self.fence = gl::FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE,0);
+            gl::Flush();
+        }
+    }
+
+    // wait until the reads are done
+    pub fn wait(&mut self) {
+        if self.fence.is_null() {
+            return;
+        }
+        unsafe {
+            loop {
+                let result = gl::ClientWaitSync(self.fence,gl::SYNC_FLUSH_COMMANDS_BIT,1_000_000_000);
+                if (result == gl::ALREADY_SIGNALED) || (result == gl::CONDITION_SATISFIED) {
+                    break;
+                }
+                if result == gl::WAIT_FAILED {
+                    panic!("Unable to wait for read back.");
+                }
+            }
+            gl::DeleteSync(self.fence);
+        }
+        self.fence = ptr::null();
+    }
+
+    // map the whole buffer once and hand the bytes to f, after wait
+    pub fn map<R,F: FnOnce(&[u8]) -> R>(&self,f: F) -> R {
+        unsafe {
+            gl::BindBuffer(gl::PIXEL_PACK_BUFFER,self.pbo);
+            let src = gl::MapBufferRange(gl::PIXEL_PACK_BUFFER,0,self.size as GLsizeiptr,gl::MAP_READ_BIT);
+            if src.is_null() {
+                panic!("Unable to map read back buffer.");
+            }
+            let result = f(slice::from_raw_parts(src as *const u8,self.size));
+            gl::UnmapBuffer(gl::PIXEL_PACK_BUFFER);
+            gl::BindBuffer(gl::PIXEL_PACK_BUFFER,0);
+            result
+        }
+    }
+}
+
+// copy bytes of a mapped buffer into typed values, the source need not be aligned
+pub fn copy_mapped<T: Copy>(src: &[u8],offset: usize,dst: &mut [T]) {
+    let bytes = dst.len() * mem::size_of::<T>();
+    let src = &src[offset..offset + bytes];
+    unsafe {
+        ptr::copy_nonoverlapping(src.as_ptr(),dst.as_mut_ptr() as *mut u8,bytes);
+    }
+}
+
+impl Drop for PixelBuffer {
+    fn drop(&mut self) {
+        unsafe {
+            if !self.fence.is_null() {
+                gl::DeleteSync(self.fence);
+            }
+            gl::DeleteBuffers(1,&self.pbo);
+        }
+    }
+}
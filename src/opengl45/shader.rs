@@ -216,3 +216,21 @@ impl SetUniform<f32rgb> for ShaderProgram {
         self.set_uniform(name,f32xyzw { x: value.r,y: value.g,z: value.b,w: 1.0, });
     }
 }
+
+impl SetUniform<i32> for ShaderProgram {
+    fn set_uniform(&self,name: &str,value: i32) {
+        let cname = CString::new(name).unwrap();
+        unsafe {
+            gl::Uniform1i(gl::GetUniformLocation(self.sp,cname.as_ptr() as *const GLchar),value as GLint);
+        }
+    }
+}
+
+impl SetUniform<&[f32]> for ShaderProgram {
+    fn set_uniform(&self,name: &str,value: &[f32]) {
+        let cname = CString::new(name).unwrap();
+        unsafe {
+            gl::Uniform1fv(gl::GetUniformLocation(self.sp,cname.as_ptr() as *const GLchar),value.len() as GLint,value.as_ptr() as *const GLfloat);
+        }
+    }
+}
@@ -0,0 +1,140 @@
+// (C) Copyright 2020, by Germans Media Technology & Services
+// Commedia
+// OpenGL 4.5 Target Framebuffer, supersampled or multisampled, to resolve from
+
+use crate::math::*;
+use std::os::raw::c_void;
+use gl::types::{GLenum,GLint,GLuint};
+use crate::*;
+
+pub struct TargetFramebuffer {
+    pub size: usizexy,
+    pub samples: usize,  // per pixel, 1 if not multisampled
+    target: GLenum,  // TEXTURE_2D or TEXTURE_2D_MULTISAMPLE
+    tex: GLuint,
+    depth_map_tex: GLuint,  // optional linear depth, 0 if not present
+    depth_tex: GLuint,  // window depth, as a texture so it can be resolved
+    fbo: GLuint,
+}
+
+unsafe fn create_texture(target: GLenum,internal_format: GLuint,format: GLuint,data_type: GLuint,size: usizexy,samples: usize) -> GLuint {
+    let mut tex: GLuint = 0;
+    gl::GenTextures(1,&mut tex);
+    gl::BindTexture(target,tex);
+    if target == gl::TEXTURE_2D_MULTISAMPLE {
+        gl::TexImage2DMultisample(target,samples as i32,internal_format,size.x as i32,size.y as i32,gl::TRUE);
+    }
+    else {
+        gl::TexParameteri(target,gl::TEXTURE_WRAP_S,gl::CLAMP_TO_EDGE as i32);
+        gl::TexParameteri(target,gl::TEXTURE_WRAP_T,gl::CLAMP_TO_EDGE as i32);
+        gl::TexParameteri(target,gl::TEXTURE_MIN_FILTER,gl::NEAREST as i32);
+        gl::TexParameteri(target,gl::TEXTURE_MAG_FILTER,gl::NEAREST as i32);
+        gl::TexImage2D(target,0,internal_format as GLint,size.x as i32,size.y as i32,0,format,data_type,0 as *const c_void);
+    }
+    tex
+}
+
+impl TargetFramebuffer {
+    pub fn new(size: usizexy,samples: usize,float_depth: bool) -> std::result::Result<TargetFramebuffer,()> {
+        let target = if samples > 1 { gl::TEXTURE_2D_MULTISAMPLE } else { gl::TEXTURE_2D };
+        unsafe {
+            let mut fbo: GLuint = 0;
+            gl::GenFramebuffers(1,&mut fbo);
+            gl::BindFramebuffer(gl::FRAMEBUFFER,fbo);
+
+            // 16 bits per channel, so the depth in the alpha channel is not quantized to 8 bits before the resolve, and multisampled color can be blitted into the layers
+            let tex = create_texture(target,gl::RGBA16,gl::RGBA,gl::UNSIGNED_SHORT,size,samples);
+            gl::FramebufferTexture(gl::FRAMEBUFFER,gl::COLOR_ATTACHMENT0,tex,0);
+            let depth_map_tex = if float_depth {
+                let depth_map_tex = create_texture(target,gl::R32F,gl::RED,gl::FLOAT,size,samples);
+                gl::FramebufferTexture(gl::FRAMEBUFFER,gl::COLOR_ATTACHMENT1,depth_map_tex,0);
+                let buffers = [gl::COLOR_ATTACHMENT0,gl::COLOR_ATTACHMENT1];
+                gl::DrawBuffers(2,buffers.as_ptr());
+                depth_map_tex
+            }
+            else {
+                0
+            };
+            let depth_tex = create_texture(target,gl::DEPTH_COMPONENT16,gl::DEPTH_COMPONENT,gl::UNSIGNED_SHORT,size,samples);
+            gl::FramebufferTexture(gl::FRAMEBUFFER,gl::DEPTH_ATTACHMENT,depth_tex,0);
+            if gl::CheckFramebufferStatus(gl::FRAMEBUFFER) != gl::FRAMEBUFFER_COMPLETE {
+                return Err(());
+            }
+            gl::BindFramebuffer(gl::FRAMEBUFFER,0);
+            Ok(TargetFramebuffer {
+                size: size,
+                samples: samples,
+                target: target,
+                tex: tex,
+                depth_map_tex: depth_map_tex,
+                depth_tex: depth_tex,
+                fbo: fbo,
+            })
+        }
+    }
+
+    pub fn has_depth_map(&self) -> bool {
+        self.depth_map_tex != 0
+    }
+
+    pub fn fbo(&self) -> GLuint {
+        self.fbo
+    }
+
+    pub fn bind(&self) {
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER,self.fbo);
+            gl::Viewport(0,0,self.size.x as i32,self.size.y as i32);
+            gl::Scissor(0,0,self.size.x as i32,self.size.y as i32);
+        }
+    }
+
+    pub fn unbind(&self) {
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER,0);
+        }
+    }
+
+    pub fn clear_depth_map(&self) {
+        if self.depth_map_tex != 0 {
+            let zero = [0.0f32; 4];
+            unsafe {
+                gl::ClearBufferfv(gl::COLOR,1,zero.as_ptr());
+            }
+        }
+    }
+
+    // bind color, linear depth and window depth to texture units 0, 1 and 2, for the resolve shader
+    pub fn bind_textures(&self) {
+        unsafe {
+            gl::BindTextureUnit(0,self.tex);
+            gl::BindTextureUnit(1,self.depth_map_tex);
+            gl::BindTextureUnit(2,self.depth_tex);
+        }
+    }
+
+    pub fn unbind_textures(&self) {
+        unsafe {
+            gl::BindTextureUnit(0,0);
+            gl::BindTextureUnit(1,0);
+            gl::BindTextureUnit(2,0);
+        }
+    }
+
+    pub fn is_multisampled(&self) -> bool {
+        self.target == gl::TEXTURE_2D_MULTISAMPLE
+    }
+}
+
+impl Drop for TargetFramebuffer {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteFramebuffers(1,&self.fbo);
+            gl::DeleteTextures(1,&self.tex);
+            gl::DeleteTextures(1,&self.depth_tex);
+            if self.depth_map_tex != 0 {
+                gl::DeleteTextures(1,&self.depth_map_tex);
+            }
+        }
+    }
+}
@@ -20,7 +20,7 @@ pub trait GlPixelParams {
 }
 
 impl GlPixelParams for ARGB8 {
-    fn gl_internal_format() -> GLuint { gl::RGBA as GLuint }
+    fn gl_internal_format() -> GLuint { gl::RGBA8 as GLuint }
     fn gl_format() -> GLenum { gl::BGRA }
     fn gl_type() -> GLenum { gl::UNSIGNED_INT_8_8_8_8_REV }
 }
@@ -66,6 +66,13 @@ impl<T: Pixel + GlPixelParams> Texture2D<T> {
             gl::BindTexture(gl::TEXTURE_2D,self.tex);
         }
     }
+
+    // replace the contents with an image of the same size
+    pub fn set(&self,image: &Image<T>) {
+        unsafe {
+            gl::TextureSubImage2D(self.tex,0,0,0,image.size.x as GLint,image.size.y as GLint,<T>::gl_format(),<T>::gl_type(),image.data.as_ptr() as *const c_void);
+        }
+    }
 }
 
 impl<T> Drop for Texture2D<T> {
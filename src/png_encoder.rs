@@ -0,0 +1,129 @@
+// (C) Copyright 2020, by Germans Media Technology & Services
+// Commedia
+// PNG encoder
+
+use crate::*;
+
+fn encode(size: usizexy,color: ::png::ColorType,depth: ::png::BitDepth,data: &[u8]) -> std::result::Result<Vec<u8>,::png::EncodingError> {
+    let mut buffer: Vec<u8> = Vec::new();
+    {
+        let mut encoder = ::png::Encoder::new(&mut buffer,size.x as u32,size.y as u32);
+        encoder.set_color(color);
+        encoder.set_depth(depth);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(data)?;
+    }
+    Ok(buffer)
+}
+
+pub fn encode_png(image: &Image<ARGB8>) -> std::result::Result<Vec<u8>,::png::EncodingError> {
+    let mut data: Vec<u8> = Vec::with_capacity(image.size.x * image.size.y * 4);
+    for y in (0..image.size.y).rev() {  // framebuffer images are bottom-up, PNG is top-down
+        for x in 0..image.size.x {
+            let pix = image.pixel(usizexy { x: x,y: y, });
+            data.push(pix.r);
+            data.push(pix.g);
+            data.push(pix.b);
+            data.push(pix.a);
+        }
+    }
+    encode(image.size,::png::ColorType::Rgba,::png::BitDepth::Eight,&data)
+}
+
+pub fn encode_png16(image: &Image<ARGB8>,depth: &Vec<u16>) -> std::result::Result<Vec<u8>,::png::EncodingError> {
+    let mut data: Vec<u8> = Vec::with_capacity(image.size.x * image.size.y * 8);
+    for y in (0..image.size.y).rev() {  // framebuffer images are bottom-up, PNG is top-down
+        for x in 0..image.size.x {
+            let pix = image.pixel(usizexy { x: x,y: y, });
+            data.extend_from_slice(&((pix.r as u16) * 257).to_be_bytes());
+            data.extend_from_slice(&((pix.g as u16) * 257).to_be_bytes());
+            data.extend_from_slice(&((pix.b as u16) * 257).to_be_bytes());
+            data.extend_from_slice(&depth[y * image.size.x + x].to_be_bytes());
+        }
+    }
+    encode(image.size,::png::ColorType::Rgba,::png::BitDepth::Sixteen,&data)
+}
+
+pub fn encode_png16_gray(size: usizexy,values: &Vec<u16>) -> std::result::Result<Vec<u8>,::png::EncodingError> {
+    let mut data: Vec<u8> = Vec::with_capacity(size.x * size.y * 2);
+    for y in (0..size.y).rev() {  // framebuffer images are bottom-up, PNG is top-down
+        for x in 0..size.x {
+            data.extend_from_slice(&values[y * size.x + x].to_be_bytes());
+        }
+    }
+    encode(size,::png::ColorType::Grayscale,::png::BitDepth::Sixteen,&data)
+}
+
+pub fn encode_png_indexed(size: usizexy,indices: &Vec<u8>,palette: &[u8]) -> std::result::Result<Vec<u8>,::png::EncodingError> {
+    let mut data: Vec<u8> = Vec::with_capacity(size.x * size.y);
+    for y in (0..size.y).rev() {  // framebuffer images are bottom-up, PNG is top-down
+        data.extend_from_slice(&indices[y * size.x..(y + 1) * size.x]);
+    }
+    let mut buffer: Vec<u8> = Vec::new();
+    {
+        let mut encoder = ::png::Encoder::new(&mut buffer,size.x as u32,size.y as u32);
+        encoder.set_color(::png::ColorType::Indexed);
+        encoder.set_depth(::png::BitDepth::Eight);
+        encoder.set_palette(palette.to_vec());
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&data)?;
+    }
+    Ok(buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(bytes: &[u8]) -> (::png::OutputInfo,Vec<u8>) {
+        let mut reader = ::png::Decoder::new(bytes).read_info().unwrap();
+        let mut data = vec![0u8; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut data).unwrap();
+        data.truncate(info.buffer_size());
+        (info,data)
+    }
+
+    fn image() -> Image<ARGB8> {
+
+        // 2x2, bottom row first
+        let mut image = Image::<ARGB8>::new(usizexy { x: 2,y: 2, });
+        image.data = vec![ARGB8::new_rgba(1,2,3,4),ARGB8::new_rgba(5,6,7,8),ARGB8::new_rgba(9,10,11,12),ARGB8::new_rgba(13,14,15,16)];
+        image
+    }
+
+    #[test]
+    fn png_flipped() {
+        let (info,data) = decode(&encode_png(&image()).unwrap());
+        assert_eq!((info.width,info.height,info.color_type,info.bit_depth),(2,2,::png::ColorType::Rgba,::png::BitDepth::Eight));
+        assert_eq!(data,vec![9,10,11,12,13,14,15,16,1,2,3,4,5,6,7,8]);
+    }
+
+    #[test]
+    fn png16_depth_plane() {
+
+        // color is widened to 16 bits, the depth goes into alpha at full precision
+        let (info,data) = decode(&encode_png16(&image(),&vec![0x0102,0x0304,0x0506,0xFFFE]).unwrap());
+        assert_eq!((info.width,info.height,info.color_type,info.bit_depth),(2,2,::png::ColorType::Rgba,::png::BitDepth::Sixteen));
+        assert_eq!(data.len(),2 * 2 * 8);
+        assert_eq!(&data[0..8],&[9,9,10,10,11,11,0x05,0x06]);
+        assert_eq!(&data[8..16],&[13,13,14,14,15,15,0xFF,0xFE]);
+        assert_eq!(&data[16..24],&[1,1,2,2,3,3,0x01,0x02]);
+    }
+
+    #[test]
+    fn png16_gray_depth_map() {
+        let (info,data) = decode(&encode_png16_gray(usizexy { x: 3,y: 2, },&vec![1,2,3,0x0400,0x0500,0xFFFF]).unwrap());
+        assert_eq!((info.width,info.height,info.color_type,info.bit_depth),(3,2,::png::ColorType::Grayscale,::png::BitDepth::Sixteen));
+        assert_eq!(data,vec![0x04,0x00,0x05,0x00,0xFF,0xFF,0,1,0,2,0,3]);
+    }
+
+    #[test]
+    fn png_indexed_mask() {
+        let png = encode_png_indexed(usizexy { x: 2,y: 2, },&vec![0,1,2,4],&SEGMENT_PALETTE).unwrap();
+        let reader = ::png::Decoder::new(&png[..]).read_info().unwrap();
+        assert_eq!(reader.info().palette.as_deref(),Some(&SEGMENT_PALETTE[..]));
+        let (info,data) = decode(&png);
+        assert_eq!((info.width,info.height,info.color_type,info.bit_depth),(2,2,::png::ColorType::Indexed,::png::BitDepth::Eight));
+        assert_eq!(data,vec![2,4,0,1]);
+    }
+}
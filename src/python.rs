@@ -0,0 +1,230 @@
+// (C) Copyright 2020, by Germans Media Technology & Services
+// Commedia
+// Python module, to render faces on the fly from a training harness
+
+use pyo3::prelude::*;
+use pyo3::exceptions::{PyRuntimeError,PyValueError};
+use pyo3::types::PyDict;
+use numpy::PyArray1;
+
+use crate::*;
+
+// framebuffer rows are bottom-up, NumPy arrays are top-down
+fn flip_rows<T: Copy>(size: usizexy,data: &[T]) -> Vec<T> {
+    let mut dst: Vec<T> = Vec::with_capacity(data.len());
+    for y in (0..size.y).rev() {
+        dst.extend_from_slice(&data[y * size.x..(y + 1) * size.x]);
+    }
+    dst
+}
+
+// append the image as top-down RGBA bytes
+fn push_image(data: &mut Vec<u8>,image: &Image<ARGB8>) {
+    for y in (0..image.size.y).rev() {
+        for x in 0..image.size.x {
+            let pix = image.pixel(usizexy { x: x,y: y, });
+            data.push(pix.r);
+            data.push(pix.g);
+            data.push(pix.b);
+            data.push(pix.a);
+        }
+    }
+}
+
+// overrides are given like --set, as key=value
+fn parse_overrides(overrides: &[String]) -> std::result::Result<Vec<(String,String)>,String> {
+    let mut pairs: Vec<(String,String)> = Vec::new();
+    for item in overrides {
+        match item.find('=') {
+            Some(index) => pairs.push((item[..index].to_string(),item[index + 1..].to_string())),
+            None => {
+                return Err(format!("invalid override {} (should be key=value)",item));
+            },
+        }
+    }
+    Ok(pairs)
+}
+
+// the same features as the TFRecord examples, except the image
+fn labels_dict(py: Python,session: &Session,size: usizexy,labels: &Labels) -> PyResult<PyObject> {
+    let instance = &labels.instance;
+    let dict = PyDict::new(py);
+    dict.set_item("session",&session.name)?;
+    dict.set_item("instance",labels.num)?;
+    dict.set_item("frame",labels.frame)?;
+    dict.set_item("seed",instance.seed)?;
+    dict.set_item("head_pos",[instance.head_pos.x,instance.head_pos.y,instance.head_pos.z])?;
+    dict.set_item("head_dir",[instance.head_dir.y,instance.head_dir.p,instance.head_dir.b])?;
+    dict.set_item("lefteye",[instance.lefteye.y,instance.lefteye.p,instance.lefteye.b])?;
+    dict.set_item("righteye",[instance.righteye.y,instance.righteye.p,instance.righteye.b])?;
+    dict.set_item("light_dir",[instance.light_dir.y,instance.light_dir.p,instance.light_dir.b])?;
+    dict.set_item("light_color",[instance.light_color.r,instance.light_color.g,instance.light_color.b])?;
+    if let InstanceBackground::Color(color) = &instance.background {
+        dict.set_item("background_color",[color.r,color.g,color.b])?;
+    }
+    dict.set_item("ambient_color",[instance.ambient_color.r,instance.ambient_color.g,instance.ambient_color.b])?;
+    dict.set_item("skin_color",[instance.skin_color.r,instance.skin_color.g,instance.skin_color.b])?;
+    dict.set_item("sclera_color",[instance.sclera_color.r,instance.sclera_color.g,instance.sclera_color.b])?;
+    dict.set_item("iris_color",[instance.iris_color.r,instance.iris_color.g,instance.iris_color.b])?;
+    if let Some(motion) = &labels.motion {
+        dict.set_item("head_vel",[motion.head_vel.x,motion.head_vel.y,motion.head_vel.z])?;
+        dict.set_item("head_angvel",[motion.head_angvel.y,motion.head_angvel.p,motion.head_angvel.b])?;
+        dict.set_item("lefteye_angvel",[motion.lefteye_angvel.y,motion.lefteye_angvel.p,motion.lefteye_angvel.b])?;
+        dict.set_item("righteye_angvel",[motion.righteye_angvel.y,motion.righteye_angvel.p,motion.righteye_angvel.b])?;
+        dict.set_item("light_angvel",[motion.light_angvel.y,motion.light_angvel.p,motion.light_angvel.b])?;
+    }
+    for (name,eye) in [("lefteye",&labels.lefteye),("righteye",&labels.righteye)].iter() {
+        dict.set_item(format!("{}/center",name),[eye.center.x,eye.center.y,eye.center.z])?;
+        dict.set_item(format!("{}/gaze",name),[eye.gaze.x,eye.gaze.y,eye.gaze.z])?;
+        dict.set_item(format!("{}/pupil",name),[eye.pupil.x,eye.pupil.y])?;
+    }
+    if let Some(depth) = labels.depth_range {
+        dict.set_item("depth_range",[depth.x,depth.y])?;
+    }
+    if let Some(boxes) = &labels.boxes {
+        for (name,b) in [("face",boxes.face),("lefteye",boxes.lefteye),("righteye",boxes.righteye)].iter() {
+            if let Some(r) = b {
+                dict.set_item(format!("{}/bbox",name),[r.o.x,r.o.y,r.s.x,r.s.y])?;
+            }
+        }
+    }
+    for landmark in &labels.landmarks {
+        dict.set_item(format!("landmark/{}",landmark.name),[landmark.screen.x,landmark.screen.y,if landmark.visible { 1.0 } else { 0.0 },landmark.depth])?;
+    }
+    if let Some(depth_map) = &labels.depth_map {
+        dict.set_item("depth_map",PyArray1::from_vec(py,flip_rows(size,depth_map)).reshape([size.y,size.x])?)?;
+    }
+    if let Some(mask) = &labels.mask {
+        dict.set_item("mask",PyArray1::from_vec(py,flip_rows(size,mask)).reshape([size.y,size.x])?)?;
+    }
+    Ok(dict.into())
+}
+
+// renders the instances of one session, as single images or as batches
+#[pyclass(name = "Generator",unsendable)]
+struct PyGenerator {
+    generator: Generator,
+}
+
+#[pymethods]
+impl PyGenerator {
+    #[new]
+    #[pyo3(signature = (config,session = None,seed = None,context = "egl",start = 0,end = None,overrides = Vec::new()))]
+    fn new(config: &str,session: Option<&str>,seed: Option<u64>,context: &str,start: usize,end: Option<usize>,overrides: Vec<String>) -> PyResult<Self> {
+        let backend = match context {
+            "glx" => ContextBackend::GLX,
+            "egl" => ContextBackend::EGL,
+            "software" => ContextBackend::Software,
+            _ => {
+                return Err(PyValueError::new_err(format!("invalid context {} (should be glx, egl or software)",context)));
+            },
+        };
+
+        let pairs = parse_overrides(&overrides).map_err(PyValueError::new_err)?;
+
+        // report all config errors at once
+        let sessions = load_config(config,&pairs).map_err(|errors| {
+            let messages: Vec<String> = errors.iter().map(|error| error.to_string()).collect();
+            PyValueError::new_err(messages.join("\n"))
+        })?;
+
+        // without session name, use the first session
+        let session = match session {
+            Some(name) => sessions.into_iter().find(|session| session.name == name).ok_or_else(|| PyValueError::new_err(format!("{}: no session {}",config,name)))?,
+            None => sessions.into_iter().next().ok_or_else(|| PyValueError::new_err(format!("{}: no sessions",config)))?,
+        };
+        let end = end.unwrap_or(session.count);
+        let mut generator = Generator::new(session,backend).map_err(PyRuntimeError::new_err)?.with_instances(start..end);
+        if let Some(seed) = seed {
+            generator = generator.with_seed(seed);
+        }
+        Ok(PyGenerator {
+            generator: generator,
+        })
+    }
+
+    #[getter]
+    fn seed(&self) -> u64 {
+        self.generator.seed()
+    }
+
+    // (width,height) of the images
+    #[getter]
+    fn size(&self) -> (usize,usize) {
+        let size = self.generator.session().size;
+        (size.x,size.y)
+    }
+
+    // up to count images as one uint8 array of (count,height,width,4) RGBA, and a list of label dicts, None when done
+    fn batch(&mut self,py: Python,count: usize) -> PyResult<Option<(PyObject,Vec<PyObject>)>> {
+        let size = self.generator.session().size;
+        let mut data: Vec<u8> = Vec::with_capacity(count * size.x * size.y * 4);
+        let mut labels: Vec<PyObject> = Vec::with_capacity(count);
+        while labels.len() < count {
+            match self.generator.next() {
+                Some((image,sample_labels)) => {
+                    push_image(&mut data,&image);
+                    labels.push(labels_dict(py,self.generator.session(),size,&sample_labels)?);
+                },
+                None => break,
+            }
+        }
+        if labels.is_empty() {
+            if let Some(error) = self.generator.error() {
+                return Err(PyRuntimeError::new_err(error.clone()));
+            }
+            return Ok(None);
+        }
+        let images = PyArray1::from_vec(py,data).reshape([labels.len(),size.y,size.x,4])?;
+        Ok(Some((images.to_object(py),labels)))
+    }
+
+    fn __iter__(slf: PyRef<'_,Self>) -> PyRef<'_,Self> {
+        slf
+    }
+
+    // a single image as a uint8 array of (height,width,4) RGBA, and its label dict
+    fn __next__(&mut self,py: Python) -> PyResult<Option<(PyObject,PyObject)>> {
+        let size = self.generator.session().size;
+        match self.generator.next() {
+            Some((image,labels)) => {
+                let mut data: Vec<u8> = Vec::with_capacity(size.x * size.y * 4);
+                push_image(&mut data,&image);
+                let image = PyArray1::from_vec(py,data).reshape([size.y,size.x,4])?;
+                Ok(Some((image.to_object(py),labels_dict(py,self.generator.session(),size,&labels)?)))
+            },
+            None => match self.generator.error() {
+                Some(error) => Err(PyRuntimeError::new_err(error.clone())),
+                None => Ok(None),
+            },
+        }
+    }
+}
+
+#[pymodule]
+fn commedia(_py: Python,m: &PyModule) -> PyResult<()> {
+    m.add_class::<PyGenerator>()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rows_top_down() {
+        assert_eq!(flip_rows(usizexy { x: 2,y: 3, },&[1,2,3,4,5,6]),vec![5,6,3,4,1,2]);
+        let mut image = Image::<ARGB8>::new(usizexy { x: 1,y: 2, });
+        image.data = vec![ARGB8::new_rgba(1,2,3,4),ARGB8::new_rgba(5,6,7,8)];
+        let mut data: Vec<u8> = vec![0];
+        push_image(&mut data,&image);
+        assert_eq!(data,vec![0,5,6,7,8,1,2,3,4]);
+    }
+
+    #[test]
+    fn overrides() {
+        let overrides = vec!["s.count=4".to_string(),"*.path=append ./out=1".to_string()];
+        assert_eq!(parse_overrides(&overrides),Ok(vec![("s.count".to_string(),"4".to_string()),("*.path".to_string(),"append ./out=1".to_string())]));
+        assert_eq!(parse_overrides(&["s.count".to_string()]),Err("invalid override s.count (should be key=value)".to_string()));
+    }
+}
@@ -0,0 +1,196 @@
+// (C) Copyright 2020, by Germans Media Technology & Services
+// Commedia
+// Render backend
+
+use crate::math::*;
+use crate::image::*;
+
+use crate::opengl45::VertexNormal;
+
+// triangle list
+pub struct Mesh {
+    pub vertices: Vec<VertexNormal>,
+    pub indices: Vec<u16>,
+}
+
+// Phong material: ambient and diffuse use the color, specular highlights use the light color
+#[derive(Clone,Copy)]
+pub struct Material {
+    pub color: f32rgb,
+    pub specular: f32,
+    pub shininess: f32,
+}
+
+pub enum Shading {
+    Full {
+        material: Material,
+        light_dir: f32xyz,
+        light_color: f32rgb,
+        ambient_color: f32rgb,
+        depth_map: f32xy,  // offset,scale of depth in the alpha channel
+        additive: bool,  // add color to what is already there (the alpha channel and linear depth are replaced)
+    },
+    Spec(f32rgb),  // flat segmentation class color
+}
+
+#[derive(Clone,Copy,Debug)]
+pub enum AntialiasFilter {
+    Box,  // average of the samples inside the pixel
+    Tent,  // triangle over 2 pixels
+    Gaussian,  // sigma of half a pixel, over 2 pixels
+}
+
+// how the render target is resolved to the session size
+#[derive(Clone,Copy,Debug)]
+pub enum Antialias {
+    Supersample(usize,AntialiasFilter),  // render at factor times the session size, and filter down
+    MSAA(usize),  // render at the session size with this many samples per pixel, and average them
+}
+
+impl Antialias {
+
+    // the render target is this many times the session size
+    pub fn scale(&self) -> usize {
+        match self {
+            Antialias::Supersample(factor,_) => *factor,
+            Antialias::MSAA(_) => 1,
+        }
+    }
+}
+
+// separable filter weights of a supersampled pixel, returns the first sample relative to the pixel's own first sample, and the normalized weights
+pub fn filter_weights(factor: usize,filter: AntialiasFilter) -> (isize,Vec<f32>) {
+    let width = match filter {
+        AntialiasFilter::Box => factor,
+        AntialiasFilter::Tent | AntialiasFilter::Gaussian => 2 * factor,
+    };
+    let first = ((factor as isize) - (width as isize)) / 2;
+    let mut weights: Vec<f32> = Vec::with_capacity(width);
+    for i in 0..width {
+
+        // distance from the sample center to the pixel center, in pixels
+        let d = (((first + i as isize) as f32) + 0.5 - 0.5 * (factor as f32)) / (factor as f32);
+        weights.push(match filter {
+            AntialiasFilter::Box => 1.0,
+            AntialiasFilter::Tent => (1.0 - d.abs()).max(0.0),
+            AntialiasFilter::Gaussian => (-2.0 * d * d).exp(),
+        });
+    }
+    let total: f32 = weights.iter().sum();
+    for weight in weights.iter_mut() {
+        *weight /= total;
+    }
+    (first,weights)
+}
+
+// one layer at the session size, as read back after a batch
+pub struct Layer {
+    pub color: Image<ARGB8>,  // bottom row first
+    pub alpha: Vec<u16>,  // the alpha channel at 16 bits, the resolve keeps more precision than 8 bits
+    pub depth_map: Vec<f32>,  // linear eye-space depth, 0 where nothing was drawn, empty if the render target has none
+    pub depth: Vec<f32>,  // window depth, empty unless asked for
+}
+
+pub fn rgba16_to_argb8(rgba: &[u16]) -> ARGB8 {
+    ARGB8::new_rgba(unorm16_to_u8(rgba[0]),unorm16_to_u8(rgba[1]),unorm16_to_u8(rgba[2]),unorm16_to_u8(rgba[3]))
+}
+
+// 16-bit to 8-bit channel, rounded to the nearest value
+pub fn unorm16_to_u8(v: u16) -> u8 {
+    ((v as u32 * 255 + 32767) / 65535) as u8
+}
+
+// split RGBA pixels of 16 bits per channel into the color image and the 16-bit alpha
+pub fn split_rgba16(size: usizexy,data: &[u16]) -> (Image<ARGB8>,Vec<u16>) {
+    let mut color = Image::<ARGB8>::new(size);
+    let mut alpha: Vec<u16> = Vec::with_capacity(size.x * size.y);
+    for (pixel,rgba) in color.data.iter_mut().zip(data.chunks(4)) {
+        *pixel = rgba16_to_argb8(rgba);
+        alpha.push(rgba[3]);
+    }
+    (color,alpha)
+}
+
+pub trait RenderBackend {
+
+    // size of the render target, the session size times the supersampling factor
+    fn size(&self) -> usizexy;
+
+    // number of layers at the session size, for batched rendering
+    fn layers(&self) -> usize;
+
+    // resolve and grab work on this layer from now on
+    fn select_layer(&mut self,layer: usize);
+
+    // upload a mesh, returns the handle to draw it with
+    fn create_mesh(&mut self,mesh: Mesh) -> usize;
+
+    // clear color and depth, and the linear depth map
+    fn clear(&mut self,color: f32rgb);
+
+    // clear depth and the linear depth map, and fill color with an image of the render target size (bottom row first)
+    fn clear_image(&mut self,image: &Image<ARGB8>);
+
+    // draw a mesh with depth test
+    fn draw(&mut self,mesh: usize,projection_matrix: f32m4x4,modelview_matrix: f32m4x4,shading: &Shading);
+
+    // resolve the render target into the selected layer, filtering the colors, or voting on the segmentation class of each pixel for the specification
+    fn resolve(&mut self,spec: bool);
+
+    // wait until everything is drawn
+    fn finish(&mut self);
+
+    // read back color of the selected layer (bottom row first)
+    fn grab(&self) -> Image<ARGB8>;
+
+    // start reading back layers 0..count without waiting for them, with window depth if asked for
+    fn start_readback(&mut self,count: usize,depth: bool);
+
+    // wait for the oldest started read back, and return its layers
+    fn finish_readback(&mut self) -> Vec<Layer>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_normalized_and_symmetric(weights: &[f32]) {
+        let total: f32 = weights.iter().sum();
+        assert!((total - 1.0).abs() < 1e-6);
+        for (a,b) in weights.iter().zip(weights.iter().rev()) {
+            assert!((a - b).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn box_weights() {
+        assert_eq!(filter_weights(1,AntialiasFilter::Box),(0,vec![1.0]));
+        assert_eq!(filter_weights(4,AntialiasFilter::Box),(0,vec![0.25; 4]));
+    }
+
+    #[test]
+    fn tent_weights() {
+
+        // 2 pixels wide, centered on the pixel
+        assert_eq!(filter_weights(2,AntialiasFilter::Tent),(-1,vec![0.125,0.375,0.375,0.125]));
+        let (first,weights) = filter_weights(4,AntialiasFilter::Tent);
+        assert_eq!(first,-2);
+        assert_eq!(weights.len(),8);
+        assert_normalized_and_symmetric(&weights);
+    }
+
+    #[test]
+    fn gaussian_weights() {
+        for factor in [2,4,8].iter() {
+            let (first,weights) = filter_weights(*factor,AntialiasFilter::Gaussian);
+            assert_eq!(first,-(*factor as isize) / 2);
+            assert_eq!(weights.len(),2 * factor);
+            assert_normalized_and_symmetric(&weights);
+
+            // falling off from the center
+            for i in 1..*factor {
+                assert!(weights[i] > weights[i - 1]);
+            }
+        }
+    }
+}
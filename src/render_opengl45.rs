@@ -0,0 +1,458 @@
+// (C) Copyright 2020, by Germans Media Technology & Services
+// Commedia
+// Render backend on OpenGL 4.5
+
+use std::ffi;
+use std::collections::VecDeque;
+use gl::types::GLuint;
+
+use crate::math::*;
+use crate::image::*;
+
+use crate::context3d_xcb_glx_opengl45::*;
+use crate::context3d_egl_opengl45::*;
+use crate::opengl45::*;
+use crate::render::*;
+
+// keeps the platform context alive
+pub enum PlatformContext {
+    GLX(Context3D),
+    EGL(HeadlessContext3D),
+}
+
+// a read back on its way from the GPU
+struct Readback {
+    buffer: PixelBuffer,
+    count: usize,
+    depth: bool,
+}
+
+// resolves the render target into a layer, texelFetch addresses either the samples of a pixel, or the pixels of a supersampled block
+const RESOLVE_SHADER: &str = r#"
+    #ifdef MULTISAMPLE
+    layout(binding = 0) uniform sampler2DMS u_color;
+    layout(binding = 1) uniform sampler2DMS u_depth_map;
+    layout(binding = 2) uniform sampler2DMS u_depth;
+    #define FETCH(tex,i) texelFetch(tex,ivec2(gl_FragCoord.xy),i)
+    #define COUNT u_samples
+    #else
+    layout(binding = 0) uniform sampler2D u_color;
+    layout(binding = 1) uniform sampler2D u_depth_map;
+    layout(binding = 2) uniform sampler2D u_depth;
+    #define FETCH(tex,i) texelFetch(tex,ivec2(gl_FragCoord.xy) * u_samples + ivec2((i) % u_samples,(i) / u_samples),0)
+    #define COUNT (u_samples * u_samples)
+    #endif
+
+    uniform int u_samples;  // samples per pixel, or the supersampling factor
+    uniform int u_first;  // first sample of the color filter, relative to the block of the pixel
+    uniform int u_taps;
+    uniform float u_weights[16];
+    uniform int u_spec;
+    uniform int u_has_depth_map;
+
+    layout(location = 0) out vec4 o_frag;
+    layout(location = 1) out float o_depth;
+
+    void main(void)
+    {
+        if (u_spec != 0) {
+
+            // majority vote over the samples, the class is in red
+            int votes[5] = int[5](0,0,0,0,0);
+            vec4 colors[5] = vec4[5](vec4(0.0),vec4(0.0),vec4(0.0),vec4(0.0),vec4(0.0));
+            for (int i = 0; i < COUNT; i++) {
+                vec4 c = FETCH(u_color,i);
+                int segment = int(c.r * 255.0 + 0.5);
+                if (segment < 5) {
+                    votes[segment]++;
+                    colors[segment] = c;
+                }
+            }
+            int best = 0;
+            for (int segment = 1; segment < 5; segment++) {
+                if (votes[segment] > votes[best]) {
+                    best = segment;
+                }
+            }
+            o_frag = colors[best];
+            o_depth = 0.0;
+            gl_FragDepth = 1.0;
+            return;
+        }
+
+    #ifdef MULTISAMPLE
+        o_frag = vec4(0.0);  // masked, color is resolved with a blit
+    #else
+        ivec2 size = textureSize(u_color,0);
+        ivec2 base = ivec2(gl_FragCoord.xy) * u_samples + ivec2(u_first);
+        vec4 color = vec4(0.0);
+        for (int i = 0; i < u_taps; i++) {
+            for (int k = 0; k < u_taps; k++) {
+                color += u_weights[i] * u_weights[k] * texelFetch(u_color,clamp(base + ivec2(k,i),ivec2(0),size - 1),0);
+            }
+        }
+        o_frag = color;
+    #endif
+
+        // average the linear depth of the samples that hit a surface, to avoid smearing the background into the edges, and keep the nearest window depth
+        float z = 0.0;
+        int hits = 0;
+        float depth = 1.0;
+        for (int i = 0; i < COUNT; i++) {
+            if (u_has_depth_map != 0) {
+                float value = FETCH(u_depth_map,i).r;
+                if (value > 0.0) {
+                    z += value;
+                    hits++;
+                }
+            }
+            depth = min(depth,FETCH(u_depth,i).r);
+        }
+        o_depth = (hits > 0) ? z / float(hits) : 0.0;
+        gl_FragDepth = depth;
+    }
+"#;
+
+pub struct OpenGLBackend {
+    target: TargetFramebuffer,  // drawn into
+    framebuffer: LayeredFramebuffer,  // resolved into
+    background: Texture2D<ARGB8>,
+    samples: i32,  // per pixel, or the supersampling factor
+    first: i32,
+    weights: Vec<f32>,
+    vao: GLuint,  // empty, for the full screen triangle
+    readbacks: VecDeque<Readback>,
+    spare: Vec<PixelBuffer>,  // finished, to use again
+    meshes: Vec<VertexIndexBuffer<VertexNormal,u16>>,
+    full_shader: ShaderProgram,
+    spec_shader: ShaderProgram,
+    copy_shader: ShaderProgram,
+    resolve_shader: ShaderProgram,
+    _ctx: PlatformContext,  // last, so the GL objects are deleted before the context goes
+}
+
+impl OpenGLBackend {
+    pub fn new(ctx: PlatformContext,size: usizexy,antialias: Antialias,layers: usize,float_depth: bool) -> std::result::Result<OpenGLBackend,String> {
+        let (samples,filter) = match antialias {
+            Antialias::Supersample(factor,filter) => (1,filter_weights(factor,filter)),
+            Antialias::MSAA(samples) => (samples,filter_weights(1,AntialiasFilter::Box)),
+        };
+        let target = TargetFramebuffer::new(antialias.scale() * size,samples,float_depth).map_err(|_| "Unable to create framebuffer object.".to_string())?;
+        let framebuffer = LayeredFramebuffer::new(size,layers,float_depth).map_err(|_| "Unable to create framebuffer object.".to_string())?;
+        let background = Texture2D::<ARGB8>::new(target.size);
+        let mut vao: GLuint = 0;
+        unsafe {
+            gl::GenVertexArrays(1,&mut vao);
+        }
+        target.bind();
+        let vs = VertexShader::new(r#"
+            #version 420 core
+
+            uniform mat4 u_projection;
+            uniform mat4 u_modelview;
+            uniform mat3 u_normal;
+
+            layout(location = 0) in vec3 i_pos;
+            layout(location = 1) in vec3 i_normal;
+
+            out vec3 v_pos;
+            out vec3 v_normal;
+
+            void main() {
+                v_pos = (u_modelview * vec4(i_pos,1.0)).xyz;
+                v_normal = u_normal * i_normal;
+                gl_Position = u_projection * vec4(v_pos,1.0);
+            }
+        "#).map_err(|error| format!("Unable to create vertex shader: {}",error))?;
+        let fs_full = FragmentShader::new(r#"
+            #version 420 core
+
+            uniform vec2 u_depth_map;
+            uniform vec4 u_ambient_color;
+            uniform vec4 u_color;
+            uniform vec2 u_specular;
+            uniform vec4 u_light_color;
+            uniform vec3 u_light_dir;
+
+            in vec3 v_pos;
+            in vec3 v_normal;
+
+            layout(location = 0) out vec4 o_frag;
+            layout(location = 1) out float o_depth;
+
+            void main(void)
+            {
+                vec3 n = normalize(v_normal);
+                vec3 pu = -v_pos;
+                vec3 pun = normalize(pu);
+                vec3 rn = reflect(-u_light_dir,n);
+                float s = max(pow(dot(rn,pun),u_specular.y),0.0);
+                float d = clamp(dot(n,u_light_dir),0.0,1.0);
+                vec3 res = u_ambient_color.xyz * u_color.xyz + d * u_light_color.xyz * u_color.xyz + u_specular.x * s * u_light_color.xyz;
+                float z = gl_FragCoord.z / gl_FragCoord.w;
+                float a = u_depth_map.y * z + u_depth_map.x;
+                o_frag = vec4(res,a);
+                o_depth = -v_pos.z;
+            }
+        "#).map_err(|error| format!("Unable to create fragment shader: {}",error))?;
+        let fs_spec = FragmentShader::new(r#"
+            #version 420 core
+
+            uniform vec4 u_class_color;
+
+            in vec3 v_pos;
+            in vec3 v_normal;
+
+            out vec4 o_frag;
+
+            void main(void)
+            {
+                o_frag = u_class_color;
+            }
+        "#).map_err(|error| format!("Unable to create fragment shader: {}",error))?;
+        let vs_screen = VertexShader::new(r#"
+            #version 420 core
+
+            void main() {
+                gl_Position = vec4(float((gl_VertexID & 1) << 2) - 1.0,float((gl_VertexID & 2) << 1) - 1.0,0.0,1.0);
+            }
+        "#).map_err(|error| format!("Unable to create vertex shader: {}",error))?;
+        let fs_copy = FragmentShader::new(r#"
+            #version 420 core
+
+            layout(binding = 0) uniform sampler2D u_image;
+
+            layout(location = 0) out vec4 o_frag;
+
+            void main(void)
+            {
+                o_frag = texelFetch(u_image,ivec2(gl_FragCoord.xy),0);
+            }
+        "#).map_err(|error| format!("Unable to create fragment shader: {}",error))?;
+        let fs_resolve = FragmentShader::new(&format!("#version 420 core\n{}{}",if target.is_multisampled() { "#define MULTISAMPLE\n" } else { "" },RESOLVE_SHADER)).map_err(|error| format!("Unable to create fragment shader: {}",error))?;
+        let (first,weights) = filter;
+        Ok(OpenGLBackend {
+            samples: if target.is_multisampled() { samples as i32 } else { antialias.scale() as i32 },
+            first: first as i32,
+            weights: weights,
+            target: target,
+            framebuffer: framebuffer,
+            background: background,
+            vao: vao,
+            readbacks: VecDeque::new(),
+            spare: Vec::new(),
+            meshes: Vec::new(),
+            full_shader: ShaderProgram::new(&vs,None,&fs_full).map_err(|error| format!("Unable to create shader program: {}",error))?,
+            spec_shader: ShaderProgram::new(&vs,None,&fs_spec).map_err(|error| format!("Unable to create shader program: {}",error))?,
+            copy_shader: ShaderProgram::new(&vs_screen,None,&fs_copy).map_err(|error| format!("Unable to create shader program: {}",error))?,
+            resolve_shader: ShaderProgram::new(&vs_screen,None,&fs_resolve).map_err(|error| format!("Unable to create shader program: {}",error))?,
+            _ctx: ctx,
+        })
+    }
+}
+
+impl Drop for OpenGLBackend {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteVertexArrays(1,&self.vao);
+        }
+    }
+}
+
+impl RenderBackend for OpenGLBackend {
+    fn size(&self) -> usizexy {
+        self.target.size
+    }
+
+    fn layers(&self) -> usize {
+        self.framebuffer.layers
+    }
+
+    fn select_layer(&mut self,layer: usize) {
+        self.framebuffer.select(layer);
+    }
+
+    fn create_mesh(&mut self,mesh: Mesh) -> usize {
+        self.meshes.push(VertexIndexBuffer::from(mesh.vertices,mesh.indices));
+        self.meshes.len() - 1
+    }
+
+    fn clear(&mut self,color: f32rgb) {
+        self.target.bind();
+        unsafe {
+            gl::ClearColor(color.r,color.g,color.b,1.0);
+            gl::ClearDepth(1.0);
+            gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+        }
+        self.target.clear_depth_map();
+    }
+
+    fn clear_image(&mut self,image: &Image<ARGB8>) {
+
+        // a multisampled target can't be uploaded to or blitted into, so draw the image over it
+        self.background.set(image);
+        self.target.bind();
+        unsafe {
+            gl::ClearDepth(1.0);
+            gl::Clear(gl::DEPTH_BUFFER_BIT);
+            gl::ActiveTexture(gl::TEXTURE0);
+            self.background.bind();
+            self.copy_shader.bind();
+            gl::BindVertexArray(self.vao);
+            gl::DrawArrays(gl::TRIANGLES,0,3);
+        }
+        self.target.clear_depth_map();
+    }
+
+    fn draw(&mut self,mesh: usize,projection_matrix: f32m4x4,modelview_matrix: f32m4x4,shading: &Shading) {
+        let normal_matrix = f32m3x3::normal_from(modelview_matrix);
+        let mesh = &self.meshes[mesh];
+        self.target.bind();
+        unsafe {
+            gl::Enable(gl::DEPTH_TEST);
+            match shading {
+                Shading::Full { material,light_dir,light_color,ambient_color,depth_map,additive, } => {
+                    self.full_shader.bind();
+                    self.full_shader.set_uniform("u_projection",projection_matrix);
+                    self.full_shader.set_uniform("u_modelview",modelview_matrix);
+                    self.full_shader.set_uniform("u_normal",normal_matrix);
+                    self.full_shader.set_uniform("u_ambient_color",*ambient_color);
+                    self.full_shader.set_uniform("u_color",material.color);
+                    self.full_shader.set_uniform("u_specular",f32xy { x: material.specular,y: material.shininess, });
+                    self.full_shader.set_uniform("u_light_color",*light_color);
+                    self.full_shader.set_uniform("u_light_dir",*light_dir);
+                    self.full_shader.set_uniform("u_depth_map",*depth_map);
+                    if *additive {
+                        gl::Enable(gl::BLEND);
+                        gl::Disablei(gl::BLEND,1);  // replace the linear depth instead of adding to it
+                        gl::BlendFuncSeparate(gl::ONE,gl::ONE,gl::ONE,gl::ZERO);
+                    }
+                },
+                Shading::Spec(class_color) => {
+                    self.spec_shader.bind();
+                    self.spec_shader.set_uniform("u_projection",projection_matrix);
+                    self.spec_shader.set_uniform("u_modelview",modelview_matrix);
+                    self.spec_shader.set_uniform("u_normal",normal_matrix);
+                    self.spec_shader.set_uniform("u_class_color",*class_color);
+                },
+            }
+            mesh.bind();
+            gl::DrawElements(gl::TRIANGLES,mesh.indices() as i32,gl::UNSIGNED_SHORT as u32,0 as *const ffi::c_void);
+            gl::Disable(gl::BLEND);
+            gl::Disable(gl::DEPTH_TEST);
+        }
+    }
+
+    fn resolve(&mut self,spec: bool) {
+
+        // multisampled color is averaged by the blit, the shader does the rest
+        let blit = self.target.is_multisampled() && !spec;
+        if blit {
+            self.framebuffer.blit_color(self.target.fbo());
+        }
+        self.framebuffer.bind();
+        self.target.bind_textures();
+        unsafe {
+            gl::Enable(gl::DEPTH_TEST);
+            gl::DepthFunc(gl::ALWAYS);
+            if blit {
+                gl::ColorMaski(0,gl::FALSE,gl::FALSE,gl::FALSE,gl::FALSE);
+            }
+            self.resolve_shader.bind();
+            self.resolve_shader.set_uniform("u_samples",self.samples);
+            self.resolve_shader.set_uniform("u_first",self.first);
+            self.resolve_shader.set_uniform("u_taps",self.weights.len() as i32);
+            self.resolve_shader.set_uniform("u_weights",&self.weights[..]);
+            self.resolve_shader.set_uniform("u_spec",spec as i32);
+            self.resolve_shader.set_uniform("u_has_depth_map",self.target.has_depth_map() as i32);
+            gl::BindVertexArray(self.vao);
+            gl::DrawArrays(gl::TRIANGLES,0,3);
+            gl::ColorMaski(0,gl::TRUE,gl::TRUE,gl::TRUE,gl::TRUE);
+            gl::DepthFunc(gl::LESS);
+            gl::Disable(gl::DEPTH_TEST);
+        }
+        self.target.unbind_textures();
+    }
+
+    fn finish(&mut self) {
+        unsafe {
+            gl::Finish();
+            gl::Flush();
+        }
+        self.framebuffer.unbind();
+    }
+
+    fn grab(&self) -> Image<ARGB8> {
+        self.framebuffer.grab()
+    }
+
+    fn start_readback(&mut self,count: usize,depth: bool) {
+
+        // color, then linear depth, then window depth, each for all layers
+        let pixels = self.framebuffer.size.x * self.framebuffer.size.y;
+        let planes = 1 + if self.framebuffer.has_depth_map() { 1 } else { 0 } + if depth { 1 } else { 0 };
+        let size = (planes + 1) * count * pixels * 4;  // color has 8 bytes per pixel
+        let mut buffer = match self.spare.iter().position(|buffer| buffer.size >= size) {
+            Some(index) => self.spare.swap_remove(index),
+            None => PixelBuffer::new(size),
+        };
+
+        // queue the reads, the GPU copies into the buffer while the next batch is drawn
+        buffer.bind();
+        let mut offset = self.framebuffer.read_color(count,0);
+        if self.framebuffer.has_depth_map() {
+            offset += self.framebuffer.read_depth_map(count,offset);
+        }
+        if depth {
+            self.framebuffer.read_depth(count,offset);
+        }
+        buffer.fence();
+        buffer.unbind();
+        self.readbacks.push_back(Readback {
+            buffer: buffer,
+            count: count,
+            depth: depth,
+        });
+    }
+
+    fn finish_readback(&mut self) -> Vec<Layer> {
+        let mut readback = self.readbacks.pop_front().expect("no read back started");
+        readback.buffer.wait();
+        let size = self.framebuffer.size;
+        let pixels = size.x * size.y;
+        let count = readback.count;
+        let want_depth = readback.depth;
+        let has_depth_map = self.framebuffer.has_depth_map();
+        let depth_map_offset = count * pixels * 8;
+        let depth_offset = if has_depth_map { depth_map_offset + count * pixels * 4 } else { depth_map_offset };
+
+        // one mapping for all layers and planes
+        let layers = readback.buffer.map(|bytes| {
+            let mut data = vec![0u16; pixels * 4];
+            let mut layers: Vec<Layer> = Vec::with_capacity(count);
+            for i in 0..count {
+                copy_mapped(bytes,i * pixels * 8,&mut data);
+                let (color,alpha) = split_rgba16(size,&data);
+                let mut depth_map: Vec<f32> = Vec::new();
+                if has_depth_map {
+                    depth_map = vec![0.0f32; pixels];
+                    copy_mapped(bytes,depth_map_offset + i * pixels * 4,&mut depth_map);
+                }
+                let mut depth: Vec<f32> = Vec::new();
+                if want_depth {
+                    depth = vec![0.0f32; pixels];
+                    copy_mapped(bytes,depth_offset + i * pixels * 4,&mut depth);
+                }
+                layers.push(Layer {
+                    color: color,
+                    alpha: alpha,
+                    depth_map: depth_map,
+                    depth: depth,
+                });
+            }
+            layers
+        });
+        self.spare.push(readback.buffer);
+        layers
+    }
+}
@@ -0,0 +1,733 @@
+// (C) Copyright 2020, by Germans Media Technology & Services
+// Commedia
+// Render backend on the CPU, following the OpenGL 4.5 backend
+
+use std::collections::VecDeque;
+
+use crate::math::*;
+use crate::image::*;
+
+use crate::render::*;
+
+pub struct SoftwareBackend {
+    size: usizexy,  // of the render target
+    antialias: Antialias,
+    offsets: Vec<f32xy>,  // sample positions inside a pixel, only the center unless multisampled
+    color: Vec<[u16; 4]>,  // RGBA per sample, at 16 bits like the render target of the OpenGL backend
+    depth: Vec<f32>,  // window depth, quantized like the 16-bit depth buffer of the framebuffer
+    depth_map: Vec<f32>,  // linear eye-space depth
+    layer: usize,
+    layers: Vec<Layer>,  // resolved, at the session size
+    readbacks: VecDeque<Vec<Layer>>,
+    meshes: Vec<Mesh>,
+}
+
+// the standard sample positions of 2, 4 and 8 samples, in 1/16 pixel from the pixel center
+const SAMPLES_2: [(i32,i32); 2] = [(4,4),(-4,-4)];
+const SAMPLES_4: [(i32,i32); 4] = [(-2,-6),(6,-2),(-6,2),(2,6)];
+const SAMPLES_8: [(i32,i32); 8] = [(1,-3),(-1,3),(5,1),(-3,-5),(-5,5),(-7,-1),(3,7),(7,-7)];
+
+// transformed vertex
+#[derive(Clone,Copy)]
+struct ClipVertex {
+    pos: f32xyz,  // eye space
+    normal: f32xyz,  // eye space, not normalized
+    clip: f32xyzw,
+}
+
+fn dot(a: f32xyz,b: f32xyz) -> f32 {
+    a.x * b.x + a.y * b.y + a.z * b.z
+}
+
+fn mix(a: ClipVertex,b: ClipVertex,t: f32) -> ClipVertex {
+    ClipVertex {
+        pos: a.pos + (b.pos - a.pos) * t,
+        normal: a.normal + (b.normal - a.normal) * t,
+        clip: f32xyzw {
+            x: a.clip.x + (b.clip.x - a.clip.x) * t,
+            y: a.clip.y + (b.clip.y - a.clip.y) * t,
+            z: a.clip.z + (b.clip.z - a.clip.z) * t,
+            w: a.clip.w + (b.clip.w - a.clip.w) * t,
+        },
+    }
+}
+
+// clip a triangle against the near plane (z >= -w), leaving a polygon of up to 4 vertices
+fn clip_near(triangle: [ClipVertex; 3]) -> Vec<ClipVertex> {
+    let mut polygon: Vec<ClipVertex> = Vec::with_capacity(4);
+    for i in 0..3 {
+        let a = triangle[i];
+        let b = triangle[(i + 1) % 3];
+        let da = a.clip.z + a.clip.w;
+        let db = b.clip.z + b.clip.w;
+        if da >= 0.0 {
+            polygon.push(a);
+        }
+        if (da >= 0.0) != (db >= 0.0) {
+            polygon.push(mix(a,b,da / (da - db)));
+        }
+    }
+    polygon
+}
+
+// twice the signed area of triangle a,b,p, positive when counterclockwise
+fn edge(a: f32xy,b: f32xy,p: f32xy) -> f32 {
+    (b.x - a.x) * (p.y - a.y) - (b.y - a.y) * (p.x - a.x)
+}
+
+// pixels exactly on an edge belong to the triangle only for top and left edges, so shared edges are drawn once
+fn top_left(a: f32xy,b: f32xy) -> bool {
+    ((a.y == b.y) && (b.x < a.x)) || (b.y < a.y)
+}
+
+// sample positions inside a pixel of the render target, only the center unless multisampled
+pub fn sample_offsets(antialias: Antialias) -> Vec<f32xy> {
+    let positions: &[(i32,i32)] = match antialias {
+        Antialias::MSAA(2) => &SAMPLES_2,
+        Antialias::MSAA(4) => &SAMPLES_4,
+        Antialias::MSAA(8) => &SAMPLES_8,
+        _ => &[(0,0)],
+    };
+    positions.iter().map(|(x,y)| f32xy { x: 0.5 + (*x as f32) / 16.0,y: 0.5 + (*y as f32) / 16.0, }).collect()
+}
+
+// true if the triangle covers at least one sample, with the same fill rules and depth range as rasterize
+fn triangle_covers_sample(v: [ClipVertex; 3],size: usizexy,offsets: &[f32xy]) -> bool {
+    let mut screen = [f32xy { x: 0.0,y: 0.0, }; 3];
+    let mut z = [0.0f32; 3];
+    for (i,vertex) in v.iter().enumerate() {
+        screen[i] = f32xy {
+            x: 0.5 * (vertex.clip.x / vertex.clip.w + 1.0) * (size.x as f32),
+            y: 0.5 * (vertex.clip.y / vertex.clip.w + 1.0) * (size.y as f32),
+        };
+        z[i] = 0.5 * (vertex.clip.z / vertex.clip.w + 1.0);
+    }
+    let mut area = edge(screen[0],screen[1],screen[2]);
+    let (screen,z) = if area < 0.0 {
+        area = -area;
+        ([screen[0],screen[2],screen[1]],[z[0],z[2],z[1]])
+    }
+    else {
+        (screen,z)
+    };
+    if area == 0.0 {
+        return false;
+    }
+    let min_x = screen[0].x.min(screen[1].x).min(screen[2].x).floor().max(0.0) as usize;
+    let min_y = screen[0].y.min(screen[1].y).min(screen[2].y).floor().max(0.0) as usize;
+    let max_x = (screen[0].x.max(screen[1].x).max(screen[2].x).ceil().max(0.0) as usize).min(size.x);
+    let max_y = (screen[0].y.max(screen[1].y).max(screen[2].y).ceil().max(0.0) as usize).min(size.y);
+    for y in min_y..max_y {
+        for x in min_x..max_x {
+            for offset in offsets {
+                let p = f32xy { x: (x as f32) + offset.x,y: (y as f32) + offset.y, };
+                let w0 = edge(screen[1],screen[2],p);
+                let w1 = edge(screen[2],screen[0],p);
+                let w2 = edge(screen[0],screen[1],p);
+                if (w0 < 0.0) || (w1 < 0.0) || (w2 < 0.0) {
+                    continue;
+                }
+                if ((w0 == 0.0) && !top_left(screen[1],screen[2])) || ((w1 == 0.0) && !top_left(screen[2],screen[0])) || ((w2 == 0.0) && !top_left(screen[0],screen[1])) {
+                    continue;
+                }
+
+                // the far plane clips per sample, like the rasterizer
+                let fz = (w0 / area) * z[0] + (w1 / area) * z[1] + (w2 / area) * z[2];
+                if (fz >= 0.0) && (fz <= 1.0) {
+                    return true;
+                }
+            }
+        }
+    }
+    false
+}
+
+// true if the triangles cover at least one sample of a render target of this size, on the CPU, clipped and filled like the rasterizer, without depth test
+pub fn covers_sample(positions: &[f32xyz],indices: &[u16],matrix: f32m4x4,size: usizexy,offsets: &[f32xy]) -> bool {
+    let vertices: Vec<ClipVertex> = positions.iter().map(|pos| ClipVertex {
+        pos: *pos,
+        normal: *pos,
+        clip: matrix * f32xyzw { x: pos.x,y: pos.y,z: pos.z,w: 1.0, },
+    }).collect();
+    for triangle in indices.chunks(3) {
+        let polygon = clip_near([vertices[triangle[0] as usize],vertices[triangle[1] as usize],vertices[triangle[2] as usize]]);
+        for i in 1..polygon.len().max(2) - 1 {
+            if triangle_covers_sample([polygon[0],polygon[i],polygon[i + 1]],size,offsets) {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+// max(pow(cos,shininess),0.0) like the shader, GPUs compute pow as exp2(y * log2(x)), which is NaN for negative x, and max returns the other operand of a NaN
+fn specular(cos: f32,shininess: f32) -> f32 {
+    (shininess * cos.log2()).exp2().max(0.0)
+}
+
+fn quantize_depth(z: f32) -> f32 {
+    (z * 65535.0).round() / 65535.0
+}
+
+fn to_unorm16(value: f32) -> u16 {
+    (value.max(0.0).min(1.0) * 65535.0).round() as u16
+}
+
+impl SoftwareBackend {
+    pub fn new(size: usizexy,antialias: Antialias,layers: usize) -> SoftwareBackend {
+        let offsets = sample_offsets(antialias);
+        let target = antialias.scale() * size;
+        let samples = target.x * target.y * offsets.len();
+        let mut resolved: Vec<Layer> = Vec::with_capacity(layers);
+        for _ in 0..layers {
+            resolved.push(Layer {
+                color: Image::<ARGB8>::new(size),
+                alpha: vec![0u16; size.x * size.y],
+                depth_map: vec![0.0f32; size.x * size.y],
+                depth: vec![1.0f32; size.x * size.y],
+            });
+        }
+        SoftwareBackend {
+            size: target,
+            antialias: antialias,
+            offsets: offsets,
+            color: vec![[0,0,0,65535]; samples],
+            depth: vec![1.0f32; samples],
+            depth_map: vec![0.0f32; samples],
+            layer: 0,
+            layers: resolved,
+            readbacks: VecDeque::new(),
+            meshes: Vec::new(),
+        }
+    }
+
+    // the samples that make up a resolved pixel
+    fn pixel_samples(&self,x: usize,y: usize) -> Vec<usize> {
+        let n = self.offsets.len();
+        let factor = self.antialias.scale();
+        let mut samples: Vec<usize> = Vec::with_capacity(factor * factor * n);
+        for i in 0..factor {
+            for k in 0..factor {
+                for s in 0..n {
+                    samples.push(((y * factor + i) * self.size.x + x * factor + k) * n + s);
+                }
+            }
+        }
+        samples
+    }
+
+    // the samples and weights that filter the color of a resolved pixel
+    fn color_taps(&self,x: usize,y: usize) -> Vec<(usize,f32)> {
+        match self.antialias {
+            Antialias::Supersample(factor,filter) => {
+                let (first,weights) = filter_weights(factor,filter);
+                let mut taps: Vec<(usize,f32)> = Vec::with_capacity(weights.len() * weights.len());
+                for i in 0..weights.len() {
+                    for k in 0..weights.len() {
+                        let sx = ((x * factor) as isize + first + k as isize).max(0).min(self.size.x as isize - 1) as usize;
+                        let sy = ((y * factor) as isize + first + i as isize).max(0).min(self.size.y as isize - 1) as usize;
+                        taps.push((sy * self.size.x + sx,weights[i] * weights[k]));
+                    }
+                }
+                taps
+            },
+            Antialias::MSAA(_) => {
+                let samples = self.pixel_samples(x,y);
+                let weight = 1.0 / (samples.len() as f32);
+                samples.into_iter().map(|sample| (sample,weight)).collect()
+            },
+        }
+    }
+
+    fn rasterize(&mut self,v: [ClipVertex; 3],shading: &Shading) {
+
+        // to window coordinates
+        let mut screen = [f32xy { x: 0.0,y: 0.0, }; 3];
+        let mut z = [0.0f32; 3];
+        let mut inv_w = [0.0f32; 3];
+        for i in 0..3 {
+            inv_w[i] = 1.0 / v[i].clip.w;
+            screen[i] = f32xy {
+                x: 0.5 * (v[i].clip.x * inv_w[i] + 1.0) * (self.size.x as f32),
+                y: 0.5 * (v[i].clip.y * inv_w[i] + 1.0) * (self.size.y as f32),
+            };
+            z[i] = 0.5 * (v[i].clip.z * inv_w[i] + 1.0);
+        }
+
+        // make counterclockwise, nothing is culled
+        let mut area = edge(screen[0],screen[1],screen[2]);
+        let (v,screen,z,inv_w) = if area < 0.0 {
+            area = -area;
+            ([v[0],v[2],v[1]],[screen[0],screen[2],screen[1]],[z[0],z[2],z[1]],[inv_w[0],inv_w[2],inv_w[1]])
+        }
+        else {
+            (v,screen,z,inv_w)
+        };
+        if area == 0.0 {
+            return;
+        }
+
+        // pixel bounds
+        let min_x = screen[0].x.min(screen[1].x).min(screen[2].x).floor().max(0.0) as usize;
+        let min_y = screen[0].y.min(screen[1].y).min(screen[2].y).floor().max(0.0) as usize;
+        let max_x = (screen[0].x.max(screen[1].x).max(screen[2].x).ceil().max(0.0) as usize).min(self.size.x);
+        let max_y = (screen[0].y.max(screen[1].y).max(screen[2].y).ceil().max(0.0) as usize).min(self.size.y);
+
+        let count = self.offsets.len();
+        for y in min_y..max_y {
+            for x in min_x..max_x {
+                for sample in 0..count {
+
+                    // coverage at the sample position, each sample is shaded on its own
+                    let p = f32xy { x: (x as f32) + self.offsets[sample].x,y: (y as f32) + self.offsets[sample].y, };
+                    let w0 = edge(screen[1],screen[2],p);
+                    let w1 = edge(screen[2],screen[0],p);
+                    let w2 = edge(screen[0],screen[1],p);
+                    if (w0 < 0.0) || (w1 < 0.0) || (w2 < 0.0) {
+                        continue;
+                    }
+                    if ((w0 == 0.0) && !top_left(screen[1],screen[2])) || ((w1 == 0.0) && !top_left(screen[2],screen[0])) || ((w2 == 0.0) && !top_left(screen[0],screen[1])) {
+                        continue;
+                    }
+                    let b0 = w0 / area;
+                    let b1 = w1 / area;
+                    let b2 = w2 / area;
+
+                    // window depth is linear in screen space
+                    let fz = b0 * z[0] + b1 * z[1] + b2 * z[2];
+                    if (fz < 0.0) || (fz > 1.0) {
+                        continue;
+                    }
+                    let index = (y * self.size.x + x) * count + sample;
+                    let qz = quantize_depth(fz);
+                    if qz >= self.depth[index] {
+                        continue;
+                    }
+
+                    // eye-space attributes are perspective-correct
+                    let p0 = b0 * inv_w[0];
+                    let p1 = b1 * inv_w[1];
+                    let p2 = b2 * inv_w[2];
+                    let sum = p0 + p1 + p2;
+                    let clip_w = 1.0 / sum;
+                    let pos = (v[0].pos * p0 + v[1].pos * p1 + v[2].pos * p2) / sum;
+                    let normal = (v[0].normal * p0 + v[1].normal * p1 + v[2].normal * p2) / sum;
+
+                    let pixel = &mut self.color[index];
+                    match shading {
+                        Shading::Full { material,light_dir,light_color,ambient_color,depth_map,additive, } => {
+
+                            // same as the fragment shader of the OpenGL backend
+                            let n = normal.norm();
+                            let pun = (f32xyz { x: 0.0,y: 0.0,z: 0.0, } - pos).norm();
+                            let i = f32xyz { x: 0.0,y: 0.0,z: 0.0, } - *light_dir;
+                            let rn = i - n * (2.0 * dot(n,i));
+                            let s = specular(dot(rn,pun),material.shininess);
+                            let d = dot(n,*light_dir).max(0.0).min(1.0);
+                            let r = ambient_color.r * material.color.r + d * light_color.r * material.color.r + material.specular * s * light_color.r;
+                            let g = ambient_color.g * material.color.g + d * light_color.g * material.color.g + material.specular * s * light_color.g;
+                            let b = ambient_color.b * material.color.b + d * light_color.b * material.color.b + material.specular * s * light_color.b;
+                            let a = depth_map.y * fz * clip_w + depth_map.x;
+                            if *additive {
+                                pixel[0] = to_unorm16(r.max(0.0).min(1.0) + (pixel[0] as f32) / 65535.0);
+                                pixel[1] = to_unorm16(g.max(0.0).min(1.0) + (pixel[1] as f32) / 65535.0);
+                                pixel[2] = to_unorm16(b.max(0.0).min(1.0) + (pixel[2] as f32) / 65535.0);
+                            }
+                            else {
+                                pixel[0] = to_unorm16(r);
+                                pixel[1] = to_unorm16(g);
+                                pixel[2] = to_unorm16(b);
+                            }
+                            pixel[3] = to_unorm16(a);
+                            self.depth_map[index] = -pos.z;
+                        },
+                        Shading::Spec(class_color) => {
+                            *pixel = [to_unorm16(class_color.r),to_unorm16(class_color.g),to_unorm16(class_color.b),65535];
+                        },
+                    }
+                    self.depth[index] = qz;
+                }
+            }
+        }
+    }
+}
+
+impl RenderBackend for SoftwareBackend {
+    fn size(&self) -> usizexy {
+        self.size
+    }
+
+    fn layers(&self) -> usize {
+        self.layers.len()
+    }
+
+    fn select_layer(&mut self,layer: usize) {
+        self.layer = layer;
+    }
+
+    fn create_mesh(&mut self,mesh: Mesh) -> usize {
+        self.meshes.push(mesh);
+        self.meshes.len() - 1
+    }
+
+    fn clear(&mut self,color: f32rgb) {
+        let pixel = [to_unorm16(color.r),to_unorm16(color.g),to_unorm16(color.b),65535];
+        for p in self.color.iter_mut() {
+            *p = pixel;
+        }
+        for z in self.depth.iter_mut() {
+            *z = 1.0;
+        }
+        for z in self.depth_map.iter_mut() {
+            *z = 0.0;
+        }
+    }
+
+    fn clear_image(&mut self,image: &Image<ARGB8>) {
+        let count = self.offsets.len();
+        for (i,p) in self.color.iter_mut().enumerate() {
+            let pixel = image.data[i / count];
+            *p = [(pixel.r as u16) * 257,(pixel.g as u16) * 257,(pixel.b as u16) * 257,(pixel.a as u16) * 257];
+        }
+        for z in self.depth.iter_mut() {
+            *z = 1.0;
+        }
+        for z in self.depth_map.iter_mut() {
+            *z = 0.0;
+        }
+    }
+
+    fn draw(&mut self,mesh: usize,projection_matrix: f32m4x4,modelview_matrix: f32m4x4,shading: &Shading) {
+        let normal_matrix = f32m3x3::normal_from(modelview_matrix);
+
+        // vertex stage
+        let vertices: Vec<ClipVertex> = self.meshes[mesh].vertices.iter().map(|vertex| {
+            let pos = modelview_matrix * f32xyzw { x: vertex.v.x,y: vertex.v.y,z: vertex.v.z,w: 1.0, };
+            let pos = f32xyz { x: pos.x,y: pos.y,z: pos.z, };
+            ClipVertex {
+                pos: pos,
+                normal: normal_matrix * vertex.n,
+                clip: projection_matrix * f32xyzw { x: pos.x,y: pos.y,z: pos.z,w: 1.0, },
+            }
+        }).collect();
+
+        // primitive stage, in submission order like the GPU
+        let triangles = self.meshes[mesh].indices.len() / 3;
+        for t in 0..triangles {
+            let indices = &self.meshes[mesh].indices[t * 3..t * 3 + 3];
+            let polygon = clip_near([vertices[indices[0] as usize],vertices[indices[1] as usize],vertices[indices[2] as usize]]);
+            for i in 1..polygon.len().max(2) - 1 {
+                self.rasterize([polygon[0],polygon[i],polygon[i + 1]],shading);
+            }
+        }
+    }
+
+    // same as the resolve shader of the OpenGL backend
+    fn resolve(&mut self,spec: bool) {
+        let mut layer = std::mem::replace(&mut self.layers[self.layer],Layer {
+            color: Image::<ARGB8>::new(usizexy { x: 0,y: 0, }),
+            alpha: Vec::new(),
+            depth_map: Vec::new(),
+            depth: Vec::new(),
+        });
+        let size = layer.color.size;
+        for y in 0..size.y {
+            for x in 0..size.x {
+                let index = y * size.x + x;
+                let samples = self.pixel_samples(x,y);
+                if spec {
+
+                    // majority vote over the samples, the class is in red
+                    let mut votes = [0usize; 5];
+                    let mut colors = [[0u16; 4]; 5];
+                    for sample in &samples {
+                        let class = (((self.color[*sample][0] as u32) * 255 + 32767) / 65535) as usize;
+                        if class < votes.len() {
+                            votes[class] += 1;
+                            colors[class] = self.color[*sample];
+                        }
+                    }
+                    let mut best = 0;
+                    for class in 1..votes.len() {
+                        if votes[class] > votes[best] {
+                            best = class;
+                        }
+                    }
+                    layer.color.data[index] = rgba16_to_argb8(&colors[best]);
+                    layer.alpha[index] = colors[best][3];
+                    layer.depth_map[index] = 0.0;
+                    layer.depth[index] = 1.0;
+                    continue;
+                }
+
+                // filter color at 16 bits
+                let mut rgba = [0.0f32; 4];
+                for (sample,weight) in self.color_taps(x,y) {
+                    let pixel = self.color[sample];
+                    for (sum,value) in rgba.iter_mut().zip(pixel.iter()) {
+                        *sum += weight * (*value as f32) / 65535.0;
+                    }
+                }
+                let rgba = [to_unorm16(rgba[0]),to_unorm16(rgba[1]),to_unorm16(rgba[2]),to_unorm16(rgba[3])];
+                layer.color.data[index] = rgba16_to_argb8(&rgba);
+                layer.alpha[index] = rgba[3];
+
+                // average the linear depth of the samples that hit a surface, and keep the nearest window depth
+                let mut z = 0.0f32;
+                let mut hits = 0;
+                let mut depth = 1.0f32;
+                for sample in &samples {
+                    if self.depth_map[*sample] > 0.0 {
+                        z += self.depth_map[*sample];
+                        hits += 1;
+                    }
+                    depth = depth.min(self.depth[*sample]);
+                }
+                layer.depth_map[index] = if hits > 0 { z / (hits as f32) } else { 0.0 };
+                layer.depth[index] = depth;
+            }
+        }
+        self.layers[self.layer] = layer;
+    }
+
+    fn finish(&mut self) {
+    }
+
+    fn grab(&self) -> Image<ARGB8> {
+        let color = &self.layers[self.layer].color;
+        let mut image = Image::<ARGB8>::new(color.size);
+        image.data.copy_from_slice(&color.data);
+        image
+    }
+
+    // everything is drawn already, so read back right away
+    fn start_readback(&mut self,count: usize,depth: bool) {
+        let mut layers: Vec<Layer> = Vec::with_capacity(count);
+        for layer in &self.layers[..count] {
+            let mut image = Image::<ARGB8>::new(layer.color.size);
+            image.data.copy_from_slice(&layer.color.data);
+            layers.push(Layer {
+                color: image,
+                alpha: layer.alpha.clone(),
+                depth_map: layer.depth_map.clone(),
+                depth: if depth { layer.depth.clone() } else { Vec::new() },
+            });
+        }
+        self.readbacks.push_back(layers);
+    }
+
+    fn finish_readback(&mut self) -> Vec<Layer> {
+        self.readbacks.pop_front().expect("no read back started")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Skin,Eye,SegmentClass,SPEC_LEFT_EYE,SPEC_RIGHT_EYE,VertexNormal};
+
+    fn identity() -> f32m4x4 {
+        f32m4x4::translate(f32xyz::new(0.0,0.0,0.0))
+    }
+
+    // the lower left half of a 4x4 target at eye-space depth 0.5, the long edge runs between pixel centers
+    fn triangle(backend: &mut SoftwareBackend) -> usize {
+        let n = f32xyz::new(0.0,0.0,1.0);
+        backend.create_mesh(Mesh {
+            vertices: vec![
+                VertexNormal { v: f32xyz::new(-1.0,-1.0,-0.5),n: n, },
+                VertexNormal { v: f32xyz::new(1.25,-1.0,-0.5),n: n, },
+                VertexNormal { v: f32xyz::new(-1.0,1.25,-0.5),n: n, },
+            ],
+            indices: vec![0,1,2],
+        })
+    }
+
+    fn read_layer(backend: &mut SoftwareBackend) -> Layer {
+        backend.start_readback(1,true);
+        backend.finish_readback().remove(0)
+    }
+
+    #[test]
+    fn triangle_full() {
+        let mut backend = SoftwareBackend::new(usizexy { x: 4,y: 4, },Antialias::Supersample(1,AntialiasFilter::Box),1);
+        let mesh = triangle(&mut backend);
+        backend.clear(f32rgb { r: 0.0,g: 0.0,b: 0.0, });
+        backend.draw(mesh,identity(),identity(),&Shading::Full {
+            material: Material { color: f32rgb { r: 1.0,g: 1.0,b: 1.0, },specular: 0.0,shininess: 1.0, },
+            light_dir: f32xyz::new(0.0,0.0,1.0),
+            light_color: f32rgb { r: 0.0,g: 0.0,b: 0.0, },
+            ambient_color: f32rgb { r: 0.2,g: 0.4,b: 0.6, },
+            depth_map: f32xy { x: 0.25,y: 0.0, },
+            additive: false,
+        });
+        backend.resolve(false);
+        let layer = read_layer(&mut backend);
+        for y in 0..4 {
+            for x in 0..4 {
+                let index = y * 4 + x;
+                let pixel = layer.color.data[index];
+                if x + y <= 3 {
+                    assert_eq!((pixel.r,pixel.g,pixel.b,pixel.a),(51,102,153,64),"pixel {},{}",x,y);
+                    assert_eq!(layer.alpha[index],16384);
+                    assert!((layer.depth_map[index] - 0.5).abs() < 1e-6);
+                    assert_eq!(layer.depth[index],16384.0 / 65535.0);  // window depth 0.25 in 16 bits
+                }
+                else {
+                    assert_eq!((pixel.r,pixel.g,pixel.b,pixel.a),(0,0,0,255),"pixel {},{}",x,y);
+                    assert_eq!(layer.alpha[index],65535);
+                    assert_eq!(layer.depth_map[index],0.0);
+                    assert_eq!(layer.depth[index],1.0);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn specular_like_gpu() {
+        assert_eq!(specular(1.0,16.0),1.0);
+        assert!((specular(0.5,2.0) - 0.25).abs() < 1e-6);
+        assert_eq!(specular(0.0,64.0),0.0);
+
+        // an even power of a negative cosine would light the far side of the reflection
+        assert_eq!(specular(-0.5,16.0),0.0);
+    }
+
+    #[test]
+    fn triangle_spec() {
+        let mut backend = SoftwareBackend::new(usizexy { x: 4,y: 4, },Antialias::Supersample(1,AntialiasFilter::Box),1);
+        let mesh = triangle(&mut backend);
+        backend.clear(SegmentClass::Background.color(0));
+        backend.draw(mesh,identity(),identity(),&Shading::Spec(SegmentClass::Iris.color(SPEC_RIGHT_EYE)));
+        backend.resolve(true);
+        let image = backend.grab();
+        for y in 0..4 {
+            for x in 0..4 {
+                let pixel = image.data[y * 4 + x];
+                let expected = if x + y <= 3 { (SegmentClass::Iris as u8,SPEC_RIGHT_EYE) } else { (SegmentClass::Background as u8,0) };
+                assert_eq!((pixel.r,pixel.g,pixel.b,pixel.a),(expected.0,expected.1,0,255),"pixel {},{}",x,y);
+            }
+        }
+    }
+
+    #[test]
+    fn face_classes() {
+        let size = usizexy { x: 128,y: 96, };
+        let mut backend = SoftwareBackend::new(size,Antialias::Supersample(2,AntialiasFilter::Box),1);
+        let skin = Skin::new(&mut backend).expect("the built-in face mesh should load");
+        let eye = Eye::new(&mut backend);
+        let projection = f32m4x4::perspective(30.0,4.0 / 3.0,0.1,100.0);
+        let head = f32m4x4::translate(f32xyz::new(0.0,0.0,-0.5));
+        let lefteye = f32m4x4::translate(crate::LEFT_EYE_POS) * f32m4x4::scale(crate::EYE_SIZE);
+        let righteye = f32m4x4::translate(crate::RIGHT_EYE_POS) * f32m4x4::scale(crate::EYE_SIZE);
+        backend.clear(SegmentClass::Background.color(0));
+        eye.render_spec(&mut backend,projection,head * lefteye,SPEC_LEFT_EYE);
+        eye.render_spec(&mut backend,projection,head * righteye,SPEC_RIGHT_EYE);
+        skin.render_spec(&mut backend,projection,head);
+        backend.resolve(true);
+        let image = backend.grab();
+
+        // the pixel a point of the head projects to, bottom row first
+        let pixel = |pos: f32xyz| {
+            let clip = projection * head * f32xyzw { x: pos.x,y: pos.y,z: pos.z,w: 1.0, };
+            let x = (0.5 * (clip.x / clip.w + 1.0) * (size.x as f32)) as usize;
+            let y = (0.5 * (clip.y / clip.w + 1.0) * (size.y as f32)) as usize;
+            image.data[y * size.x + x]
+        };
+
+        // the eyes look straight ahead, so the iris or pupil covers the eye centers
+        for (pos,side) in [(crate::LEFT_EYE_POS,SPEC_LEFT_EYE),(crate::RIGHT_EYE_POS,SPEC_RIGHT_EYE)].iter() {
+            let center = pixel(*pos);
+            assert!((center.r == SegmentClass::Iris as u8) || (center.r == SegmentClass::Pupil as u8),"eye class {}",center.r);
+            assert_eq!(center.g,*side);
+        }
+        let forehead = pixel(f32xyz::new(0.0,0.05,0.05));
+        assert_eq!((forehead.r,forehead.g),(SegmentClass::Skin as u8,0));
+        for corner in [0,size.x - 1,(size.y - 1) * size.x,size.y * size.x - 1].iter() {
+            assert_eq!(image.data[*corner].r,SegmentClass::Background as u8);
+        }
+
+        // every pixel has one of the classes, and only eye classes name an eye
+        for pix in image.data.iter() {
+            assert!(pix.r <= SegmentClass::Pupil as u8);
+            if pix.r >= SegmentClass::Sclera as u8 {
+                assert!((pix.g == SPEC_LEFT_EYE) || (pix.g == SPEC_RIGHT_EYE));
+            }
+            else {
+                assert_eq!(pix.g,0);
+            }
+        }
+    }
+
+    // a small triangle inside pixel 1,1 of a 4x4 target, around the 4x MSAA sample at 1.375,1.125 but not the pixel center
+    #[test]
+    fn coverage_samples() {
+        let size = usizexy { x: 4,y: 4, };
+        let positions = [f32xyz::new(-0.35,-0.475,-0.5),f32xyz::new(-0.25,-0.475,-0.5),f32xyz::new(-0.35,-0.375,-0.5)];
+        assert!(!covers_sample(&positions,&[0,1,2],identity(),size,&sample_offsets(Antialias::Supersample(1,AntialiasFilter::Box))));
+        assert!(!covers_sample(&positions,&[0,1,2],identity(),2 * size,&sample_offsets(Antialias::Supersample(2,AntialiasFilter::Box))));
+        let msaa = Antialias::MSAA(4);
+        assert!(covers_sample(&positions,&[0,1,2],identity(),size,&sample_offsets(msaa)));
+
+        // and the rasterizer agrees
+        let mut backend = SoftwareBackend::new(size,msaa,1);
+        let n = f32xyz::new(0.0,0.0,1.0);
+        let mesh = backend.create_mesh(Mesh {
+            vertices: positions.iter().map(|v| VertexNormal { v: *v,n: n, }).collect(),
+            indices: vec![0,1,2],
+        });
+        backend.clear(f32rgb { r: 0.0,g: 0.0,b: 0.0, });
+        backend.draw(mesh,identity(),identity(),&Shading::Full {
+            material: Material { color: f32rgb { r: 1.0,g: 1.0,b: 1.0, },specular: 0.0,shininess: 1.0, },
+            light_dir: n,
+            light_color: f32rgb { r: 0.0,g: 0.0,b: 0.0, },
+            ambient_color: f32rgb { r: 1.0,g: 1.0,b: 1.0, },
+            depth_map: f32xy { x: 0.25,y: 0.0, },
+            additive: false,
+        });
+        backend.resolve(false);
+        let layer = read_layer(&mut backend);
+        for (index,alpha) in layer.alpha.iter().enumerate() {
+            assert_eq!(*alpha < 65535,index == 5,"pixel {}",index);
+        }
+    }
+
+    #[test]
+    fn coverage_clipping() {
+        let size = usizexy { x: 4,y: 4, };
+        let offsets = sample_offsets(Antialias::Supersample(1,AntialiasFilter::Box));
+
+        // entirely in front of the near plane or behind the far plane
+        for z in [-1.5,1.5].iter() {
+            let positions = [f32xyz::new(-1.0,-1.0,*z),f32xyz::new(3.0,-1.0,*z),f32xyz::new(-1.0,3.0,*z)];
+            assert!(!covers_sample(&positions,&[0,1,2],identity(),size,&offsets));
+        }
+
+        // crossing the far plane or the near plane, the part inside is still drawn
+        for z in [2.0,-2.0].iter() {
+            let positions = [f32xyz::new(-1.0,-1.0,0.0),f32xyz::new(3.0,-1.0,0.0),f32xyz::new(-1.0,3.0,*z)];
+            assert!(covers_sample(&positions,&[0,1,2],identity(),size,&offsets));
+        }
+    }
+
+    #[test]
+    fn face_coverage() {
+        let size = usizexy { x: 128,y: 96, };
+        let mut backend = SoftwareBackend::new(size,Antialias::Supersample(2,AntialiasFilter::Box),1);
+        let skin = Skin::new(&mut backend).expect("the built-in face mesh should load");
+        let eye = Eye::new(&mut backend);
+        let projection = f32m4x4::perspective(30.0,4.0 / 3.0,0.1,100.0);
+        let offsets = sample_offsets(Antialias::Supersample(2,AntialiasFilter::Box));
+        let lefteye = f32m4x4::translate(crate::LEFT_EYE_POS) * f32m4x4::scale(crate::EYE_SIZE);
+        let head = f32m4x4::translate(f32xyz::new(0.0,0.0,-0.5));
+        assert!(skin.covers_sample(projection,head,2 * size,&offsets));
+        assert!(eye.covers_sample(projection,head * lefteye,2 * size,&offsets));
+
+        // behind the camera, and beyond the far plane
+        for z in [0.5,-200.0].iter() {
+            let head = f32m4x4::translate(f32xyz::new(0.0,0.0,*z));
+            assert!(!skin.covers_sample(projection,head,2 * size,&offsets));
+            assert!(!eye.covers_sample(projection,head * lefteye,2 * size,&offsets));
+        }
+    }
+}
@@ -0,0 +1,206 @@
+// (C) Copyright 2020, by Germans Media Technology & Services
+// Commedia
+// TensorFlow TFRecord writer
+
+use std::fs;
+use std::io::prelude::*;
+
+const CRC32C_POLY: u32 = 0x82F63B78;
+const CRC32C_MASK_DELTA: u32 = 0xA282EAD8;
+
+fn crc32c(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFFu32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if (crc & 1) != 0 { (crc >> 1) ^ CRC32C_POLY } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn masked_crc32c(data: &[u8]) -> u32 {
+    let crc = crc32c(data);
+    ((crc >> 15) | (crc << 17)).wrapping_add(CRC32C_MASK_DELTA)
+}
+
+fn push_varint(buffer: &mut Vec<u8>,mut value: u64) {
+    while value >= 0x80 {
+        buffer.push(((value & 0x7F) | 0x80) as u8);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+fn push_delimited(buffer: &mut Vec<u8>,tag: u8,data: &[u8]) {
+    buffer.push(tag);
+    push_varint(buffer,data.len() as u64);
+    buffer.extend_from_slice(data);
+}
+
+pub enum Feature {
+    Bytes(Vec<u8>),
+    Float(Vec<f32>),
+    Int64(Vec<i64>),
+}
+
+impl Feature {
+    fn encode(&self) -> Vec<u8> {
+        let mut list: Vec<u8> = Vec::new();
+        let mut feature: Vec<u8> = Vec::new();
+        match self {
+            Feature::Bytes(value) => {
+                push_delimited(&mut list,0x0A,value);  // BytesList.value
+                push_delimited(&mut feature,0x0A,&list);  // Feature.bytes_list
+            },
+            Feature::Float(values) => {
+                let mut packed: Vec<u8> = Vec::new();
+                for value in values {
+                    packed.extend_from_slice(&value.to_le_bytes());
+                }
+                push_delimited(&mut list,0x0A,&packed);  // FloatList.value
+                push_delimited(&mut feature,0x12,&list);  // Feature.float_list
+            },
+            Feature::Int64(values) => {
+                let mut packed: Vec<u8> = Vec::new();
+                for value in values {
+                    push_varint(&mut packed,*value as u64);
+                }
+                push_delimited(&mut list,0x0A,&packed);  // Int64List.value
+                push_delimited(&mut feature,0x1A,&list);  // Feature.int64_list
+            },
+        }
+        feature
+    }
+}
+
+// tf.train.Example
+pub struct Example {
+    features: Vec<(String,Feature)>,
+}
+
+impl Default for Example {
+    fn default() -> Example {
+        Example::new()
+    }
+}
+
+impl Example {
+    pub fn new() -> Example {
+        Example {
+            features: Vec::new(),
+        }
+    }
+
+    pub fn add_bytes(&mut self,key: &str,value: &[u8]) {
+        self.features.push((key.to_string(),Feature::Bytes(value.to_vec())));
+    }
+
+    pub fn add_floats(&mut self,key: &str,values: &[f32]) {
+        self.features.push((key.to_string(),Feature::Float(values.to_vec())));
+    }
+
+    pub fn add_int64s(&mut self,key: &str,values: &[i64]) {
+        self.features.push((key.to_string(),Feature::Int64(values.to_vec())));
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut features: Vec<u8> = Vec::new();
+        for (key,feature) in &self.features {
+            let mut entry: Vec<u8> = Vec::new();
+            push_delimited(&mut entry,0x0A,key.as_bytes());  // map key
+            push_delimited(&mut entry,0x12,&feature.encode());  // map value
+            push_delimited(&mut features,0x0A,&entry);  // Features.feature
+        }
+        let mut example: Vec<u8> = Vec::new();
+        push_delimited(&mut example,0x0A,&features);  // Example.features
+        example
+    }
+}
+
+pub struct TFRecordWriter {
+    files: Vec<fs::File>,
+}
+
+impl TFRecordWriter {
+    // append to existing shards, or start them over
+    pub fn new(prefix: &str,shards: usize,append: bool) -> std::io::Result<TFRecordWriter> {
+        let mut files: Vec<fs::File> = Vec::new();
+        for i in 0..shards {
+            let name = format!("{}-{:05}-of-{:05}.tfrecord",prefix,i,shards);
+            if append {
+                files.push(fs::OpenOptions::new().create(true).append(true).open(name)?);
+            }
+            else {
+                files.push(fs::File::create(name)?);
+            }
+        }
+        Ok(TFRecordWriter {
+            files: files,
+        })
+    }
+
+    pub fn write(&mut self,shard: usize,data: &[u8]) -> std::io::Result<()> {
+        let length = (data.len() as u64).to_le_bytes();
+        let file = &mut self.files[shard % self.files.len()];
+        file.write_all(&length)?;
+        file.write_all(&masked_crc32c(&length).to_le_bytes())?;
+        file.write_all(data)?;
+        file.write_all(&masked_crc32c(data).to_le_bytes())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc32c_check_value() {
+        assert_eq!(crc32c(b"123456789"),0xE3069283);
+        assert_eq!(crc32c(b""),0);
+        assert_eq!(masked_crc32c(b"123456789"),0xC78AB0E5);
+    }
+
+    #[test]
+    fn example_encoding() {
+        let mut example = Example::new();
+        example.add_int64s("n",&[1,300]);
+        example.add_floats("f",&[1.0]);
+        example.add_bytes("b",b"ab");
+        assert_eq!(example.encode(),vec![
+            0x0A,0x2A,  // Example.features
+            0x0A,0x0C,0x0A,0x01,b'n',0x12,0x07,0x1A,0x05,0x0A,0x03,0x01,0xAC,0x02,  // int64_list, 300 takes 2 varint bytes
+            0x0A,0x0D,0x0A,0x01,b'f',0x12,0x08,0x12,0x06,0x0A,0x04,0x00,0x00,0x80,0x3F,  // float_list, little endian
+            0x0A,0x0B,0x0A,0x01,b'b',0x12,0x06,0x0A,0x04,0x0A,0x02,b'a',b'b',  // bytes_list
+        ]);
+    }
+
+    #[test]
+    fn record_framing() {
+
+        // each record is its length, the masked crc of the length, the data and the masked crc of the data
+        let prefix = std::env::temp_dir().join("commedia_record_framing");
+        let prefix = prefix.to_str().unwrap();
+        let mut writer = TFRecordWriter::new(prefix,2,false).unwrap();
+        writer.write(1,b"123456789").unwrap();
+        drop(writer);
+        let name = format!("{}-00001-of-00002.tfrecord",prefix);
+        let record = fs::read(&name).unwrap();
+        assert_eq!(fs::read(format!("{}-00000-of-00002.tfrecord",prefix)).unwrap().len(),0);
+        assert_eq!(record.len(),8 + 4 + 9 + 4);
+        assert_eq!(&record[0..8],&9u64.to_le_bytes());
+        assert_eq!(&record[8..12],&masked_crc32c(&9u64.to_le_bytes()).to_le_bytes());
+        assert_eq!(&record[12..21],b"123456789");
+        assert_eq!(&record[21..25],&0xC78AB0E5u32.to_le_bytes());
+
+        // appending keeps what is there
+        let mut writer = TFRecordWriter::new(prefix,2,true).unwrap();
+        writer.write(3,b"123456789").unwrap();
+        drop(writer);
+        assert_eq!(fs::read(&name).unwrap().len(),2 * record.len());
+        for i in 0..2 {
+            fs::remove_file(format!("{}-{:05}-of-00002.tfrecord",prefix,i)).unwrap();
+        }
+    }
+}